@@ -1,17 +1,101 @@
 use crate::*;
 
+// number of tokens returned by the enumeration views when no limit is passed in
+pub const DEFAULT_PAGE_LIMIT: u64 = 50;
+// the most tokens any enumeration view will return in a single call
+pub const MAX_PAGE_LIMIT: u64 = 100;
+// the most tokens nft_tokens_filtered looks at in a single call, whether they match the filter or not
+pub const MAX_FILTER_SCAN_LEN: usize = 500;
+
+// a page of nft_tokens_filtered. Pass `next_token_id` as `from_token_id` to carry on, None means there is nothing left
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct JsonTokensPage {
+	pub tokens: Vec<JsonToken>,
+	pub next_token_id: Option<TokenId>,
+}
+
+// clamp the requested page size between 1 and MAX_PAGE_LIMIT, using DEFAULT_PAGE_LIMIT if none was passed in
+pub(crate) fn page_limit(limit: Option<u64>) -> usize {
+	limit.unwrap_or(DEFAULT_PAGE_LIMIT).min(MAX_PAGE_LIMIT) as usize
+}
+
+// iterate the token IDs of an ordered index, starting right after `from_token_id` if one was passed in
+fn token_ids_after<'a, V>(
+	index: &'a TreeMap<TokenId, V>,
+	from_token_id: Option<TokenId>,
+) -> Box<dyn Iterator<Item = TokenId> + 'a>
+where
+	V: BorshSerialize + BorshDeserialize,
+{
+	match from_token_id {
+		Some(from_token_id) => Box::new(index.iter_from(from_token_id).map(|(k, _)| k)),
+		None => Box::new(index.iter().map(|(k, _)| k)),
+	}
+}
+
+/*
+	a token matches an extra tag if its `extra` field is a JSON object with a `tags` array containing the tag,
+	or if the whole `extra` field is equal to the tag.
+*/
+fn extra_has_tag(extra: &Option<String>, tag: &str) -> bool {
+	let extra = if let Some(extra) = extra {
+		extra
+	} else {
+		return false;
+	};
+
+	if let Ok(value) = near_sdk::serde_json::from_str::<near_sdk::serde_json::Value>(extra) {
+		if let Some(tags) = value.get("tags").and_then(|tags| tags.as_array()) {
+			return tags.iter().any(|t| t.as_str() == Some(tag));
+		}
+	}
+
+	extra == tag
+}
+
 #[near_bindgen]
 impl Contract {
-	pub fn nft_tokens(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<JsonToken> {
-		let keys = self.token_metadata_by_id.keys_as_vector();
+	// get the total number of tokens minted on the contract
+	pub fn nft_total_supply(&self) -> U128 {
+		U128(self.token_metadata_by_id.len() as u128)
+	}
 
+	// Query for nft tokens on the contract regardless of the owner. Tokens are returned sorted by token ID.
+	pub fn nft_tokens(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<JsonToken> {
 		//where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
 		let start = u128::from(from_index.unwrap_or(U128(0)));
 
-		keys.iter()
+		self.token_metadata_by_id
+			.iter()
 			.skip(start as usize)
-			.take(limit.unwrap_or(0) as usize)
-			.map(|token_id| self.nft_token(token_id.clone()).unwrap())
+			.take(page_limit(limit))
+			.map(|(token_id, _)| self.nft_token(token_id).unwrap())
+			.collect()
+	}
+
+	/*
+		Cursor based version of nft_tokens. Returns the tokens sorted by token ID that come after `from_token_id`.
+		Unlike an index, the cursor doesn't shift when tokens before it are removed.
+	*/
+	pub fn nft_tokens_from(&self, from_token_id: Option<TokenId>, limit: Option<u64>) -> Vec<JsonToken> {
+		token_ids_after(&self.token_metadata_by_id, from_token_id)
+			.take(page_limit(limit))
+			.map(|token_id| self.nft_token(token_id).unwrap())
+			.collect()
+	}
+
+	// Batch lookup of tokens. The result has one entry per requested token ID, None if the token doesn't exist.
+	pub fn nft_tokens_by_ids(&self, token_ids: Vec<TokenId>) -> Vec<Option<JsonToken>> {
+		assert!(
+			token_ids.len() as u64 <= MAX_PAGE_LIMIT,
+			"Cannot query more than {} tokens at once",
+			MAX_PAGE_LIMIT
+		);
+
+		token_ids
+			.into_iter()
+			.map(|token_id| self.nft_token(token_id))
 			.collect()
 	}
 
@@ -42,21 +126,221 @@ impl Contract {
 			return vec![];
 		};
 
-		// We'll convert the UnorderedSet into a vector of strings
-		let keys = tokens.as_vector();
-
 		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
 		let start = u128::from(from_index.unwrap_or(U128(0)));
 
-		// iterate through the keys vector
-		keys.iter()
+		// iterate through the token IDs of the owner, sorted by token ID
+		tokens
+			.iter()
 			// skip to the index we specified in the start variable
 			.skip(start as usize)
-			// take the first "limit" elements in the vector. If we didn't specify a limit, use 0
-			.take(limit.unwrap_or(0) as usize)
+			// take the first "limit" elements. If we didn't specify a limit, use the default page size
+			.take(page_limit(limit))
 			// we'll map the token IDs which are strings into Json Tokens
-			.map(|token_id| self.nft_token(token_id.clone()).unwrap())
+			.map(|(token_id, _)| self.nft_token(token_id).unwrap())
 			// since we turned the keys into an iterator, we need to turn it back into a vector to return
 			.collect()
 	}
+
+	// Cursor based version of nft_tokens_for_owner. Returns the owner's tokens that come after `from_token_id`.
+	pub fn nft_tokens_for_owner_from(
+		&self,
+		account_id: AccountId,
+		from_token_id: Option<TokenId>,
+		limit: Option<u64>,
+	) -> Vec<JsonToken> {
+		let tokens = if let Some(tokens) = self.tokens_per_owner.get(&account_id) {
+			tokens
+		} else {
+			return vec![];
+		};
+
+		token_ids_after(&tokens, from_token_id)
+			.take(page_limit(limit))
+			.map(|token_id| self.nft_token(token_id).unwrap())
+			.collect()
+	}
+
+	// get the number of tokens minted for a given token type (series)
+	pub fn nft_supply_for_type(&self, token_type: TokenType) -> U128 {
+		let tokens_for_type = self.tokens_per_type.get(&token_type);
+
+		if let Some(tokens_for_type) = tokens_for_type {
+			U128(tokens_for_type.len() as u128)
+		} else {
+			U128(0)
+		}
+	}
+
+	/*
+		Cursor based query for tokens filtered by token type (series) and/or a tag in the metadata `extra` field.
+		When a token type is passed in we only walk the tokens of that type, otherwise all tokens sorted by ID.
+		At most MAX_FILTER_SCAN_LEN tokens are looked at per call, so a page can come back short (or empty) with a
+		`next_token_id` to carry on from.
+	*/
+	pub fn nft_tokens_filtered(
+		&self,
+		token_type: Option<TokenType>,
+		extra_tag: Option<String>,
+		from_token_id: Option<TokenId>,
+		limit: Option<u64>,
+	) -> JsonTokensPage {
+		let tokens_for_type = if let Some(token_type) = token_type {
+			if let Some(tokens_for_type) = self.tokens_per_type.get(&token_type) {
+				Some(tokens_for_type)
+			} else {
+				return JsonTokensPage {
+					tokens: vec![],
+					next_token_id: None,
+				};
+			}
+		} else {
+			None
+		};
+
+		let token_ids = if let Some(tokens_for_type) = tokens_for_type.as_ref() {
+			token_ids_after(tokens_for_type, from_token_id)
+		} else {
+			token_ids_after(&self.token_metadata_by_id, from_token_id)
+		};
+
+		let limit = page_limit(limit);
+		let mut tokens = vec![];
+		let mut last_token_id = None;
+
+		for (scanned, token_id) in token_ids.enumerate() {
+			// stop once the page is full or we looked at enough tokens, the caller carries on after the last one
+			if tokens.len() == limit || scanned == MAX_FILTER_SCAN_LEN {
+				return JsonTokensPage {
+					tokens,
+					next_token_id: last_token_id,
+				};
+			}
+			last_token_id = Some(token_id.clone());
+
+			let token = self.nft_token(token_id).unwrap();
+			let is_match = extra_tag
+				.as_ref()
+				.map(|tag| extra_has_tag(&token.metadata.extra, tag))
+				.unwrap_or(true);
+			if is_match {
+				tokens.push(token);
+			}
+		}
+
+		JsonTokensPage {
+			tokens,
+			next_token_id: None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use near_sdk::test_utils::{accounts, VMContextBuilder};
+	use near_sdk::testing_env;
+
+	const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+
+	fn set_context(predecessor_id: AccountId, attached_deposit: Balance) {
+		testing_env!(VMContextBuilder::new()
+			.current_account_id(accounts(0))
+			.predecessor_account_id(predecessor_id)
+			.attached_deposit(attached_deposit)
+			.build());
+	}
+
+	fn metadata(extra: Option<&str>) -> TokenMetadata {
+		TokenMetadata {
+			title: None,
+			description: None,
+			media: None,
+			media_hash: None,
+			copies: None,
+			issued_at: None,
+			expires_at: None,
+			starts_at: None,
+			updated_at: None,
+			extra: extra.map(|extra| extra.to_string()),
+			reference: None,
+			reference_hash: None,
+		}
+	}
+
+	// mint the tokens to bob, in the order given
+	fn setup(tokens: &[(&str, Option<&str>, Option<&str>)]) -> Contract {
+		set_context(accounts(0), 0);
+		let mut contract = Contract::new_default_meta(accounts(0));
+
+		for (token_id, token_type, extra) in tokens {
+			set_context(accounts(1), ONE_NEAR);
+			contract.nft_mint(
+				token_id.to_string(),
+				metadata(*extra),
+				accounts(1),
+				None,
+				token_type.map(|token_type| token_type.to_string()),
+				None,
+			);
+		}
+
+		contract
+	}
+
+	fn token_ids(tokens: &[JsonToken]) -> Vec<&str> {
+		tokens.iter().map(|token| token.token_id.as_str()).collect()
+	}
+
+	#[test]
+	fn tokens_are_sorted_and_paged_after_the_cursor() {
+		let contract = setup(&[("c", None, None), ("a", None, None), ("d", None, None), ("b", None, None)]);
+
+		assert_eq!(contract.nft_total_supply(), U128(4));
+		assert_eq!(token_ids(&contract.nft_tokens_from(None, Some(2))), vec!["a", "b"]);
+		assert_eq!(
+			token_ids(&contract.nft_tokens_from(Some("b".to_string()), Some(2))),
+			vec!["c", "d"]
+		);
+		assert_eq!(
+			token_ids(&contract.nft_tokens_for_owner_from(accounts(1), Some("a".to_string()), None)),
+			vec!["b", "c", "d"]
+		);
+		assert!(contract.nft_tokens_from(Some("d".to_string()), None).is_empty());
+	}
+
+	#[test]
+	fn filters_by_token_type_and_extra_tag() {
+		let contract = setup(&[
+			("1", Some("gold"), Some(r#"{"tags":["rare","shiny"]}"#)),
+			("2", Some("gold"), Some("common")),
+			("3", Some("silver"), Some(r#"{"tags":["rare"]}"#)),
+			("4", Some("gold"), Some(r#"{"tags":["rare"]}"#)),
+			("5", None, Some("rare")),
+		]);
+
+		let page = contract.nft_tokens_filtered(Some("gold".to_string()), Some("rare".to_string()), None, None);
+		assert_eq!(token_ids(&page.tokens), vec!["1", "4"]);
+		assert_eq!(page.next_token_id, None);
+
+		let page = contract.nft_tokens_filtered(None, Some("rare".to_string()), None, None);
+		assert_eq!(token_ids(&page.tokens), vec!["1", "3", "4", "5"]);
+
+		assert_eq!(contract.nft_supply_for_type("gold".to_string()), U128(3));
+		let page = contract.nft_tokens_filtered(Some("bronze".to_string()), None, None, None);
+		assert!(page.tokens.is_empty());
+	}
+
+	#[test]
+	fn a_full_filtered_page_returns_a_cursor_to_carry_on() {
+		let contract = setup(&[("1", Some("gold"), None), ("2", Some("gold"), None), ("3", Some("gold"), None)]);
+
+		let page = contract.nft_tokens_filtered(Some("gold".to_string()), None, None, Some(2));
+		assert_eq!(token_ids(&page.tokens), vec!["1", "2"]);
+		assert_eq!(page.next_token_id, Some("2".to_string()));
+
+		let page = contract.nft_tokens_filtered(Some("gold".to_string()), None, page.next_token_id, Some(2));
+		assert_eq!(token_ids(&page.tokens), vec!["3"]);
+		assert_eq!(page.next_token_id, None);
+	}
 }
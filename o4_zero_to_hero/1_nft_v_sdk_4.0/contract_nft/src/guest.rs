@@ -0,0 +1,265 @@
+use crate::*;
+
+// the only method a guest access key is allowed to call
+pub const GUEST_MINT_METHOD: &str = "nft_guest_mint";
+
+// default amount of NEAR a guest key can spend on gas if the owner doesn't specify one (0.25 NEAR)
+pub const DEFAULT_GUEST_KEY_ALLOWANCE: Balance = 250_000_000_000_000_000_000_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct GuestKey {
+	// how many more tokens this key is allowed to mint
+	pub remaining_mints: u32,
+	// how many tokens this key has minted so far
+	pub minted: u32,
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		Register a function call access key on this contract that can only call nft_guest_mint. The key is deleted
+		once it has minted `quota` tokens. Only the contract owner can register keys.
+	*/
+	pub fn add_guest_key(&mut self, public_key: PublicKey, quota: u32, allowance: Option<U128>) {
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the contract owner can add guest keys"
+		);
+		assert!(quota > 0, "Quota must be greater than 0");
+
+		let initial_storage_usage = env::storage_usage();
+		assert!(
+			self.guest_keys
+				.insert(
+					&public_key,
+					&GuestKey {
+						remaining_mints: quota,
+						minted: 0,
+					},
+				)
+				.is_none(),
+			"Guest key already exists"
+		);
+		// the storage for the key entry is sponsored as well
+		self.internal_charge_sponsor_pool(env::storage_usage() - initial_storage_usage);
+
+		Promise::new(env::current_account_id()).add_access_key(
+			public_key,
+			allowance.map(|a| a.0).unwrap_or(DEFAULT_GUEST_KEY_ALLOWANCE),
+			env::current_account_id(),
+			GUEST_MINT_METHOD.to_string(),
+		);
+	}
+
+	// Remove a guest key before its quota is used up.
+	pub fn remove_guest_key(&mut self, public_key: PublicKey) {
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the contract owner can remove guest keys"
+		);
+
+		self.internal_remove_guest_key(&public_key);
+	}
+
+	// Add funds to the pool that pays for the storage of guest mints. Anyone can sponsor.
+	#[payable]
+	pub fn sponsor_pool_deposit(&mut self) {
+		let deposit = env::attached_deposit();
+		assert!(deposit > 0, "Requires attached deposit greater than 0");
+
+		self.sponsor_pool += deposit;
+	}
+
+	// Withdraw unused funds from the sponsor pool to the contract owner.
+	#[payable]
+	pub fn sponsor_pool_withdraw(&mut self, amount: U128) {
+		assert_one_yocto();
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the contract owner can withdraw from the sponsor pool"
+		);
+		assert!(
+			amount.0 <= self.sponsor_pool,
+			"Amount exceeds the sponsor pool balance of {}",
+			self.sponsor_pool
+		);
+
+		self.sponsor_pool -= amount.0;
+		Promise::new(self.owner_id.clone()).transfer(amount.0);
+	}
+
+	/*
+		Mint a token with a guest access key. The signer key must have been registered with add_guest_key and still
+		have mints left in its quota. The storage is paid from the sponsor pool.
+	*/
+	pub fn nft_guest_mint(&mut self, token_id: TokenId, metadata: TokenMetadata, receiver_id: AccountId) {
+		// guest keys live on this contract's account, so the call must come from the contract itself
+		assert_eq!(
+			env::predecessor_account_id(),
+			env::current_account_id(),
+			"nft_guest_mint must be called with a guest access key"
+		);
+
+		let public_key = env::signer_account_pk();
+		let mut guest_key = self.guest_keys.get(&public_key).expect("Not a guest key");
+		guest_key.remaining_mints -= 1;
+		guest_key.minted += 1;
+
+		let initial_storage_usage = env::storage_usage();
+
+		self.internal_mint(token_id, metadata, receiver_id, None, None, None);
+
+		self.internal_charge_sponsor_pool(env::storage_usage() - initial_storage_usage);
+
+		// once the quota is used up, the key is removed from the contract and the account
+		if guest_key.remaining_mints == 0 {
+			self.internal_remove_guest_key(&public_key);
+		} else {
+			self.guest_keys.insert(&public_key, &guest_key);
+		}
+	}
+
+	pub fn get_guest_key(&self, public_key: PublicKey) -> Option<GuestKey> {
+		self.guest_keys.get(&public_key)
+	}
+
+	pub fn get_sponsor_pool(&self) -> U128 {
+		U128(self.sponsor_pool)
+	}
+}
+
+impl Contract {
+	// pay for the given storage out of the sponsor pool. Panic if the pool can't cover it.
+	pub(crate) fn internal_charge_sponsor_pool(&mut self, storage_used: u64) {
+		let required_cost = env::storage_byte_cost() * Balance::from(storage_used);
+
+		assert!(
+			required_cost <= self.sponsor_pool,
+			"Sponsor pool can't cover {} yoctoNEAR of storage",
+			required_cost
+		);
+
+		self.sponsor_pool -= required_cost;
+	}
+
+	// remove the guest key entry, credit its storage back to the pool and delete the access key
+	pub(crate) fn internal_remove_guest_key(&mut self, public_key: &PublicKey) {
+		let initial_storage_usage = env::storage_usage();
+		self.guest_keys.remove(public_key).expect("Not a guest key");
+		self.sponsor_pool += env::storage_byte_cost()
+			* Balance::from(initial_storage_usage - env::storage_usage());
+
+		Promise::new(env::current_account_id()).delete_key(public_key.clone());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use near_sdk::test_utils::{accounts, VMContextBuilder};
+	use near_sdk::testing_env;
+
+	const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+
+	fn guest_public_key() -> PublicKey {
+		"ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp".parse().unwrap()
+	}
+
+	fn set_context(predecessor_id: AccountId, attached_deposit: Balance) {
+		testing_env!(VMContextBuilder::new()
+			.current_account_id(accounts(0))
+			.predecessor_account_id(predecessor_id)
+			.attached_deposit(attached_deposit)
+			.build());
+	}
+
+	// a call signed with the guest key, which can only come from the contract account itself
+	fn set_guest_context() {
+		testing_env!(VMContextBuilder::new()
+			.current_account_id(accounts(0))
+			.predecessor_account_id(accounts(0))
+			.signer_account_pk(guest_public_key())
+			.build());
+	}
+
+	fn metadata() -> TokenMetadata {
+		TokenMetadata {
+			title: Some("Guest pass".to_string()),
+			description: None,
+			media: None,
+			media_hash: None,
+			copies: None,
+			issued_at: None,
+			expires_at: None,
+			starts_at: None,
+			updated_at: None,
+			extra: None,
+			reference: None,
+			reference_hash: None,
+		}
+	}
+
+	// the contract account alice is also the owner. Bob sponsors the pool and alice registers a key with a quota
+	fn setup(quota: u32) -> Contract {
+		set_context(accounts(0), 0);
+		let mut contract = Contract::new_default_meta(accounts(0));
+
+		set_context(accounts(1), ONE_NEAR);
+		contract.sponsor_pool_deposit();
+		set_context(accounts(0), 0);
+		contract.add_guest_key(guest_public_key(), quota, None);
+
+		contract
+	}
+
+	#[test]
+	fn guest_key_mints_until_its_quota_is_used_up() {
+		let mut contract = setup(2);
+		let pool_before_mints = contract.get_sponsor_pool().0;
+
+		set_guest_context();
+		contract.nft_guest_mint("1".to_string(), metadata(), accounts(2));
+		let guest_key = contract.get_guest_key(guest_public_key()).unwrap();
+		assert_eq!(guest_key.remaining_mints, 1);
+		assert_eq!(guest_key.minted, 1);
+		assert!(contract.get_sponsor_pool().0 < pool_before_mints);
+
+		set_guest_context();
+		contract.nft_guest_mint("2".to_string(), metadata(), accounts(2));
+		assert!(contract.get_guest_key(guest_public_key()).is_none());
+		assert_eq!(contract.nft_supply_for_owner(accounts(2)), U128(2));
+	}
+
+	#[test]
+	#[should_panic(expected = "Not a guest key")]
+	fn guest_key_can_not_mint_past_its_quota() {
+		let mut contract = setup(1);
+
+		set_guest_context();
+		contract.nft_guest_mint("1".to_string(), metadata(), accounts(2));
+		set_guest_context();
+		contract.nft_guest_mint("2".to_string(), metadata(), accounts(2));
+	}
+
+	#[test]
+	#[should_panic(expected = "nft_guest_mint must be called with a guest access key")]
+	fn guest_mint_from_another_account_fails() {
+		let mut contract = setup(1);
+
+		set_context(accounts(2), 0);
+		contract.nft_guest_mint("1".to_string(), metadata(), accounts(2));
+	}
+
+	#[test]
+	#[should_panic(expected = "Only the contract owner can add guest keys")]
+	fn only_the_owner_can_add_guest_keys() {
+		let mut contract = setup(1);
+
+		set_context(accounts(1), 0);
+		contract.add_guest_key("ed25519:8fWHD35Rjd78yeowShh9GwhRudRtLLsGCRjZtgPjAtw9".parse().unwrap(), 1, None);
+	}
+}
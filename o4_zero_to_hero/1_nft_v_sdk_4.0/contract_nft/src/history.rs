@@ -0,0 +1,329 @@
+use crate::*;
+
+// the maximum number of provenance records we keep per token. Once a transfer is final, the oldest records are dropped.
+pub const MAX_TOKEN_HISTORY_LEN: usize = 32;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProvenanceRecord {
+	// the account that owned the token before this transfer
+	pub previous_owner_id: AccountId,
+	// the account that received the token
+	pub new_owner_id: AccountId,
+	// the approved account that made the transfer on behalf of the owner (if any)
+	pub authorized_id: Option<AccountId>,
+	// the memo attached to the transfer
+	pub memo: Option<String>,
+	// block timestamp of the transfer, in nanoseconds
+	pub transferred_at: U64,
+}
+
+#[near_bindgen]
+impl Contract {
+	// Opt a token in to on-chain provenance tracking. Only the token owner can call this and it can't be undone.
+	#[payable]
+	pub fn nft_enable_history(&mut self, token_id: TokenId) {
+		assert_at_least_one_yocto();
+
+		let token = self.tokens_by_id.get(&token_id).expect("No token");
+		assert_eq!(
+			env::predecessor_account_id(),
+			token.owner_id,
+			"Predecessor must be the owner."
+		);
+		assert!(
+			self.token_history.get(&token_id).is_none(),
+			"History is already enabled for this token"
+		);
+
+		let initial_storage_usage = env::storage_usage();
+		self.token_history.insert(&token_id, &Vec::new());
+
+		refund_deposit(env::storage_usage() - initial_storage_usage);
+	}
+
+	// Deposit NEAR to cover the storage of provenance records written when the account transfers a token away.
+	#[payable]
+	pub fn nft_history_storage_deposit(&mut self, account_id: Option<AccountId>) {
+		let storage_account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+
+		let deposit = env::attached_deposit();
+		assert!(deposit > 0, "Requires attached deposit greater than 0");
+
+		let balance = self
+			.history_storage_deposits
+			.get(&storage_account_id)
+			.unwrap_or(0);
+		self.history_storage_deposits
+			.insert(&storage_account_id, &(balance + deposit));
+	}
+
+	// Withdraw the full unused history storage balance of the caller.
+	#[payable]
+	pub fn nft_history_storage_withdraw(&mut self) {
+		assert_one_yocto();
+
+		let account_id = env::predecessor_account_id();
+		let amount = self
+			.history_storage_deposits
+			.remove(&account_id)
+			.unwrap_or(0);
+
+		if amount > 0 {
+			Promise::new(account_id).transfer(amount);
+		}
+	}
+
+	pub fn nft_history_storage_balance_of(&self, account_id: AccountId) -> U128 {
+		U128(self.history_storage_deposits.get(&account_id).unwrap_or(0))
+	}
+
+	pub fn nft_history_enabled(&self, token_id: TokenId) -> bool {
+		self.token_history.get(&token_id).is_some()
+	}
+
+	// Paginated provenance records for a token, oldest first.
+	pub fn nft_token_history(
+		&self,
+		token_id: TokenId,
+		from_index: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<ProvenanceRecord> {
+		let history = if let Some(history) = self.token_history.get(&token_id) {
+			history
+		} else {
+			return vec![];
+		};
+
+		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+		let start = u128::from(from_index.unwrap_or(U128(0)));
+
+		history
+			.into_iter()
+			.skip(start as usize)
+			// the history is bounded, so without a limit we return everything that is left
+			.take(limit.unwrap_or(MAX_TOKEN_HISTORY_LEN as u64) as usize)
+			.collect()
+	}
+}
+
+impl Contract {
+	/*
+		append a provenance record for a token that opted in to history tracking. The storage used by the new record
+		is charged to the previous owner's history storage balance since they are the party transferring the token.
+	*/
+	pub(crate) fn internal_record_provenance(
+		&mut self,
+		token_id: &TokenId,
+		previous_owner_id: &AccountId,
+		new_owner_id: &AccountId,
+		authorized_id: Option<AccountId>,
+		memo: Option<String>,
+	) {
+		let mut history = if let Some(history) = self.token_history.get(token_id) {
+			history
+		} else {
+			return;
+		};
+
+		let initial_storage_usage = env::storage_usage();
+
+		/*
+			the log can go over MAX_TOKEN_HISTORY_LEN here. The oldest records are only dropped by
+			internal_trim_provenance once the transfer is final, so a reverted transfer call doesn't cost a genuine record.
+		*/
+		history.push(ProvenanceRecord {
+			previous_owner_id: previous_owner_id.clone(),
+			new_owner_id: new_owner_id.clone(),
+			authorized_id,
+			memo,
+			transferred_at: U64(env::block_timestamp()),
+		});
+		self.token_history.insert(token_id, &history);
+
+		let final_storage_usage = env::storage_usage();
+		let balance = self
+			.history_storage_deposits
+			.get(previous_owner_id)
+			.unwrap_or(0);
+
+		let required_cost = env::storage_byte_cost()
+			* Balance::from(final_storage_usage.saturating_sub(initial_storage_usage));
+		assert!(
+			balance >= required_cost,
+			"{} must deposit {} yoctoNEAR of history storage to transfer this token",
+			previous_owner_id,
+			required_cost
+		);
+		self.history_storage_deposits
+			.insert(previous_owner_id, &(balance - required_cost));
+	}
+
+	/*
+		drop the oldest provenance records of a token that go over MAX_TOKEN_HISTORY_LEN. Call this once a transfer is
+		final. The storage of every dropped record is credited back to the account that paid for it.
+	*/
+	pub(crate) fn internal_trim_provenance(&mut self, token_id: &TokenId) {
+		let mut history = if let Some(history) = self.token_history.get(token_id) {
+			history
+		} else {
+			return;
+		};
+
+		if history.len() <= MAX_TOKEN_HISTORY_LEN {
+			return;
+		}
+
+		let dropped_len = history.len() - MAX_TOKEN_HISTORY_LEN;
+		let dropped_records: Vec<ProvenanceRecord> = history.drain(..dropped_len).collect();
+		self.token_history.insert(token_id, &history);
+
+		for record in dropped_records {
+			// a record takes up as many bytes in the log as it takes to serialize it
+			let released_storage = record.try_to_vec().unwrap().len() as u64;
+			let balance = self
+				.history_storage_deposits
+				.get(&record.previous_owner_id)
+				.unwrap_or(0);
+			self.history_storage_deposits.insert(
+				&record.previous_owner_id,
+				&(balance + env::storage_byte_cost() * Balance::from(released_storage)),
+			);
+		}
+	}
+
+	/*
+		remove the record written by a transfer that was reverted in nft_resolve_transfer. The released storage is
+		credited back to the account that paid for it.
+	*/
+	pub(crate) fn internal_revert_provenance(&mut self, token_id: &TokenId, previous_owner_id: &AccountId) {
+		let mut history = if let Some(history) = self.token_history.get(token_id) {
+			history
+		} else {
+			return;
+		};
+
+		let is_reverted_record = history
+			.last()
+			.map(|record| &record.previous_owner_id == previous_owner_id)
+			.unwrap_or(false);
+		if !is_reverted_record {
+			return;
+		}
+
+		let initial_storage_usage = env::storage_usage();
+		history.pop();
+		self.token_history.insert(token_id, &history);
+		let released_storage = initial_storage_usage.saturating_sub(env::storage_usage());
+
+		let balance = self
+			.history_storage_deposits
+			.get(previous_owner_id)
+			.unwrap_or(0);
+		self.history_storage_deposits.insert(
+			previous_owner_id,
+			&(balance + env::storage_byte_cost() * Balance::from(released_storage)),
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use near_sdk::test_utils::{accounts, VMContextBuilder};
+	use near_sdk::testing_env;
+
+	const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+
+	fn set_context(predecessor_id: AccountId, attached_deposit: Balance) {
+		testing_env!(VMContextBuilder::new()
+			.predecessor_account_id(predecessor_id)
+			.attached_deposit(attached_deposit)
+			.build());
+	}
+
+	fn metadata() -> TokenMetadata {
+		TokenMetadata {
+			title: Some("Olympus Mons".to_string()),
+			description: None,
+			media: None,
+			media_hash: None,
+			copies: None,
+			issued_at: None,
+			expires_at: None,
+			starts_at: None,
+			updated_at: None,
+			extra: None,
+			reference: None,
+			reference_hash: None,
+		}
+	}
+
+	// mint a token to bob and opt it in to history tracking
+	fn setup() -> Contract {
+		set_context(accounts(0), 0);
+		let mut contract = Contract::new_default_meta(accounts(0));
+
+		set_context(accounts(1), ONE_NEAR);
+		contract.nft_mint("token".to_string(), metadata(), accounts(1), None, None, None);
+		set_context(accounts(1), ONE_NEAR);
+		contract.nft_enable_history("token".to_string());
+
+		contract
+	}
+
+	#[test]
+	fn transfer_records_provenance_and_charges_the_sender() {
+		let mut contract = setup();
+
+		set_context(accounts(1), ONE_NEAR);
+		contract.nft_history_storage_deposit(None);
+		set_context(accounts(1), 1);
+		contract.nft_transfer(accounts(2), "token".to_string(), None, Some("gift".to_string()));
+
+		let history = contract.nft_token_history("token".to_string(), None, None);
+		assert_eq!(history.len(), 1);
+		assert_eq!(history[0].previous_owner_id, accounts(1));
+		assert_eq!(history[0].new_owner_id, accounts(2));
+		assert_eq!(history[0].authorized_id, None);
+		assert_eq!(history[0].memo, Some("gift".to_string()));
+		assert!(contract.nft_history_storage_balance_of(accounts(1)).0 < ONE_NEAR);
+	}
+
+	#[test]
+	#[should_panic(expected = "yoctoNEAR of history storage to transfer this token")]
+	fn transfer_without_history_storage_fails() {
+		let mut contract = setup();
+
+		set_context(accounts(1), 1);
+		contract.nft_transfer(accounts(2), "token".to_string(), None, None);
+	}
+
+	#[test]
+	fn history_is_bounded_and_refunds_dropped_records() {
+		let mut contract = setup();
+
+		for account_id in [accounts(1), accounts(2)] {
+			set_context(account_id, ONE_NEAR);
+			contract.nft_history_storage_deposit(None);
+		}
+		for transfer in 0..MAX_TOKEN_HISTORY_LEN + 2 {
+			let (sender_id, receiver_id) = if transfer % 2 == 0 {
+				(accounts(1), accounts(2))
+			} else {
+				(accounts(2), accounts(1))
+			};
+			set_context(sender_id, 1);
+			contract.nft_transfer(receiver_id, "token".to_string(), None, None);
+		}
+
+		let history = contract.nft_token_history("token".to_string(), None, None);
+		assert_eq!(history.len(), MAX_TOKEN_HISTORY_LEN);
+		// the two oldest records were dropped, one paid by each account, so both get the same storage back
+		assert_eq!(history[0].previous_owner_id, accounts(1));
+		assert_eq!(
+			contract.nft_history_storage_balance_of(accounts(1)),
+			contract.nft_history_storage_balance_of(accounts(2))
+		);
+	}
+}
@@ -44,6 +44,12 @@ pub(crate) fn hash_account_id(account_id: &AccountId) -> CryptoHash {
 	hash
 }
 
+pub(crate) fn hash_token_type(token_type: &TokenType) -> CryptoHash {
+	let mut hash = CryptoHash::default();
+	hash.copy_from_slice(&env::sha256(token_type.as_bytes()));
+	hash
+}
+
 // used to make sure the user attached exactly 1 yoctoNEAR
 pub(crate) fn assert_one_yocto() {
 	assert_eq!(
@@ -91,8 +97,8 @@ impl Contract {
 	) {
 		//get the set of tokens for the given account
 		let mut tokens_set = self.tokens_per_owner.get(account_id).unwrap_or_else(|| {
-			// if the account doesn't have any tokens, we create a new unordered set
-			UnorderedSet::new(
+			// if the account doesn't have any tokens, we create a new ordered set
+			TreeMap::new(
 				StorageKey::TokenPerOwnerInner {
 					account_id_hash: hash_account_id(&account_id),
 				}
@@ -101,12 +107,28 @@ impl Contract {
 			)
 		});
 		// We insert the token ID into the set
-		tokens_set.insert(token_id);
+		tokens_set.insert(token_id, &());
 
 		// We insert that set for the given account ID.
 		self.tokens_per_owner.insert(&account_id, &tokens_set);
 	}
 
+	// add a token to the set of tokens of its token type (series)
+	pub(crate) fn internal_add_token_to_type(&mut self, token_type: &TokenType, token_id: &TokenId) {
+		let mut tokens_set = self.tokens_per_type.get(token_type).unwrap_or_else(|| {
+			TreeMap::new(
+				StorageKey::TokensPerTypeInner {
+					token_type_hash: hash_token_type(token_type),
+				}
+				.try_to_vec()
+				.unwrap(),
+			)
+		});
+		tokens_set.insert(token_id, &());
+
+		self.tokens_per_type.insert(token_type, &tokens_set);
+	}
+
 	// remove a token from an owner (internal method and can't be called directly via CLI).
 	pub(crate) fn internal_remove_token_from_owner(
 		&mut self,
@@ -180,18 +202,34 @@ impl Contract {
 			approved_account_ids: Default::default(),
 			next_approval_id: token.next_approval_id,
 			royalty: token.royalty.clone(),
+			token_type: token.token_type.clone(),
 		};
 
 		// insert that new token into the tokens_by_id, replacing the old entry
 		self.tokens_by_id.insert(token_id, &new_token);
 
+		// record the transfer in the token's provenance log if it opted in to history tracking
+		let provenance_authorized_id = if sender_id != &token.owner_id {
+			Some(sender_id.clone())
+		} else {
+			None
+		};
+		self.internal_record_provenance(
+			token_id,
+			&token.owner_id,
+			receiver_id,
+			provenance_authorized_id,
+			memo.clone(),
+		);
+
 		// if there was some memo attached, we log it.
 		if let Some(memo_content) = memo.as_ref() {
 			env::log_str(&format!("Memo: {}", memo_content).to_string());
 		}
 
+		// the approval ID is optional, so whether the transfer was authorized depends on who sent it
 		let mut authorized_id = None;
-		if approval_id.is_some() {
+		if sender_id != &token.owner_id {
 			authorized_id = Some(sender_id.to_string());
 		}
 		let nft_transfer_log: EventLog = EventLog {
@@ -1,18 +1,23 @@
 use near_sdk::{
 	borsh::{self, BorshDeserialize, BorshSerialize},
-	collections::{LazyOption, LookupMap, UnorderedMap, UnorderedSet},
+	collections::{LazyOption, LookupMap, TreeMap, UnorderedMap, UnorderedSet},
 	env, ext_contract,
-	json_types::{Base64VecU8, U128},
+	json_types::{Base64VecU8, U128, U64},
 	log, near_bindgen,
 	serde::{Deserialize, Serialize},
 	AccountId, Balance, CryptoHash, Gas, PanicOnDefault, Promise, PromiseOrValue, PromiseResult,
+	PublicKey,
 };
 use std::collections::HashMap;
 
 pub use crate::approval::*;
 pub use crate::events::*;
+pub use crate::guest::*;
+pub use crate::history::*;
+pub use crate::media::*;
 use crate::internal::*;
 pub use crate::metadata::*;
+pub use crate::migrate::*;
 pub use crate::mint::*;
 pub use crate::nft_core::*;
 pub use crate::royalty::*;
@@ -20,8 +25,12 @@ pub use crate::royalty::*;
 mod approval;
 mod enumeration;
 mod events;
+mod guest;
+mod history;
 mod internal;
+mod media;
 mod metadata;
+mod migrate;
 mod mint;
 mod nft_core;
 mod royalty;
@@ -42,6 +51,12 @@ pub enum StorageKey {
 	TokensPerType,
 	TokensPerTypeInner { token_type_hash: CryptoHash },
 	TokenTypesLocked,
+	TokenHistory,
+	HistoryStorageDeposits,
+	GuestKeys,
+	OnChainMedia,
+	TokensPerOwnerSorted,
+	TokenMetadataByIdSorted,
 }
 
 #[near_bindgen]
@@ -49,14 +64,30 @@ pub enum StorageKey {
 pub struct Contract {
 	// contract owner
 	pub owner_id: AccountId,
-	// keeps track of all the token IDs for a given account
-	pub tokens_per_owner: LookupMap<AccountId, UnorderedSet<TokenId>>,
+	// keeps track of all the token IDs for a given account, sorted by token ID
+	pub tokens_per_owner: LookupMap<AccountId, TreeMap<TokenId, ()>>,
 	// keeps track of the token struct for a given ID
 	pub tokens_by_id: LookupMap<TokenId, Token>,
-	// keeps track of the token metadata for a given ID
-	pub token_metadata_by_id: UnorderedMap<TokenId, TokenMetadata>,
+	// keeps track of the token metadata for a given ID, sorted by token ID
+	pub token_metadata_by_id: TreeMap<TokenId, TokenMetadata>,
+	// keeps track of all the token IDs for a given token type (series), sorted by token ID
+	pub tokens_per_type: LookupMap<TokenType, TreeMap<TokenId, ()>>,
 	// keeps track of the metadata for the contract
 	pub metadata: LazyOption<NFTContractMetadata>,
+	// keeps track of the provenance records for tokens that opted in to history tracking
+	pub token_history: LookupMap<TokenId, Vec<ProvenanceRecord>>,
+	// keeps track of the storage that accounts have paid for provenance records
+	pub history_storage_deposits: LookupMap<AccountId, Balance>,
+	// keeps track of the remaining mint quota for every guest access key
+	pub guest_keys: LookupMap<PublicKey, GuestKey>,
+	// NEAR available to pay for the storage of guest mints
+	pub sponsor_pool: Balance,
+	// URL schemes accepted for token media and reference
+	pub allowed_url_schemes: Vec<String>,
+	// keeps track of the media stored on-chain for a given token ID
+	pub on_chain_media: LookupMap<TokenId, OnChainMedia>,
+	// the old collections of a migrated contract, until all of their tokens have been moved over
+	pub legacy_tokens: Option<LegacyTokens>,
 }
 
 #[near_bindgen]
@@ -67,13 +98,24 @@ impl Contract {
 			owner_id,
 			tokens_per_owner: LookupMap::new(StorageKey::TokensPerOwner.try_to_vec().unwrap()),
 			tokens_by_id: LookupMap::new(StorageKey::TokensById.try_to_vec().unwrap()),
-			token_metadata_by_id: UnorderedMap::new(
-				StorageKey::TokenMetadataById.try_to_vec().unwrap(),
-			),
+			token_metadata_by_id: TreeMap::new(StorageKey::TokenMetadataById.try_to_vec().unwrap()),
+			tokens_per_type: LookupMap::new(StorageKey::TokensPerType.try_to_vec().unwrap()),
 			metadata: LazyOption::new(
 				StorageKey::NFTContractMetadata.try_to_vec().unwrap(),
 				Some(&metadata),
 			),
+			token_history: LookupMap::new(StorageKey::TokenHistory.try_to_vec().unwrap()),
+			history_storage_deposits: LookupMap::new(
+				StorageKey::HistoryStorageDeposits.try_to_vec().unwrap(),
+			),
+			guest_keys: LookupMap::new(StorageKey::GuestKeys.try_to_vec().unwrap()),
+			sponsor_pool: 0,
+			allowed_url_schemes: DEFAULT_ALLOWED_URL_SCHEMES
+				.iter()
+				.map(|scheme| scheme.to_string())
+				.collect(),
+			on_chain_media: LookupMap::new(StorageKey::OnChainMedia.try_to_vec().unwrap()),
+			legacy_tokens: None,
 		}
 	}
 
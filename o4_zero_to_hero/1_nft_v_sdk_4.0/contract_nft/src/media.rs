@@ -0,0 +1,258 @@
+use crate::*;
+
+// length of a sha256 hash in bytes
+pub const HASH_LEN: usize = 32;
+
+// the largest media file that can be stored on-chain (16 KB), enough for small SVGs
+pub const MAX_ON_CHAIN_MEDIA_BYTES: usize = 16_384;
+
+// URL schemes accepted for `media` and `reference` when the contract is initialized
+pub const DEFAULT_ALLOWED_URL_SCHEMES: [&str; 3] = ["https", "ipfs", "ar"];
+
+// small media file stored on the contract itself instead of behind a URL
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OnChainMedia {
+	// ex. "image/svg+xml"
+	pub mime_type: String,
+	// the raw bytes of the media file
+	pub data: Base64VecU8,
+}
+
+// make sure a hash has the size of a sha256 hash
+fn assert_valid_hash(hash: &Base64VecU8, field: &str) {
+	assert_eq!(
+		hash.0.len(),
+		HASH_LEN,
+		"{}_hash must decode to {} bytes",
+		field,
+		HASH_LEN
+	);
+}
+
+#[near_bindgen]
+impl Contract {
+	// Replace the list of URL schemes accepted for token media and reference. Only the contract owner can call this.
+	pub fn set_allowed_url_schemes(&mut self, schemes: Vec<String>) {
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the contract owner can set the allowed URL schemes"
+		);
+
+		self.allowed_url_schemes = schemes.iter().map(|scheme| scheme.to_lowercase()).collect();
+	}
+
+	pub fn get_allowed_url_schemes(&self) -> Vec<String> {
+		self.allowed_url_schemes.clone()
+	}
+
+	// Returns the media stored on-chain for a token, if it was minted with on-chain media.
+	pub fn nft_media(&self, token_id: TokenId) -> Option<OnChainMedia> {
+		self.on_chain_media.get(&token_id)
+	}
+
+	/*
+		Check that the token points to the media the caller has. `media_bytes_hash` is the sha256 of the media
+		bytes the caller downloaded. If the media is stored on-chain, we also check that the stored bytes still
+		hash to the token's media_hash.
+	*/
+	pub fn nft_verify_metadata(&self, token_id: TokenId, media_bytes_hash: Base64VecU8) -> bool {
+		let metadata = self.token_metadata_by_id.get(&token_id).expect("No token");
+
+		let media_hash = if let Some(media_hash) = metadata.media_hash {
+			media_hash
+		} else {
+			return false;
+		};
+
+		if let Some(on_chain_media) = self.on_chain_media.get(&token_id) {
+			if env::sha256(&on_chain_media.data.0) != media_hash.0 {
+				return false;
+			}
+		}
+
+		media_hash.0 == media_bytes_hash.0
+	}
+}
+
+impl Contract {
+	// make sure the URL uses one of the allowed schemes
+	pub(crate) fn assert_allowed_url(&self, url: &str, field: &str) {
+		let scheme = url
+			.split_once("://")
+			.map(|(scheme, _)| scheme.to_lowercase())
+			.unwrap_or_else(|| env::panic_str(&format!("{} must be a URL with a scheme", field)));
+
+		assert!(
+			self.allowed_url_schemes.contains(&scheme),
+			"{} URL scheme {} is not allowed",
+			field,
+			scheme
+		);
+	}
+
+	/*
+		validate the token metadata before minting. Media and reference must come with a hash and use an
+		allowed URL scheme. Every hash that is given must be 32 bytes, even without its media or reference. If the
+		media is stored on-chain, its hash is filled in (or checked) from the bytes.
+	*/
+	pub(crate) fn internal_validate_metadata(
+		&self,
+		metadata: &mut TokenMetadata,
+		on_chain_media: Option<&OnChainMedia>,
+	) {
+		if let Some(on_chain_media) = on_chain_media {
+			assert!(
+				on_chain_media.data.0.len() <= MAX_ON_CHAIN_MEDIA_BYTES,
+				"On-chain media cannot be larger than {} bytes",
+				MAX_ON_CHAIN_MEDIA_BYTES
+			);
+			assert!(
+				on_chain_media.mime_type.starts_with("image/"),
+				"On-chain media must be an image"
+			);
+
+			let data_hash = Base64VecU8(env::sha256(&on_chain_media.data.0));
+			if let Some(media_hash) = metadata.media_hash.as_ref() {
+				assert_eq!(
+					media_hash.0, data_hash.0,
+					"media_hash doesn't match the on-chain media"
+				);
+			} else {
+				metadata.media_hash = Some(data_hash);
+			}
+		}
+
+		if let Some(media) = metadata.media.as_ref() {
+			self.assert_allowed_url(media, "media");
+			assert!(metadata.media_hash.is_some(), "media_hash is required when media is set");
+		}
+		if let Some(media_hash) = metadata.media_hash.as_ref() {
+			assert_valid_hash(media_hash, "media");
+		}
+
+		if let Some(reference) = metadata.reference.as_ref() {
+			self.assert_allowed_url(reference, "reference");
+			assert!(
+				metadata.reference_hash.is_some(),
+				"reference_hash is required when reference is set"
+			);
+		}
+		if let Some(reference_hash) = metadata.reference_hash.as_ref() {
+			assert_valid_hash(reference_hash, "reference");
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use near_sdk::test_utils::{accounts, VMContextBuilder};
+	use near_sdk::testing_env;
+
+	const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+
+	fn set_context(predecessor_id: AccountId, attached_deposit: Balance) {
+		testing_env!(VMContextBuilder::new()
+			.current_account_id(accounts(0))
+			.predecessor_account_id(predecessor_id)
+			.attached_deposit(attached_deposit)
+			.build());
+	}
+
+	fn metadata(media: Option<&str>, media_hash: Option<Vec<u8>>) -> TokenMetadata {
+		TokenMetadata {
+			title: None,
+			description: None,
+			media: media.map(|media| media.to_string()),
+			media_hash: media_hash.map(Base64VecU8),
+			copies: None,
+			issued_at: None,
+			expires_at: None,
+			starts_at: None,
+			updated_at: None,
+			extra: None,
+			reference: None,
+			reference_hash: None,
+		}
+	}
+
+	fn setup() -> Contract {
+		set_context(accounts(0), 0);
+		Contract::new_default_meta(accounts(0))
+	}
+
+	fn mint(contract: &mut Contract, metadata: TokenMetadata, on_chain_media: Option<OnChainMedia>) {
+		set_context(accounts(1), ONE_NEAR);
+		contract.nft_mint("token".to_string(), metadata, accounts(1), None, None, on_chain_media);
+	}
+
+	#[test]
+	fn media_with_a_hash_can_be_verified() {
+		let mut contract = setup();
+		let media_hash = env::sha256(b"media bytes");
+
+		mint(&mut contract, metadata(Some("ipfs://media"), Some(media_hash.clone())), None);
+
+		assert!(contract.nft_verify_metadata("token".to_string(), Base64VecU8(media_hash)));
+		assert!(!contract.nft_verify_metadata(
+			"token".to_string(),
+			Base64VecU8(env::sha256(b"other bytes"))
+		));
+	}
+
+	#[test]
+	fn on_chain_media_fills_in_its_hash() {
+		let mut contract = setup();
+		let on_chain_media = OnChainMedia {
+			mime_type: "image/svg+xml".to_string(),
+			data: Base64VecU8(b"<svg></svg>".to_vec()),
+		};
+
+		mint(&mut contract, metadata(None, None), Some(on_chain_media));
+
+		let token = contract.nft_token("token".to_string()).unwrap();
+		assert_eq!(token.metadata.media_hash.unwrap().0, env::sha256(b"<svg></svg>"));
+		assert!(contract.nft_verify_metadata(
+			"token".to_string(),
+			Base64VecU8(env::sha256(b"<svg></svg>"))
+		));
+	}
+
+	#[test]
+	#[should_panic(expected = "media_hash is required when media is set")]
+	fn media_without_a_hash_is_rejected() {
+		let mut contract = setup();
+
+		mint(&mut contract, metadata(Some("ipfs://media"), None), None);
+	}
+
+	#[test]
+	#[should_panic(expected = "media_hash must decode to 32 bytes")]
+	fn a_hash_without_its_media_is_still_validated() {
+		let mut contract = setup();
+
+		mint(&mut contract, metadata(None, Some(vec![1, 2, 3])), None);
+	}
+
+	#[test]
+	#[should_panic(expected = "media URL scheme http is not allowed")]
+	fn media_with_a_scheme_that_is_not_allowed_is_rejected() {
+		let mut contract = setup();
+
+		mint(&mut contract, metadata(Some("http://media"), Some(env::sha256(b"media bytes"))), None);
+	}
+
+	#[test]
+	#[should_panic(expected = "media_hash doesn't match the on-chain media")]
+	fn on_chain_media_must_match_a_given_hash() {
+		let mut contract = setup();
+		let on_chain_media = OnChainMedia {
+			mime_type: "image/png".to_string(),
+			data: Base64VecU8(vec![0; 16]),
+		};
+
+		mint(&mut contract, metadata(None, Some(env::sha256(b"media bytes"))), Some(on_chain_media));
+	}
+}
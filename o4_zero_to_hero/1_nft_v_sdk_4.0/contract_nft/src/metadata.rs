@@ -1,6 +1,8 @@
 use crate::*;
 
 pub type TokenId = String;
+// token types group tokens into a series
+pub type TokenType = String;
 
 #[derive(Deserialize, Serialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -17,6 +19,8 @@ pub struct Token {
 	pub next_approval_id: u64,
 	// keep track of the royalty percentages for the token in a hash map
 	pub royalty: HashMap<AccountId, u32>,
+	// the token type (series) the token belongs to
+	pub token_type: Option<TokenType>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -32,6 +36,9 @@ pub struct JsonToken {
 	pub approved_account_ids: HashMap<AccountId, u64>,
 	// keep track of the royalty percentages for the token in a hash map
 	pub royalty: HashMap<AccountId, u32>,
+	// the token type (series) the token belongs to
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub token_type: Option<TokenType>,
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
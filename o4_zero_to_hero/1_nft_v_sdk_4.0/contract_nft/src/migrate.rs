@@ -0,0 +1,129 @@
+use crate::*;
+
+// the most tokens nft_migrate_tokens moves in a single call. Moving each token costs GAS.
+pub const MAX_MIGRATE_BATCH_LEN: u64 = 100;
+
+// a token from before tokens had a token type
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct OldToken {
+	pub owner_id: AccountId,
+	pub approved_account_ids: HashMap<AccountId, u64>,
+	pub next_approval_id: u64,
+	pub royalty: HashMap<AccountId, u32>,
+}
+
+// the contract state from before the enumeration indexes were sorted by token ID
+#[derive(BorshDeserialize)]
+pub struct OldContract {
+	pub owner_id: AccountId,
+	pub tokens_per_owner: LookupMap<AccountId, UnorderedSet<TokenId>>,
+	pub tokens_by_id: LookupMap<TokenId, OldToken>,
+	pub token_metadata_by_id: UnorderedMap<TokenId, TokenMetadata>,
+	pub metadata: LazyOption<NFTContractMetadata>,
+}
+
+// the old indexes that still hold tokens which haven't been moved over by nft_migrate_tokens
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LegacyTokens {
+	pub tokens_per_owner: LookupMap<AccountId, UnorderedSet<TokenId>>,
+	pub token_metadata_by_id: UnorderedMap<TokenId, TokenMetadata>,
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		Switch a contract deployed with the old state over to the new one. Call this right after deploying the new code.
+		The tokens themselves are moved in batches by nft_migrate_tokens, a token can't be used until it was moved.
+	*/
+	#[private]
+	#[init(ignore_state)]
+	pub fn migrate() -> Self {
+		let old: OldContract = env::state_read().expect("Failed to read the old state");
+
+		Self {
+			owner_id: old.owner_id,
+			// the new indexes get their own prefixes so they don't mix with the old ones while tokens are moved
+			tokens_per_owner: LookupMap::new(StorageKey::TokensPerOwnerSorted.try_to_vec().unwrap()),
+			// tokens keep their prefix, every token is rewritten in place when it is moved
+			tokens_by_id: LookupMap::new(StorageKey::TokensById.try_to_vec().unwrap()),
+			token_metadata_by_id: TreeMap::new(StorageKey::TokenMetadataByIdSorted.try_to_vec().unwrap()),
+			tokens_per_type: LookupMap::new(StorageKey::TokensPerType.try_to_vec().unwrap()),
+			metadata: old.metadata,
+			token_history: LookupMap::new(StorageKey::TokenHistory.try_to_vec().unwrap()),
+			history_storage_deposits: LookupMap::new(
+				StorageKey::HistoryStorageDeposits.try_to_vec().unwrap(),
+			),
+			guest_keys: LookupMap::new(StorageKey::GuestKeys.try_to_vec().unwrap()),
+			sponsor_pool: 0,
+			allowed_url_schemes: DEFAULT_ALLOWED_URL_SCHEMES
+				.iter()
+				.map(|scheme| scheme.to_string())
+				.collect(),
+			on_chain_media: LookupMap::new(StorageKey::OnChainMedia.try_to_vec().unwrap()),
+			legacy_tokens: Some(LegacyTokens {
+				tokens_per_owner: old.tokens_per_owner,
+				token_metadata_by_id: old.token_metadata_by_id,
+			}),
+		}
+	}
+
+	/*
+		Move up to `limit` tokens (at most MAX_MIGRATE_BATCH_LEN) from the old state over to the new indexes. Call this
+		until it returns 0, the number of tokens that are left to move. Only the owner of the contract can call this.
+	*/
+	pub fn nft_migrate_tokens(&mut self, limit: Option<u64>) -> U64 {
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the owner can migrate tokens"
+		);
+
+		let mut legacy_tokens = self.legacy_tokens.take().expect("No tokens left to migrate");
+		let mut old_tokens_by_id: LookupMap<TokenId, OldToken> =
+			LookupMap::new(StorageKey::TokensById.try_to_vec().unwrap());
+
+		for _ in 0..limit.unwrap_or(MAX_MIGRATE_BATCH_LEN).min(MAX_MIGRATE_BATCH_LEN) {
+			// take the tokens from the end so removing them doesn't shuffle the rest of the old index
+			let len = legacy_tokens.token_metadata_by_id.len();
+			if len == 0 {
+				break;
+			}
+			let token_id = legacy_tokens.token_metadata_by_id.keys_as_vector().get(len - 1).unwrap();
+			let metadata = legacy_tokens.token_metadata_by_id.remove(&token_id).unwrap();
+
+			// the old token has to be removed before the new one is written, the old bytes can't be read as a Token
+			let old_token = old_tokens_by_id.remove(&token_id).expect("No token");
+			self.tokens_by_id.insert(
+				&token_id,
+				&Token {
+					owner_id: old_token.owner_id.clone(),
+					approved_account_ids: old_token.approved_account_ids,
+					next_approval_id: old_token.next_approval_id,
+					royalty: old_token.royalty,
+					token_type: None,
+				},
+			);
+			self.token_metadata_by_id.insert(&token_id, &metadata);
+			self.internal_add_token_to_owner(&old_token.owner_id, &token_id);
+
+			// drop the token from the owner's old set, and the set itself once it is empty
+			if let Some(mut old_tokens_set) = legacy_tokens.tokens_per_owner.get(&old_token.owner_id) {
+				old_tokens_set.remove(&token_id);
+				if old_tokens_set.is_empty() {
+					legacy_tokens.tokens_per_owner.remove(&old_token.owner_id);
+				} else {
+					legacy_tokens
+						.tokens_per_owner
+						.insert(&old_token.owner_id, &old_tokens_set);
+				}
+			}
+		}
+
+		let remaining = legacy_tokens.token_metadata_by_id.len();
+		if remaining > 0 {
+			self.legacy_tokens = Some(legacy_tokens);
+		}
+
+		U64(remaining)
+	}
+}
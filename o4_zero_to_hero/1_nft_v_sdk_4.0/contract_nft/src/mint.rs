@@ -9,10 +9,43 @@ impl Contract {
 		metadata: TokenMetadata,
 		receiver_id: AccountId,
 		perpetual_royalties: Option<HashMap<AccountId, u32>>,
+		token_type: Option<TokenType>,
+		on_chain_media: Option<OnChainMedia>,
 	) {
 		// measure the initial storage being used on the contract
 		let initial_storage_usage = env::storage_usage();
 
+		self.internal_mint(
+			token_id,
+			metadata,
+			receiver_id,
+			perpetual_royalties,
+			token_type,
+			on_chain_media,
+		);
+
+		// calculate the required storage which was the used - initial
+		let required_storage_in_bytes = env::storage_usage() - initial_storage_usage;
+
+		// refund any excess storage if the user attached too much. Panic if they didn't attach enough to cover the required.
+		refund_deposit(required_storage_in_bytes);
+	}
+}
+
+impl Contract {
+	// creates the token, stores its metadata and logs the mint event. The caller is responsible for the storage cost.
+	pub(crate) fn internal_mint(
+		&mut self,
+		token_id: TokenId,
+		mut metadata: TokenMetadata,
+		receiver_id: AccountId,
+		perpetual_royalties: Option<HashMap<AccountId, u32>>,
+		token_type: Option<TokenType>,
+		on_chain_media: Option<OnChainMedia>,
+	) {
+		// make sure the media and reference can be verified before we store anything
+		self.internal_validate_metadata(&mut metadata, on_chain_media.as_ref());
+
 		// create a royalty map to store in the token
 		let mut royalty = HashMap::new();
 
@@ -34,6 +67,7 @@ impl Contract {
 			approved_account_ids: Default::default(),
 			next_approval_id: 0,
 			royalty,
+			token_type,
 		};
 
 		// insert the token ID token struct and make sure that the token doesn't exist
@@ -45,9 +79,19 @@ impl Contract {
 		// insert the token ID and metadata
 		self.token_metadata_by_id.insert(&token_id, &metadata);
 
+		// store the media bytes if the token is minted with on-chain media
+		if let Some(on_chain_media) = on_chain_media.as_ref() {
+			self.on_chain_media.insert(&token_id, on_chain_media);
+		}
+
 		// call the internal method for adding the token to the owner
 		self.internal_add_token_to_owner(&token.owner_id, &token_id);
 
+		// if the token belongs to a series, index it under its token type
+		if let Some(token_type) = token.token_type.as_ref() {
+			self.internal_add_token_to_type(token_type, &token_id);
+		}
+
 		let nft_mint_log: EventLog = EventLog {
 			standard: NFT_STANDARD_NAME.to_string(),
 			version: NFT_METADATA_SPEC.to_string(),
@@ -59,11 +103,5 @@ impl Contract {
 		};
 
 		env::log_str(&nft_mint_log.to_string());
-
-		// calculate the required storage which was the used - initial
-		let required_storage_in_bytes = env::storage_usage() - initial_storage_usage;
-
-		// refund any excess storage if the user attached too much. Panic if they didn't attach enough to cover the required.
-		refund_deposit(required_storage_in_bytes);
 	}
 }
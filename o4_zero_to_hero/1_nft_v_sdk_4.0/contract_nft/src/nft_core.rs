@@ -4,6 +4,20 @@ const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas(10_000_000_000_000);
 const GAS_FOR_NFT_TRANSFER_CALL: Gas = Gas(5_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER.0);
 const MIN_GAS_FOR_NFT_TRANSFER_CALL: Gas = Gas(100_000_000_000_000);
 const NO_DEPOSIT: Balance = 0;
+// the maximum number of tokens that can be sent in one nft_batch_transfer_call. Resolving each token costs GAS.
+const MAX_BATCH_TRANSFER_LEN: usize = 10;
+// GAS nft_batch_transfer_call keeps for itself for every token it transfers, on top of GAS_FOR_NFT_TRANSFER_CALL
+const GAS_FOR_BATCH_TRANSFER_PER_TOKEN: Gas = Gas(5_000_000_000_000);
+
+// the state of a token before it was transferred in a transfer call, used to return it if the receiver rejects it
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PreviousTokenState {
+	pub authorized_id: Option<String>,
+	pub owner_id: AccountId,
+	pub token_id: TokenId,
+	pub approved_account_ids: HashMap<AccountId, u64>,
+}
 
 pub trait CoreNonFungibleToken {
 	fn nft_token(&self, token_id: TokenId) -> Option<JsonToken>;
@@ -12,7 +26,7 @@ pub trait CoreNonFungibleToken {
 		&mut self,
 		receiver_id: AccountId,
 		token_id: TokenId,
-		approval_id: u64,
+		approval_id: Option<u64>,
 		memo: Option<String>,
 	);
 
@@ -20,7 +34,7 @@ pub trait CoreNonFungibleToken {
 		&mut self,
 		receiver_id: AccountId,
 		token_id: TokenId,
-		approval_id: u64,
+		approval_id: Option<u64>,
 		memo: Option<String>,
 		msg: String,
 	) -> PromiseOrValue<bool>;
@@ -35,6 +49,15 @@ trait NonFungibleTokenReceiver {
 		token_id: TokenId,
 		msg: String,
 	) -> Promise;
+
+	// Receives several tokens at once. Returns one bool per token, true meaning the token should be returned.
+	fn nft_on_batch_transfer(
+		&mut self,
+		sender_id: AccountId,
+		previous_owner_ids: Vec<AccountId>,
+		token_ids: Vec<TokenId>,
+		msg: String,
+	) -> Promise;
 }
 
 #[ext_contract(ext_self)]
@@ -48,9 +71,16 @@ trait NonFungibleTokenResolver {
 		approved_account_ids: HashMap<AccountId, u64>,
 		memo: Option<String>,
 	) -> bool;
+
+	fn nft_resolve_batch_transfer(
+		&mut self,
+		receiver_id: AccountId,
+		previous_tokens: Vec<PreviousTokenState>,
+		memo: Option<String>,
+	) -> Vec<bool>;
 }
 
-trait NonFungibleTokenResolver {
+pub trait NonFungibleTokenResolver {
 	fn nft_resolve_transfer(
 		&mut self,
 		authorized_id: Option<String>,
@@ -60,6 +90,13 @@ trait NonFungibleTokenResolver {
 		approved_account_ids: HashMap<AccountId, u64>,
 		memo: Option<String>,
 	) -> bool;
+
+	fn nft_resolve_batch_transfer(
+		&mut self,
+		receiver_id: AccountId,
+		previous_tokens: Vec<PreviousTokenState>,
+		memo: Option<String>,
+	) -> Vec<bool>;
 }
 
 #[near_bindgen]
@@ -70,7 +107,7 @@ impl CoreNonFungibleToken for Contract {
 		&mut self,
 		receiver_id: AccountId,
 		token_id: TokenId,
-		approval_id: u64,
+		approval_id: Option<u64>,
 		memo: Option<String>,
 	) {
 		// assert that the user attached exactly 1 yoctoNEAR. This is for security and so that the user will be redirected to the NEAR wallet.
@@ -80,7 +117,10 @@ impl CoreNonFungibleToken for Contract {
 
 		//call the internal transfer method and get back the previous token so we can refund the approved account IDs
 		let previous_token =
-			self.internal_transfer(&sender_id, &receiver_id, &token_id, Some(approval_id), memo);
+			self.internal_transfer(&sender_id, &receiver_id, &token_id, approval_id, memo);
+
+		// the transfer is final, so the provenance log can drop its oldest records
+		self.internal_trim_provenance(&token_id);
 
 		//we refund the owner for releasing the storage used up by the approved account IDs
 		refund_approved_account_ids(
@@ -95,7 +135,7 @@ impl CoreNonFungibleToken for Contract {
 		&mut self,
 		receiver_id: AccountId,
 		token_id: TokenId,
-		approval_id: u64,
+		approval_id: Option<u64>,
 		memo: Option<String>,
 		msg: String,
 	) -> PromiseOrValue<bool> {
@@ -123,7 +163,7 @@ impl CoreNonFungibleToken for Contract {
 			&sender_id,
 			&receiver_id,
 			&token_id,
-			Some(approval_id),
+			approval_id,
 			memo.clone(),
 		);
 
@@ -166,6 +206,7 @@ impl CoreNonFungibleToken for Contract {
 				metadata,
 				approved_account_ids: token.approved_account_ids,
 				royalty: token.royalty,
+				token_type: token.token_type,
 			});
 		} else {
 			None
@@ -173,6 +214,104 @@ impl CoreNonFungibleToken for Contract {
 	}
 }
 
+#[near_bindgen]
+impl Contract {
+	/*
+		Transfer several tokens to one receiver contract and call nft_on_batch_transfer on it once. The receiver
+		decides per token whether to keep it, and nft_resolve_batch_transfer only returns the rejected tokens.
+		Resolves to one bool per token, true meaning the receiver kept the token.
+	*/
+	#[payable]
+	pub fn nft_batch_transfer_call(
+		&mut self,
+		receiver_id: AccountId,
+		token_ids: Vec<TokenId>,
+		approval_ids: Option<Vec<u64>>,
+		memo: Option<String>,
+		msg: String,
+	) -> PromiseOrValue<Vec<bool>> {
+		assert_one_yocto();
+
+		assert!(!token_ids.is_empty(), "Token IDs must not be empty");
+		assert!(
+			token_ids.len() <= MAX_BATCH_TRANSFER_LEN,
+			"Cannot transfer more than {} tokens at once",
+			MAX_BATCH_TRANSFER_LEN
+		);
+		if let Some(approval_ids) = approval_ids.as_ref() {
+			assert_eq!(
+				approval_ids.len(),
+				token_ids.len(),
+				"There must be one approval ID per token"
+			);
+		}
+
+		// transferring and resolving cost GAS for every token on top of the receiver call
+		let gas_for_resolve = GAS_FOR_RESOLVE_TRANSFER * token_ids.len() as u64;
+		let gas_for_transfers = GAS_FOR_BATCH_TRANSFER_PER_TOKEN * token_ids.len() as u64;
+		assert!(
+			env::prepaid_gas() >= MIN_GAS_FOR_NFT_TRANSFER_CALL + gas_for_resolve + gas_for_transfers,
+			"You cannot attach less than {:?} Gas to nft_batch_transfer_call",
+			MIN_GAS_FOR_NFT_TRANSFER_CALL + gas_for_resolve + gas_for_transfers
+		);
+
+		let sender_id = env::predecessor_account_id();
+
+		// transfer every token and keep the previous state around in case the receiver rejects it
+		let previous_tokens: Vec<PreviousTokenState> = token_ids
+			.iter()
+			.enumerate()
+			.map(|(index, token_id)| {
+				let approval_id = approval_ids.as_ref().map(|approval_ids| approval_ids[index]);
+				let previous_token = self.internal_transfer(
+					&sender_id,
+					&receiver_id,
+					token_id,
+					approval_id,
+					memo.clone(),
+				);
+
+				let mut authorized_id = None;
+				if sender_id != previous_token.owner_id {
+					authorized_id = Some(sender_id.to_string());
+				}
+
+				PreviousTokenState {
+					authorized_id,
+					owner_id: previous_token.owner_id,
+					token_id: token_id.clone(),
+					approved_account_ids: previous_token.approved_account_ids,
+				}
+			})
+			.collect();
+
+		let previous_owner_ids = previous_tokens
+			.iter()
+			.map(|previous_token| previous_token.owner_id.clone())
+			.collect();
+
+		// Initiating receiver's call and the callback
+		ext_non_fungible_token_receiver::nft_on_batch_transfer(
+			sender_id,
+			previous_owner_ids,
+			token_ids,
+			msg,
+			receiver_id.clone(), // contract account to make the call to
+			NO_DEPOSIT,          // attached deposit
+			env::prepaid_gas() - GAS_FOR_NFT_TRANSFER_CALL - gas_for_resolve - gas_for_transfers, //attached GAS
+		)
+		.then(ext_self::nft_resolve_batch_transfer(
+			receiver_id,
+			previous_tokens,
+			memo,
+			env::current_account_id(), // contract account to make the call to
+			NO_DEPOSIT,                // attached deposit
+			gas_for_resolve,           // GAS attached to the call
+		))
+		.into()
+	}
+}
+
 #[near_bindgen]
 impl NonFungibleTokenResolver for Contract {
 	#[private]
@@ -186,25 +325,102 @@ impl NonFungibleTokenResolver for Contract {
 		memo: Option<String>,
 	) -> bool {
 		// Whether receiver wants to return token back to the sender, based on `nft_on_transfer` call result.
+		// If the call failed or returned something we can't parse, we return the token.
+		let mut return_token = true;
 		if let PromiseResult::Successful(value) = env::promise_result(0) {
 			// As per the standard, the nft_on_transfer should return whether we should return the token to it's owner or not
-			if let Ok(return_token) = near_sdk::serde_json::from_slice::<bool>(&value) {
-				// if we need don't need to return the token, we simply return true meaning everything went fine
-				if !return_token {
-					/*
-						since we've already transferred the token and nft_on_transfer returned false, we don't have to
-						revert the original transfer and thus we can just return true since nothing went wrong.
-					*/
-					// we refund the owner for releasing the storage used up by the approved account IDs
-					refund_approved_account_ids(owner_id, &approved_account_ids);
-					return true;
-				}
+			if let Ok(should_return) = near_sdk::serde_json::from_slice::<bool>(&value) {
+				return_token = should_return;
 			}
 		}
 
+		let is_transferred = self.internal_resolve_transfer(
+			&receiver_id,
+			PreviousTokenState {
+				authorized_id,
+				owner_id,
+				token_id: token_id.clone(),
+				approved_account_ids,
+			},
+			memo,
+			return_token,
+		);
+
+		// whether the token was returned or not, the transfer is final now
+		self.internal_trim_provenance(&token_id);
+
+		is_transferred
+	}
+
+	#[private]
+	fn nft_resolve_batch_transfer(
+		&mut self,
+		receiver_id: AccountId,
+		previous_tokens: Vec<PreviousTokenState>,
+		memo: Option<String>,
+	) -> Vec<bool> {
+		// the receiver tells us per token whether it should be returned. If the call failed, or the receiver
+		// didn't answer for every token, we return all of them.
+		let return_tokens = if let PromiseResult::Successful(value) = env::promise_result(0) {
+			near_sdk::serde_json::from_slice::<Vec<bool>>(&value)
+				.ok()
+				.filter(|return_tokens| return_tokens.len() == previous_tokens.len())
+		} else {
+			None
+		}
+		.unwrap_or_else(|| vec![true; previous_tokens.len()]);
+
+		previous_tokens
+			.into_iter()
+			.zip(return_tokens)
+			.map(|(previous_token, return_token)| {
+				let token_id = previous_token.token_id.clone();
+				let is_transferred =
+					self.internal_resolve_transfer(&receiver_id, previous_token, memo.clone(), return_token);
+
+				// whether the token was returned or not, the transfer is final now
+				self.internal_trim_provenance(&token_id);
+
+				is_transferred
+			})
+			.collect()
+	}
+}
+
+impl Contract {
+	/*
+		resolve a single token of a transfer call. If the receiver doesn't want to return the token, or it can't be
+		returned anymore, the transfer is final. Otherwise the token goes back to the original owner with its
+		approvals restored. Returns whether the token ended up with the receiver.
+	*/
+	pub(crate) fn internal_resolve_transfer(
+		&mut self,
+		receiver_id: &AccountId,
+		previous_token: PreviousTokenState,
+		memo: Option<String>,
+		return_token: bool,
+	) -> bool {
+		let PreviousTokenState {
+			authorized_id,
+			owner_id,
+			token_id,
+			approved_account_ids,
+		} = previous_token;
+
+		// if we need don't need to return the token, we simply return true meaning everything went fine
+		if !return_token {
+			/*
+				since we've already transferred the token and nft_on_transfer returned false, we don't have to
+				revert the original transfer and thus we can just return true since nothing went wrong.
+			*/
+			// we refund the owner for releasing the storage used up by the approved account IDs
+			refund_approved_account_ids(owner_id, &approved_account_ids);
+			return true;
+		}
+
 		// get the token object if there is some token object
 		let mut token = if let Some(tok) = self.tokens_by_id.get(&token_id) {
-			if tok.owner_id != receiver_id {
+			if &tok.owner_id != receiver_id {
 				// we refund the owner for releasing the storage used up by the approved account IDs
 				refund_approved_account_ids(owner_id, &approved_account_ids);
 				// The token is not owner by the receiver anymore. Can't return it.
@@ -218,15 +434,10 @@ impl NonFungibleTokenResolver for Contract {
 		};
 
 		// if at the end, we haven't returned true, that means that we should return the token to it's original owner
-		log!(
-			"Return {} from @{} to @{}",
-			token_id,
-			receiver_id.clone(),
-			owner_id
-		);
+		log!("Return {} from @{} to @{}", token_id, receiver_id, owner_id);
 
 		// we remove the token from the receiver
-		self.internal_remove_token_from_owner(&receiver_id.clone(), &token_id);
+		self.internal_remove_token_from_owner(receiver_id, &token_id);
 
 		//we add the token to the original owner
 		self.internal_add_token_to_owner(&owner_id, &token_id);
@@ -240,6 +451,9 @@ impl NonFungibleTokenResolver for Contract {
 		// Reset the approved account IDs to what they were before the transfer
 		token.approved_account_ids = approved_account_ids;
 
+		// the transfer never took effect, so it shouldn't stay in the provenance log
+		self.internal_revert_provenance(&token_id, &owner_id);
+
 		let nft_transfer_log: EventLog = EventLog {
 			standard: NFT_STANDARD_NAME.to_string(),
 			version: NFT_METADATA_SPEC.to_string(),
@@ -260,3 +474,138 @@ impl NonFungibleTokenResolver for Contract {
 		false
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use near_sdk::test_utils::{accounts, VMContextBuilder};
+	use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+	const ONE_NEAR: Balance = 1_000_000_000_000_000_000_000_000;
+
+	fn set_context(predecessor_id: AccountId, attached_deposit: Balance) {
+		testing_env!(VMContextBuilder::new()
+			.current_account_id(accounts(0))
+			.predecessor_account_id(predecessor_id)
+			.attached_deposit(attached_deposit)
+			.build());
+	}
+
+	// the callback context of nft_resolve_batch_transfer, with the result of nft_on_batch_transfer
+	fn set_resolve_context(promise_result: PromiseResult) {
+		testing_env!(
+			VMContextBuilder::new()
+				.current_account_id(accounts(0))
+				.predecessor_account_id(accounts(0))
+				.build(),
+			VMConfig::test(),
+			RuntimeFeesConfig::test(),
+			Default::default(),
+			vec![promise_result],
+		);
+	}
+
+	fn metadata() -> TokenMetadata {
+		TokenMetadata {
+			title: None,
+			description: None,
+			media: None,
+			media_hash: None,
+			copies: None,
+			issued_at: None,
+			expires_at: None,
+			starts_at: None,
+			updated_at: None,
+			extra: None,
+			reference: None,
+			reference_hash: None,
+		}
+	}
+
+	// mint tokens "1", "2" and "3" to bob and batch transfer them to charlie
+	fn setup() -> (Contract, Vec<PreviousTokenState>) {
+		set_context(accounts(0), 0);
+		let mut contract = Contract::new_default_meta(accounts(0));
+
+		let token_ids: Vec<TokenId> = vec!["1".to_string(), "2".to_string(), "3".to_string()];
+		for token_id in token_ids.iter() {
+			set_context(accounts(1), ONE_NEAR);
+			contract.nft_mint(token_id.clone(), metadata(), accounts(1), None, None, None);
+		}
+
+		set_context(accounts(1), 1);
+		contract.nft_batch_transfer_call(accounts(2), token_ids.clone(), None, None, "".to_string());
+		for token_id in token_ids.iter() {
+			assert_eq!(contract.nft_token(token_id.clone()).unwrap().owner_id, accounts(2));
+		}
+
+		let previous_tokens = token_ids
+			.into_iter()
+			.map(|token_id| PreviousTokenState {
+				authorized_id: None,
+				owner_id: accounts(1),
+				token_id,
+				approved_account_ids: HashMap::new(),
+			})
+			.collect();
+
+		(contract, previous_tokens)
+	}
+
+	fn owner_of(contract: &Contract, token_id: &str) -> AccountId {
+		contract.nft_token(token_id.to_string()).unwrap().owner_id
+	}
+
+	#[test]
+	fn receiver_keeps_only_the_tokens_it_accepts() {
+		let (mut contract, previous_tokens) = setup();
+
+		set_resolve_context(PromiseResult::Successful(b"[false,true,false]".to_vec()));
+		let is_transferred = contract.nft_resolve_batch_transfer(accounts(2), previous_tokens, None);
+
+		assert_eq!(is_transferred, vec![true, false, true]);
+		assert_eq!(owner_of(&contract, "1"), accounts(2));
+		assert_eq!(owner_of(&contract, "2"), accounts(1));
+		assert_eq!(owner_of(&contract, "3"), accounts(2));
+		assert_eq!(contract.nft_supply_for_owner(accounts(1)), U128(1));
+	}
+
+	#[test]
+	fn a_full_batch_fits_in_300_tgas() {
+		set_context(accounts(0), 0);
+		let mut contract = Contract::new_default_meta(accounts(0));
+
+		let token_ids: Vec<TokenId> = (0..MAX_BATCH_TRANSFER_LEN).map(|index| index.to_string()).collect();
+		for token_id in token_ids.iter() {
+			set_context(accounts(1), ONE_NEAR);
+			contract.nft_mint(token_id.clone(), metadata(), accounts(1), None, None, None);
+		}
+
+		// the context attaches 300 TGas, creating the receiver call and the callback panics if they don't fit
+		set_context(accounts(1), 1);
+		contract.nft_batch_transfer_call(accounts(2), token_ids, None, None, "".to_string());
+		assert_eq!(contract.nft_supply_for_owner(accounts(2)), U128(MAX_BATCH_TRANSFER_LEN as u128));
+	}
+
+	#[test]
+	fn every_token_is_returned_if_the_receiver_call_failed() {
+		let (mut contract, previous_tokens) = setup();
+
+		set_resolve_context(PromiseResult::Failed);
+		let is_transferred = contract.nft_resolve_batch_transfer(accounts(2), previous_tokens, None);
+
+		assert_eq!(is_transferred, vec![false, false, false]);
+		assert_eq!(contract.nft_supply_for_owner(accounts(1)), U128(3));
+	}
+
+	#[test]
+	fn every_token_is_returned_if_the_receiver_answered_for_the_wrong_number_of_tokens() {
+		let (mut contract, previous_tokens) = setup();
+
+		set_resolve_context(PromiseResult::Successful(b"[false,false]".to_vec()));
+		let is_transferred = contract.nft_resolve_batch_transfer(accounts(2), previous_tokens, None);
+
+		assert_eq!(is_transferred, vec![false, false, false]);
+		assert_eq!(contract.nft_supply_for_owner(accounts(2)), U128(0));
+	}
+}
@@ -79,6 +79,9 @@ impl RoyaltyNonFungibleTokenCore for Contract {
 			Some(memo),
 		);
 
+		// the transfer is final, so the provenance log can drop its oldest records
+		self.internal_trim_provenance(&token_id);
+
 		// refund the previous token owner for the storage used up by the previous approved account IDs
 		refund_approved_account_ids(
 			previous_token.owner_id.clone(),
@@ -0,0 +1,469 @@
+use crate::*;
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Bid {
+	pub bidder_id: AccountId,
+	pub amount: U128,
+	// block timestamp of the bid, in nanoseconds
+	pub placed_at: U64,
+}
+
+/*
+	English auction state. The reserve price is the sale's `sale_conditions`. The bid amounts are escrowed on the
+	market and the last bid in `bids` is always the highest one.
+*/
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EnglishAuction {
+	pub min_bid_increment: U128,
+	// when the auction ends, in nanoseconds. It moves forward when a bid comes in right before the end.
+	pub ends_at: U64,
+	// bids placed within this window before the end push the end back to `now + extension`
+	pub extension: U64,
+	// the most recent bids, oldest first. Bounded by MAX_BID_HISTORY_LEN
+	pub bids: Vec<Bid>,
+}
+
+impl EnglishAuction {
+	pub fn highest_bid(&self) -> Option<&Bid> {
+		self.bids.last()
+	}
+}
+
+/*
+	Dutch auction state. The price falls from `start_price` to the floor, which is the sale's `sale_conditions`,
+	between `starts_at` and `ends_at`. Without `steps` it falls linearly, otherwise in that many equal steps.
+*/
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct DutchAuction {
+	pub start_price: U128,
+	pub starts_at: U64,
+	pub ends_at: U64,
+	pub steps: Option<u64>,
+}
+
+impl DutchAuction {
+	// the price at the given block timestamp
+	pub fn price_at(&self, floor_price: u128, timestamp: u64) -> u128 {
+		let (starts_at, ends_at) = (self.starts_at.0, self.ends_at.0);
+		if timestamp <= starts_at {
+			return self.start_price.0;
+		}
+		if timestamp >= ends_at {
+			return floor_price;
+		}
+
+		let total_drop = self.start_price.0 - floor_price;
+		let duration = u128::from(ends_at - starts_at);
+		let mut elapsed = u128::from(timestamp - starts_at);
+
+		// with steps, the price only drops at the end of every step
+		if let Some(steps) = self.steps {
+			let step_duration = duration / u128::from(steps);
+			elapsed -= elapsed % step_duration;
+		}
+
+		// total_drop * elapsed / duration, split up so the multiplication can't overflow
+		let drop = total_drop / duration * elapsed + total_drop % duration * elapsed / duration;
+
+		self.start_price.0 - drop
+	}
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Auction {
+	English(EnglishAuction),
+	Dutch(DutchAuction),
+}
+
+// auction parameters passed in the `msg` of nft_approve as part of SaleArgs
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuctionArgs {
+	English {
+		min_bid_increment: U128,
+		ends_at: U64,
+		extension: Option<U64>,
+	},
+	Dutch {
+		start_price: U128,
+		starts_at: Option<U64>,
+		ends_at: U64,
+		steps: Option<u64>,
+	},
+}
+
+impl AuctionArgs {
+	// validate the arguments and create the initial auction state. The floor or reserve price is the sale_conditions.
+	pub(crate) fn into_auction(self, sale_conditions: TypeSalePriceInYoctoNear) -> Auction {
+		match self {
+			AuctionArgs::English {
+				min_bid_increment,
+				ends_at,
+				extension,
+			} => {
+				assert!(
+					ends_at.0 > env::block_timestamp(),
+					"Auction must end in the future"
+				);
+				assert!(min_bid_increment.0 > 0, "Minimum bid increment must be greater than 0");
+
+				Auction::English(EnglishAuction {
+					min_bid_increment,
+					ends_at,
+					extension: extension.unwrap_or(U64(CONST_AUCTION_EXTENSION)),
+					bids: vec![],
+				})
+			}
+			AuctionArgs::Dutch {
+				start_price,
+				starts_at,
+				ends_at,
+				steps,
+			} => {
+				let starts_at = starts_at.unwrap_or(U64(env::block_timestamp()));
+				assert!(
+					ends_at.0 > starts_at.0,
+					"Auction must end after it starts"
+				);
+				assert!(
+					start_price.0 > sale_conditions.0,
+					"Start price must be greater than the floor price"
+				);
+				if let Some(steps) = steps {
+					assert!(
+						steps > 0 && u128::from(steps) <= u128::from(ends_at.0 - starts_at.0),
+						"Invalid number of steps"
+					);
+				}
+
+				Auction::Dutch(DutchAuction {
+					start_price,
+					starts_at,
+					ends_at,
+					steps,
+				})
+			}
+		}
+	}
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		Place a bid on an English auction. The attached deposit is the bid and is held by the market. It must be
+		at least the reserve price, or the highest bid plus the minimum increment. The previous highest bidder is
+		refunded right away.
+	*/
+	#[payable]
+	pub fn place_bid(&mut self, nft_contract_id: AccountId, token_id: TypeTokenId) {
+		let amount = env::attached_deposit();
+		assert!(amount > 0, "Bid must be greater than 0");
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
+
+		let mut sale = self.sales.get(&sale_key).expect("No sale");
+
+		let bidder_id = env::predecessor_account_id();
+		assert_ne!(sale.owner_id, bidder_id, "Can not bid on your own sale.");
+		sale.assert_can_be_bought_by(&bidder_id);
+
+		let mut auction = match sale.auction {
+			Some(Auction::English(auction)) => auction,
+			_ => env::panic_str("Sale is not an English auction"),
+		};
+
+		let now = env::block_timestamp();
+		assert!(now < auction.ends_at.0, "Auction has ended");
+
+		// the bid needs to beat the reserve price, or the current highest bid by at least the increment
+		let min_amount = auction
+			.highest_bid()
+			.map(|bid| bid.amount.0 + auction.min_bid_increment.0)
+			.unwrap_or(sale.sale_conditions.0);
+		assert!(
+			amount >= min_amount,
+			"Bid must be at least {}",
+			min_amount
+		);
+
+		// refund the bidder we just outbid
+		if let Some(previous_bid) = auction.highest_bid() {
+			Promise::new(previous_bid.bidder_id.clone()).transfer(previous_bid.amount.0);
+		}
+
+		// anti-sniping: a bid right before the end extends the auction
+		if auction.ends_at.0 - now < auction.extension.0 {
+			auction.ends_at = U64(now + auction.extension.0);
+		}
+
+		if auction.bids.len() >= MAX_BID_HISTORY_LEN {
+			auction.bids.remove(0);
+		}
+		auction.bids.push(Bid {
+			bidder_id,
+			amount: U128(amount),
+			placed_at: U64(now),
+		});
+
+		sale.auction = Some(Auction::English(auction));
+		self.sales.insert(&sale_key, &sale);
+	}
+
+	/*
+		Settle an English auction once it has ended. Anyone can call this. The highest bid buys the token through
+		the usual nft_transfer_payout + resolve_purchase path. If there were no bids, the listing is removed.
+	*/
+	pub fn settle_auction(&mut self, nft_contract_id: AccountId, token_id: TypeTokenId) {
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
+		let sale = self.sales.get(&sale_key).expect("No sale");
+
+		let auction = match sale.auction {
+			Some(Auction::English(auction)) => auction,
+			_ => env::panic_str("Sale is not an English auction"),
+		};
+		assert!(
+			env::block_timestamp() >= auction.ends_at.0,
+			"Auction has not ended yet"
+		);
+
+		if let Some(highest_bid) = auction.highest_bid() {
+			self.process_purchase(
+				nft_contract_id,
+				token_id,
+				highest_bid.amount,
+				highest_bid.bidder_id.clone(),
+				None,
+			);
+		} else {
+			// nobody bid, so an escrowed token goes back to the seller
+			self.internal_take_down_sale(nft_contract_id, token_id);
+		}
+	}
+
+	/*
+		Returns what it currently costs to buy the token: the fixed price, the current Dutch auction price, or the
+		amount to beat for an English auction.
+	*/
+	pub fn get_current_price(&self, nft_contract_id: AccountId, token_id: TypeTokenId) -> Option<U128> {
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
+
+		self.sales.get(&sale_key).map(|sale| sale.current_price())
+	}
+
+	// Returns the current highest bid of an English auction
+	pub fn get_auction_highest_bid(&self, nft_contract_id: AccountId, token_id: TypeTokenId) -> Option<Bid> {
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
+
+		match self.sales.get(&sale_key)?.auction {
+			Some(Auction::English(auction)) => auction.highest_bid().cloned(),
+			_ => None,
+		}
+	}
+
+	// Returns paginated bids of an English auction, oldest first
+	pub fn get_auction_bids(
+		&self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		from_index: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<Bid> {
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
+
+		let auction = match self.sales.get(&sale_key).and_then(|sale| sale.auction) {
+			Some(Auction::English(auction)) => auction,
+			_ => return vec![],
+		};
+
+		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+		let start = u128::from(from_index.unwrap_or(U128(0)));
+
+		auction
+			.bids
+			.into_iter()
+			.skip(start as usize)
+			// the bid history is bounded, so without a limit we return everything that is left
+			.take(limit.unwrap_or(MAX_BID_HISTORY_LEN as u64) as usize)
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::accounts;
+
+	const ENDS_AT: u64 = 1_000_000;
+	const EXTENSION: u64 = 100;
+
+	// bob lists token "1" as an English auction with the given reserve price and an increment of 10
+	fn setup_english_auction(reserve_price: u128) -> Contract {
+		let mut contract = setup();
+		list(
+			&mut contract,
+			accounts(1),
+			"1",
+			&format!(
+				r#"{{"sale_conditions":"{}","auction":{{"type":"english","min_bid_increment":"10","ends_at":"{}","extension":"{}"}}}}"#,
+				reserve_price, ENDS_AT, EXTENSION
+			),
+		);
+
+		contract
+	}
+
+	fn bid(contract: &mut Contract, bidder_id: AccountId, amount: Balance, timestamp: u64) {
+		set_context_at(bidder_id, amount, timestamp);
+		contract.place_bid(nft_id(), "1".to_string());
+	}
+
+	fn ends_at(contract: &Contract) -> u64 {
+		match get_sale(contract, "1").unwrap().auction {
+			Some(Auction::English(auction)) => auction.ends_at.0,
+			_ => panic!("Sale is not an English auction"),
+		}
+	}
+
+	#[test]
+	fn a_higher_bid_refunds_the_previous_bidder() {
+		let mut contract = setup_english_auction(100);
+
+		bid(&mut contract, accounts(2), 100, 0);
+		bid(&mut contract, accounts(3), 110, 0);
+
+		assert_eq!(transfers(), vec![(accounts(2), 100)]);
+		let highest_bid = contract.get_auction_highest_bid(nft_id(), "1".to_string()).unwrap();
+		assert_eq!(highest_bid.bidder_id, accounts(3));
+		assert_eq!(highest_bid.amount, U128(110));
+		assert_eq!(contract.get_current_price(nft_id(), "1".to_string()), Some(U128(120)));
+	}
+
+	#[test]
+	#[should_panic(expected = "Bid must be at least 100")]
+	fn a_bid_below_the_reserve_price_is_rejected() {
+		let mut contract = setup_english_auction(100);
+
+		bid(&mut contract, accounts(2), 99, 0);
+	}
+
+	#[test]
+	#[should_panic(expected = "Bid must be at least 110")]
+	fn a_bid_below_the_increment_is_rejected() {
+		let mut contract = setup_english_auction(100);
+
+		bid(&mut contract, accounts(2), 100, 0);
+		bid(&mut contract, accounts(3), 109, 0);
+	}
+
+	#[test]
+	#[should_panic(expected = "Bid must be greater than 0")]
+	fn a_zero_bid_is_rejected_without_a_reserve_price() {
+		let mut contract = setup_english_auction(0);
+
+		bid(&mut contract, accounts(2), 0, 0);
+	}
+
+	#[test]
+	fn a_bid_right_before_the_end_extends_the_auction() {
+		let mut contract = setup_english_auction(100);
+
+		bid(&mut contract, accounts(2), 100, 0);
+		assert_eq!(ends_at(&contract), ENDS_AT);
+
+		bid(&mut contract, accounts(3), 110, ENDS_AT - 10);
+		assert_eq!(ends_at(&contract), ENDS_AT - 10 + EXTENSION);
+	}
+
+	#[test]
+	#[should_panic(expected = "Auction has ended")]
+	fn bids_after_the_end_are_rejected() {
+		let mut contract = setup_english_auction(100);
+
+		bid(&mut contract, accounts(2), 100, ENDS_AT);
+	}
+
+	#[test]
+	#[should_panic(expected = "Auction has not ended yet")]
+	fn an_auction_can_not_be_settled_before_it_ends() {
+		let mut contract = setup_english_auction(100);
+
+		bid(&mut contract, accounts(2), 100, 0);
+		set_context_at(accounts(3), 0, ENDS_AT - 1);
+		contract.settle_auction(nft_id(), "1".to_string());
+	}
+
+	#[test]
+	fn settling_sells_the_token_to_the_highest_bidder() {
+		let mut contract = setup_english_auction(100);
+
+		bid(&mut contract, accounts(2), 100, 0);
+		set_context_at(accounts(3), 0, ENDS_AT);
+		contract.settle_auction(nft_id(), "1".to_string());
+
+		assert!(get_sale(&contract, "1").is_none());
+		assert!(function_calls().contains(&(nft_id(), "nft_transfer_payout".to_string())));
+	}
+
+	#[test]
+	fn dutch_price_falls_linearly_or_in_steps() {
+		let linear = DutchAuction {
+			start_price: U128(1_000),
+			starts_at: U64(100),
+			ends_at: U64(200),
+			steps: None,
+		};
+		assert_eq!(linear.price_at(100, 0), 1_000);
+		assert_eq!(linear.price_at(100, 150), 550);
+		assert_eq!(linear.price_at(100, 199), 109);
+		assert_eq!(linear.price_at(100, 200), 100);
+		assert_eq!(linear.price_at(100, 1_000), 100);
+
+		let stepped = DutchAuction {
+			steps: Some(4),
+			..linear
+		};
+		assert_eq!(stepped.price_at(100, 124), 1_000);
+		assert_eq!(stepped.price_at(100, 125), 775);
+		assert_eq!(stepped.price_at(100, 199), 325);
+	}
+
+	#[test]
+	fn a_dutch_auction_is_bought_at_its_current_price() {
+		let mut contract = setup();
+		list(
+			&mut contract,
+			accounts(1),
+			"1",
+			r#"{"sale_conditions":"100","auction":{"type":"dutch","start_price":"1000","starts_at":"0","ends_at":"100"}}"#,
+		);
+
+		set_context_at(accounts(2), 1_000, 50);
+		assert_eq!(contract.get_current_price(nft_id(), "1".to_string()), Some(U128(550)));
+		contract.offer(nft_id(), "1".to_string(), None);
+
+		// the buyer gets back what they attached on top of the current price
+		assert_eq!(transfers(), vec![(accounts(2), 450)]);
+		assert!(get_sale(&contract, "1").is_none());
+	}
+
+	#[test]
+	#[should_panic(expected = "Attached deposit must be greater than or equal to the current price: 550")]
+	fn a_dutch_auction_can_not_be_bought_below_its_current_price() {
+		let mut contract = setup();
+		list(
+			&mut contract,
+			accounts(1),
+			"1",
+			r#"{"sale_conditions":"100","auction":{"type":"dutch","start_price":"1000","starts_at":"0","ends_at":"100"}}"#,
+		);
+
+		set_context_at(accounts(2), 549, 50);
+		contract.offer(nft_id(), "1".to_string(), None);
+	}
+}
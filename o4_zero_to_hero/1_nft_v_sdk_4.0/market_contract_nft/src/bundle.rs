@@ -0,0 +1,600 @@
+use crate::*;
+
+// one token of a bundle. The seller must have approved the market for it with `approval_id`
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructBundleItem {
+	pub nft_contract_id: AccountId,
+	pub token_id: TypeTokenId,
+	pub approval_id: u64,
+	// the part of the bundle price this token stands for, relative to the weights of the other tokens
+	pub weight: u32,
+}
+
+/*
+	Several tokens, possibly of different NFT contracts, sold together at one price in NEAR. The bundle can only be
+	bought while the seller still owns every token and the market still holds every approval.
+*/
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructBundle {
+	pub bundle_id: U64,
+	pub owner_id: AccountId,
+	pub items: Vec<StructBundleItem>,
+	pub price: U128,
+}
+
+impl StructBundle {
+	/*
+		split the price across the tokens by their weights. The royalties of every token are paid out of its share.
+		The last token gets what is left over from rounding down.
+	*/
+	pub fn item_prices(&self) -> Vec<U128> {
+		let total_weight: u128 = self.items.iter().map(|item| u128::from(item.weight)).sum();
+		let mut remainder = self.price.0;
+
+		self.items
+			.iter()
+			.enumerate()
+			.map(|(index, item)| {
+				let item_price = if index + 1 == self.items.len() {
+					remainder
+				} else {
+					self.price.0 * u128::from(item.weight) / total_weight
+				};
+				remainder -= item_price;
+				U128(item_price)
+			})
+			.collect()
+	}
+}
+
+// msg passed to nft_approve by the token owner to approve the market for a bundle: `{"bundle": true}`
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BundleApprovalArgs {
+	pub bundle: bool,
+}
+
+/*
+	The GAS every step of buying a bundle of `len` tokens needs: 10 TGas for its own work, plus the GAS it attaches to
+	every call it makes, CONST_GAS_FOR_RECEIPT for sending each of them and CONST_GAS_FOR_DATA_RECEIPT for every
+	result its callback waits on. MAX_BUNDLE_LEN is what fits in 300 TGas.
+*/
+pub(crate) const fn resolve_bundle_delivery_gas(len: usize) -> Gas {
+	// paying out a token, or returning it to the seller if the buyer didn't get it
+	let per_token = 5_000_000_000_000
+		+ CONST_GAS_FOR_NFT_TRANSFER.0
+		+ CONST_GAS_FOR_RESOLVE_RETURN_TOKEN.0
+		+ 2 * CONST_GAS_FOR_RECEIPT.0
+		+ CONST_GAS_FOR_DATA_RECEIPT.0;
+	Gas(10_000_000_000_000 + per_token * len as u64)
+}
+
+pub(crate) const fn resolve_bundle_escrow_gas(len: usize) -> Gas {
+	let per_token = CONST_GAS_FOR_NFT_TRANSFER.0 + CONST_GAS_FOR_RECEIPT.0 + CONST_GAS_FOR_DATA_RECEIPT.0;
+	Gas(10_000_000_000_000 + CONST_GAS_FOR_RECEIPT.0 + resolve_bundle_delivery_gas(len).0 + per_token * len as u64)
+}
+
+pub(crate) const fn buy_bundle_gas(len: usize) -> Gas {
+	let per_token = CONST_GAS_FOR_NFT_TRANSFER.0 + CONST_GAS_FOR_RECEIPT.0 + CONST_GAS_FOR_DATA_RECEIPT.0;
+	Gas(10_000_000_000_000 + CONST_GAS_FOR_RECEIPT.0 + resolve_bundle_escrow_gas(len).0 + per_token * len as u64)
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		List between 2 and MAX_BUNDLE_LEN tokens as one bundle at one price in NEAR. Approve the market for every token
+		first with the msg `{"bundle": true}`, so the token isn't listed on its own too. The bundle locks
+		CONST_STORAGE_PER_BUNDLE of the seller's storage balance. Every token's share of the price is checked against
+		the policy of its collection, and the listing fees of verified collections are charged to the storage balance
+		like for single sales. Returns the bundle ID.
+	*/
+	pub fn list_bundle(&mut self, items: Vec<StructBundleItem>, price: U128) -> U64 {
+		assert!(
+			items.len() >= 2 && items.len() <= MAX_BUNDLE_LEN,
+			"A bundle must have between 2 and {} tokens",
+			MAX_BUNDLE_LEN
+		);
+		assert!(price.0 > 0, "Price must be greater than 0");
+		for (index, item) in items.iter().enumerate() {
+			assert!(item.weight > 0, "Weights must be greater than 0");
+			assert!(
+				!items[..index]
+					.iter()
+					.any(|other| other.nft_contract_id == item.nft_contract_id && other.token_id == item.token_id),
+				"Token {} is in the bundle twice",
+				item.token_id
+			);
+		}
+
+		let owner_id = env::predecessor_account_id();
+
+		let bundle_id = self.next_bundle_id;
+		let bundle = StructBundle {
+			bundle_id: U64(bundle_id),
+			owner_id: owner_id.clone(),
+			items,
+			price,
+		};
+
+		// every token is held to the policy of its collection at its share of the price
+		let listing_fee: Balance = bundle
+			.items
+			.iter()
+			.zip(bundle.item_prices())
+			.map(|(item, item_price)| self.internal_assert_listing_policy(&item.nft_contract_id, item_price, None))
+			.sum();
+
+		let available = self
+			.storage_balance_of(owner_id.clone())
+			.map_or(0, |storage_balance| storage_balance.available.0);
+		assert!(
+			available >= CONST_STORAGE_PER_BUNDLE + listing_fee,
+			"Insufficient storage paid: {} available, a bundle needs {} plus a listing fee of {}",
+			available,
+			CONST_STORAGE_PER_BUNDLE,
+			listing_fee
+		);
+
+		if listing_fee > 0 {
+			let owner_paid_storage = self.storage_deposits.get(&owner_id).unwrap_or(0);
+			self.storage_deposits
+				.insert(&owner_id, &(owner_paid_storage - listing_fee));
+			self.internal_collect_fee(None, listing_fee);
+		}
+
+		self.next_bundle_id += 1;
+		self.bundles.insert(&bundle_id, &bundle);
+
+		let mut by_owner_id = self.bundles_by_owner_id.get(&owner_id).unwrap_or_else(|| {
+			UnorderedSet::new(
+				EnumStorageKey::BundlesByOwnerIdInner {
+					account_id_hash: hash_account_id(&owner_id),
+				}
+				.try_to_vec()
+				.unwrap(),
+			)
+		});
+		by_owner_id.insert(&bundle_id);
+		self.bundles_by_owner_id.insert(&owner_id, &by_owner_id);
+
+		let bundle_listed_log: EventLog = EventLog {
+			standard: CONST_MARKET_STANDARD_NAME.to_string(),
+			version: CONST_MARKET_EVENT_VERSION.to_string(),
+			event: EventLogVariant::BundleListed(vec![BundleListedLog {
+				bundle_id: bundle.bundle_id,
+				owner_id: owner_id.to_string(),
+				items: bundle.items,
+				price,
+			}]),
+		};
+
+		env::log_str(&bundle_listed_log.to_string());
+
+		U64(bundle_id)
+	}
+
+	// Removes a bundle from the market.
+	#[payable]
+	pub fn remove_bundle(&mut self, bundle_id: U64) {
+		assert_one_yocto();
+		let bundle = self.internal_remove_bundle(bundle_id.0);
+
+		assert_eq!(
+			env::predecessor_account_id(),
+			bundle.owner_id,
+			"Must be bundle owner"
+		);
+	}
+
+	/*
+		Buy a bundle. The attached deposit must cover the price, anything on top is refunded, and the call needs
+		buy_bundle_gas for the bundle's tokens. The market first pulls every token into escrow with nft_transfer_payout,
+		which fails for a token the seller doesn't own anymore or the market isn't approved for anymore, and then hands
+		them to the buyer. The seller is only paid for the tokens the buyer got, for everything else the buyer gets their
+		money back.
+	*/
+	#[payable]
+	pub fn buy_bundle(&mut self, bundle_id: U64) {
+		let deposit = env::attached_deposit();
+		let buyer_id = env::predecessor_account_id();
+
+		let bundle = self.bundles.get(&bundle_id.0).expect("No bundle");
+		// the buyer's money is only safe if every callback gets the GAS it needs
+		let required_gas = buy_bundle_gas(bundle.items.len());
+		assert!(
+			env::prepaid_gas() >= required_gas,
+			"Buying this bundle needs at least {} TGas",
+			required_gas.0 / 1_000_000_000_000
+		);
+
+		let bundle = self.internal_remove_bundle(bundle_id.0);
+		assert_ne!(buyer_id, bundle.owner_id, "Cannot bid on your own sale.");
+		assert!(
+			deposit >= bundle.price.0,
+			"Attached deposit must be greater than or equal to the current price: {:?}",
+			bundle.price.0
+		);
+
+		if deposit > bundle.price.0 {
+			Promise::new(buyer_id.clone()).transfer(deposit - bundle.price.0);
+		}
+
+		let (purchases, fees): (Vec<StructPurchase>, Vec<U128>) =
+			self.internal_bundle_purchases(&bundle, &buyer_id).into_iter().unzip();
+
+		// every token is pulled into escrow, its share of the price minus the protocol fee is split by its payout
+		let market_id = env::current_account_id();
+		let transfers = bundle
+			.items
+			.iter()
+			.zip(purchases.iter().zip(fees.iter()))
+			.map(|(item, (purchase, fee))| {
+				ext_contract::nft_transfer_payout(
+					market_id.clone(),
+					item.token_id.clone(),
+					item.approval_id,
+					"bundle sale on market".to_string(),
+					purchase.payout_balance(*fee),
+					purchase.max_len_payout(), // the maximum amount of accounts the market can payout at once (this is limited by GAS)
+					item.nft_contract_id.clone(),
+					1,
+					CONST_GAS_FOR_NFT_TRANSFER,
+				)
+			})
+			.reduce(|transfers, transfer| transfers.and(transfer))
+			.unwrap();
+
+		let resolve_gas = resolve_bundle_escrow_gas(bundle.items.len());
+		transfers.then(ext_self::resolve_bundle_escrow(
+			bundle,
+			purchases,
+			fees,
+			market_id,
+			CONST_NO_DEPOSIT,
+			resolve_gas,
+		));
+	}
+
+	/*
+		private callback for the nft_transfer_payout calls of buy_bundle. If every token made it to the market with a
+		valid payout, they are all handed to the buyer and resolve_bundle_delivery settles the sale. Otherwise the
+		tokens that did arrive go back to the seller and the buyer gets the whole price back.
+	*/
+	#[private]
+	pub fn resolve_bundle_escrow(&mut self, bundle: StructBundle, purchases: Vec<StructPurchase>, fees: Vec<U128>) {
+		let len = bundle.items.len();
+		let arrived: Vec<bool> = (0..len)
+			.map(|index| matches!(env::promise_result(index as u64), PromiseResult::Successful(_)))
+			.collect();
+
+		let payouts: Option<Vec<Payout>> = purchases
+			.iter()
+			.zip(fees.iter())
+			.enumerate()
+			.map(|(index, (purchase, fee))| match env::promise_result(index as u64) {
+				PromiseResult::Successful(value) => {
+					parse_payout(&value, purchase.payout_balance(*fee).0, purchase.max_len_payout()).map(|payout| Payout { payout })
+				}
+				_ => None,
+			})
+			.collect();
+
+		let payouts = if let Some(payouts) = payouts {
+			payouts
+		} else {
+			env::log_str(&format!("Bundle {} failed, the tokens are returned", bundle.bundle_id.0));
+			for (item, arrived) in bundle.items.iter().zip(arrived) {
+				if arrived {
+					self.internal_return_escrowed_token(
+						item.nft_contract_id.clone(),
+						item.token_id.clone(),
+						bundle.owner_id.clone(),
+					);
+				}
+			}
+			Promise::new(purchases[0].buyer_id.clone()).transfer(bundle.price.0);
+			return;
+		};
+
+		let deliveries = purchases
+			.iter()
+			.map(|purchase| {
+				ext_contract::nft_transfer(
+					purchase.buyer_id.clone(),
+					purchase.token_id.clone(),
+					None,
+					Some("payout from market".to_string()),
+					purchase.nft_contract_id.clone(),
+					1,
+					CONST_GAS_FOR_NFT_TRANSFER,
+				)
+			})
+			.reduce(|deliveries, delivery| deliveries.and(delivery))
+			.unwrap();
+
+		deliveries.then(ext_self::resolve_bundle_delivery(
+			purchases,
+			fees,
+			payouts,
+			env::current_account_id(),
+			CONST_NO_DEPOSIT,
+			resolve_bundle_delivery_gas(len),
+		));
+	}
+
+	/*
+		private callback for the transfers to the buyer of resolve_bundle_escrow. The seller and royalties are paid
+		for every token the buyer got, like resolve_purchase does. A token that couldn't be handed over goes back to
+		the seller and the buyer gets its share of the price back.
+	*/
+	#[private]
+	pub fn resolve_bundle_delivery(&mut self, purchases: Vec<StructPurchase>, fees: Vec<U128>, payouts: Vec<Payout>) {
+		let market_id = env::current_account_id();
+
+		for (index, ((purchase, fee), payout)) in purchases.into_iter().zip(fees).zip(payouts).enumerate() {
+			if !matches!(env::promise_result(index as u64), PromiseResult::Successful(_)) {
+				self.internal_return_escrowed_token(
+					purchase.nft_contract_id.clone(),
+					purchase.token_id.clone(),
+					purchase.seller_id.clone(),
+				);
+				self.internal_resolve_purchase(purchase, fee, None);
+				continue;
+			}
+
+			let mut payout = payout.payout;
+			// an NFT contract that splits the payout after the transfer names the market as the owner, that part goes to the seller
+			if let Some(amount) = payout.remove(&market_id) {
+				let seller_amount = payout.get(&purchase.seller_id).map_or(0, |seller_amount| seller_amount.0);
+				payout.insert(purchase.seller_id.clone(), U128(seller_amount + amount.0));
+			}
+
+			let payout_result = near_sdk::serde_json::to_vec(&Payout { payout }).ok();
+			self.internal_resolve_purchase(purchase, fee, payout_result);
+		}
+	}
+
+	pub fn get_bundle(&self, bundle_id: U64) -> Option<StructBundle> {
+		self.bundles.get(&bundle_id.0)
+	}
+
+	// Returns paginated bundles of an account
+	pub fn get_bundles_by_owner_id(
+		&self,
+		account_id: AccountId,
+		from_index: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<StructBundle> {
+		let bundle_ids = if let Some(bundle_ids) = self.bundles_by_owner_id.get(&account_id) {
+			bundle_ids
+		} else {
+			return vec![];
+		};
+
+		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+		let start = u128::from(from_index.unwrap_or(U128(0)));
+
+		bundle_ids
+			.as_vector()
+			.iter()
+			.skip(start as usize)
+			.take(limit.unwrap_or(CONST_DEFAULT_PAGE_LIMIT) as usize)
+			.map(|bundle_id| self.bundles.get(&bundle_id).unwrap())
+			.collect()
+	}
+}
+
+impl Contract {
+	// internal method for removing a bundle from the market. This returns the removed bundle
+	pub(crate) fn internal_remove_bundle(&mut self, bundle_id: u64) -> StructBundle {
+		let bundle = self.bundles.remove(&bundle_id).expect("No bundle");
+
+		let mut by_owner_id = self
+			.bundles_by_owner_id
+			.get(&bundle.owner_id)
+			.expect("No bundle by_owner_id");
+		by_owner_id.remove(&bundle_id);
+
+		if by_owner_id.is_empty() {
+			self.bundles_by_owner_id.remove(&bundle.owner_id);
+		} else {
+			self.bundles_by_owner_id.insert(&bundle.owner_id, &by_owner_id);
+		}
+
+		let bundle_removed_log: EventLog = EventLog {
+			standard: CONST_MARKET_STANDARD_NAME.to_string(),
+			version: CONST_MARKET_EVENT_VERSION.to_string(),
+			event: EventLogVariant::BundleRemoved(vec![BundleRemovedLog {
+				bundle_id: bundle.bundle_id,
+				owner_id: bundle.owner_id.to_string(),
+			}]),
+		};
+
+		env::log_str(&bundle_removed_log.to_string());
+
+		bundle
+	}
+
+	// every token of a bundle as a purchase at its share of the price, with the protocol fee on that share
+	pub(crate) fn internal_bundle_purchases(
+		&self,
+		bundle: &StructBundle,
+		buyer_id: &AccountId,
+	) -> Vec<(StructPurchase, U128)> {
+		bundle
+			.items
+			.iter()
+			.zip(bundle.item_prices())
+			.map(|(item, price)| {
+				let purchase = StructPurchase {
+					buyer_id: buyer_id.clone(),
+					seller_id: bundle.owner_id.clone(),
+					nft_contract_id: item.nft_contract_id.clone(),
+					token_id: item.token_id.clone(),
+					price,
+					ft_token_id: None,
+					referrer_id: None,
+					referral_bounty: U128(0),
+				};
+				let fee = self.internal_protocol_fee(price);
+				(purchase, fee)
+			})
+			.collect()
+	}
+
+	// the storage locked by the open bundles of an account
+	pub(crate) fn internal_bundle_storage_locked(&self, account_id: &AccountId) -> Balance {
+		self.bundles_by_owner_id
+			.get(account_id)
+			.map_or(0, |bundle_ids| u128::from(bundle_ids.len()) * CONST_STORAGE_PER_BUNDLE)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::{accounts, VMContextBuilder};
+	use near_sdk::testing_env;
+
+	fn bundle_items(weights: &[u32]) -> Vec<StructBundleItem> {
+		weights
+			.iter()
+			.enumerate()
+			.map(|(index, weight)| StructBundleItem {
+				nft_contract_id: nft_id(),
+				token_id: index.to_string(),
+				approval_id: 0,
+				weight: *weight,
+			})
+			.collect()
+	}
+
+	// bob approves the market for his tokens with the bundle msg and lists them as one bundle
+	fn setup_bundle(weights: &[u32], price: u128) -> (Contract, U64) {
+		let mut contract = setup();
+		let items = bundle_items(weights);
+		for item in items.iter() {
+			approve(&mut contract, accounts(1), &item.token_id, r#"{"bundle":true}"#);
+		}
+
+		set_context(accounts(1), CONST_STORAGE_PER_BUNDLE);
+		contract.storage_deposit(None, None);
+		let bundle_id = contract.list_bundle(items, U128(price));
+
+		(contract, bundle_id)
+	}
+
+	fn buy_bundle(contract: &mut Contract, bundle_id: U64, deposit: Balance, prepaid_gas: Gas) {
+		testing_env!(VMContextBuilder::new()
+			.current_account_id(market_id())
+			.signer_account_id(accounts(2))
+			.predecessor_account_id(accounts(2))
+			.attached_deposit(deposit)
+			.prepaid_gas(prepaid_gas)
+			.build());
+		contract.buy_bundle(bundle_id);
+	}
+
+	fn calls_named(function_name: &str) -> usize {
+		function_calls().into_iter().filter(|(_, name)| name == function_name).count()
+	}
+
+	#[test]
+	fn tokens_approved_for_a_bundle_are_not_listed_on_their_own() {
+		let (contract, bundle_id) = setup_bundle(&[1, 1], 100);
+
+		assert!(get_sale(&contract, "0").is_none());
+		assert_eq!(contract.get_bundle(bundle_id).unwrap().items.len(), 2);
+		assert_eq!(contract.storage_balance_of(accounts(1)).unwrap().available, U128(0));
+	}
+
+	#[test]
+	fn the_price_is_split_by_weight_and_the_last_token_gets_the_rest() {
+		let (contract, bundle_id) = setup_bundle(&[1, 2], 100);
+
+		assert_eq!(contract.get_bundle(bundle_id).unwrap().item_prices(), vec![U128(33), U128(67)]);
+	}
+
+	#[test]
+	fn the_largest_bundle_can_be_bought_with_300_tgas() {
+		let weights = vec![1; MAX_BUNDLE_LEN];
+		let (mut contract, bundle_id) = setup_bundle(&weights, 100);
+		assert!(buy_bundle_gas(MAX_BUNDLE_LEN).0 <= 300_000_000_000_000);
+
+		// creating the calls panics if they don't fit
+		buy_bundle(&mut contract, bundle_id, 150, Gas(300_000_000_000_000));
+
+		assert_eq!(calls_named("nft_transfer_payout"), MAX_BUNDLE_LEN);
+		assert_eq!(transfers(), vec![(accounts(2), 50)]);
+		assert!(contract.get_bundle(bundle_id).is_none());
+	}
+
+	#[test]
+	#[should_panic(expected = "Buying this bundle needs at least")]
+	fn buying_a_bundle_without_enough_gas_is_rejected() {
+		let (mut contract, bundle_id) = setup_bundle(&[1, 1], 100);
+
+		buy_bundle(&mut contract, bundle_id, 100, Gas(buy_bundle_gas(2).0 - 1));
+	}
+
+	#[test]
+	fn tokens_in_escrow_are_handed_to_the_buyer_with_the_gas_of_the_callback() {
+		let (mut contract, bundle_id) = setup_bundle(&[1, 1], 100);
+		let bundle = contract.get_bundle(bundle_id).unwrap();
+		let (purchases, fees) = contract.internal_bundle_purchases(&bundle, &accounts(2)).into_iter().unzip();
+
+		set_callback_context_with_gas(
+			resolve_bundle_escrow_gas(2),
+			vec![payout_result(&[(accounts(1), 50)]), payout_result(&[(accounts(1), 50)])],
+		);
+		contract.resolve_bundle_escrow(bundle, purchases, fees);
+
+		assert_eq!(calls_named("nft_transfer"), 2);
+		assert!(transfers().is_empty());
+	}
+
+	#[test]
+	fn a_token_that_did_not_arrive_returns_the_others_and_refunds_the_buyer() {
+		let (mut contract, bundle_id) = setup_bundle(&[1, 1], 100);
+		let bundle = contract.get_bundle(bundle_id).unwrap();
+		let (purchases, fees) = contract.internal_bundle_purchases(&bundle, &accounts(2)).into_iter().unzip();
+
+		set_callback_context(vec![payout_result(&[(accounts(1), 50)]), PromiseResult::Failed]);
+		contract.resolve_bundle_escrow(bundle, purchases, fees);
+
+		// the token that arrived goes back to the seller
+		assert_eq!(calls_named("nft_transfer"), 1);
+		assert_eq!(transfers(), vec![(accounts(2), 100)]);
+	}
+
+	#[test]
+	fn the_seller_is_only_paid_for_the_tokens_the_buyer_got() {
+		let (mut contract, bundle_id) = setup_bundle(&[1, 1], 100);
+		let bundle = contract.get_bundle(bundle_id).unwrap();
+		let (purchases, fees): (Vec<StructPurchase>, Vec<U128>) =
+			contract.internal_bundle_purchases(&bundle, &accounts(2)).into_iter().unzip();
+		let payout: Vec<(AccountId, u128)> = (0..MAX_PAYOUT_LEN)
+			.map(|index| (format!("royalty{}", index).parse().unwrap(), 5))
+			.collect();
+		let payouts = (0..2)
+			.map(|_| Payout {
+				payout: payout.iter().map(|(account_id, amount)| (account_id.clone(), U128(*amount))).collect(),
+			})
+			.collect();
+
+		set_callback_context_with_gas(
+			resolve_bundle_delivery_gas(2),
+			vec![PromiseResult::Successful(vec![]), PromiseResult::Failed],
+		);
+		contract.resolve_bundle_delivery(purchases, fees, payouts);
+
+		// the royalties of the delivered token are paid, the buyer gets the share of the other one back
+		let paid = transfers();
+		assert_eq!(paid.len(), MAX_PAYOUT_LEN as usize + 1);
+		assert!(paid.contains(&(accounts(2), 50)));
+		assert_eq!(calls_named("nft_transfer"), 1);
+	}
+}
@@ -0,0 +1,244 @@
+use crate::*;
+
+// one item of a cart purchase
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructCartItem {
+	pub nft_contract_id: AccountId,
+	pub token_id: TypeTokenId,
+	// the most the buyer is willing to pay for the item. The item is skipped if it currently costs more
+	pub max_price: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		Buy up to MAX_CART_LEN tokens with a single deposit. Every item is bought at its current price as long as that
+		is no more than its max_price. Items that can't be bought (not listed, priced in a fungible token, escrowed,
+		English auctions, your own sales, expired or reserved for someone else, or above max_price) are skipped and refunded, as is whatever the deposit has left.
+		Items are bought in order, and an item that costs more than what the deposit has left is skipped too.
+	*/
+	#[payable]
+	pub fn offer_many(&mut self, items: Vec<StructCartItem>) {
+		let deposit = env::attached_deposit();
+		assert!(!items.is_empty(), "Cart is empty");
+		assert!(
+			items.len() <= MAX_CART_LEN,
+			"Can not buy more than {} items at once",
+			MAX_CART_LEN
+		);
+
+		let buyer_id = env::predecessor_account_id();
+		let mut total: u128 = 0;
+		let mut purchases = vec![];
+		let mut fees = vec![];
+		let mut transfers: Option<Promise> = None;
+
+		for item in items {
+			let sale_key = StructSaleKey::new(&item.nft_contract_id, &item.token_id);
+			let sale = if let Some(sale) = self.sales.get(&sale_key) {
+				sale
+			} else {
+				env::log_str(&format!("Skipping {}: no sale", item.token_id));
+				continue;
+			};
+
+			if sale.owner_id == buyer_id
+				|| sale.ft_token_id.is_some()
+				|| sale.escrowed
+				|| !sale.can_be_bought_by(&buyer_id)
+				|| matches!(sale.auction, Some(Auction::English(_)))
+			{
+				env::log_str(&format!("Skipping {}: can not be bought with offer_many", item.token_id));
+				continue;
+			}
+
+			let price = sale.current_price();
+			if price.0 > item.max_price.0 {
+				env::log_str(&format!(
+					"Skipping {}: price {} is above the max price",
+					item.token_id, price.0
+				));
+				continue;
+			}
+
+			if total + price.0 > deposit {
+				env::log_str(&format!(
+					"Skipping {}: price {} is above what the deposit has left",
+					item.token_id, price.0
+				));
+				continue;
+			}
+			total += price.0;
+
+			let sale = self.internal_remove_sale(item.nft_contract_id.clone(), item.token_id.clone());
+			let purchase = StructPurchase {
+				buyer_id: buyer_id.clone(),
+				seller_id: sale.owner_id,
+				nft_contract_id: item.nft_contract_id,
+				token_id: item.token_id,
+				price,
+				ft_token_id: None,
+				referrer_id: None,
+				referral_bounty: U128(0),
+			};
+			let fee = self.internal_protocol_fee(price);
+
+			let transfer = self.internal_nft_transfer_payout(&purchase, sale.approval_id, fee);
+			transfers = Some(match transfers {
+				Some(transfers) => transfers.and(transfer),
+				None => transfer,
+			});
+			purchases.push(purchase);
+			fees.push(fee);
+		}
+
+		// refund the skipped items and whatever the deposit has left
+		if deposit > total {
+			Promise::new(buyer_id).transfer(deposit - total);
+		}
+
+		if let Some(transfers) = transfers {
+			let resolve_gas = Gas(CONST_GAS_FOR_RESOLVE_CART_ITEM.0 * purchases.len() as u64);
+			transfers.then(ext_self::resolve_cart_purchase(
+				purchases,
+				fees,
+				env::current_account_id(),
+				CONST_NO_DEPOSIT,
+				resolve_gas,
+			));
+		}
+	}
+
+	/*
+		private method used to resolve the nft_transfer_payout calls of offer_many. Every item is settled like
+		resolve_purchase: the accounts in its payout are paid, or the buyer is refunded its price.
+	*/
+	#[private]
+	pub fn resolve_cart_purchase(&mut self, purchases: Vec<StructPurchase>, fees: Vec<U128>) -> Vec<U128> {
+		purchases
+			.into_iter()
+			.zip(fees)
+			.enumerate()
+			.map(|(index, (purchase, fee))| {
+				let payout_result = match env::promise_result(index as u64) {
+					PromiseResult::Successful(value) => Some(value),
+					_ => None,
+				};
+
+				self.internal_resolve_purchase(purchase, fee, payout_result)
+			})
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::accounts;
+
+	fn item(token_id: &str, max_price: u128) -> StructCartItem {
+		StructCartItem {
+			nft_contract_id: nft_id(),
+			token_id: token_id.to_string(),
+			max_price: U128(max_price),
+		}
+	}
+
+	// how many nft_transfer_payout calls the last call made
+	fn transfer_payouts() -> usize {
+		function_calls()
+			.into_iter()
+			.filter(|(_, function_name)| function_name == "nft_transfer_payout")
+			.count()
+	}
+
+	#[test]
+	fn the_items_are_bought_at_their_price_and_the_rest_is_refunded() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+		list(&mut contract, accounts(1), "2", r#"{"sale_conditions":"200"}"#);
+
+		set_context(accounts(2), 350);
+		contract.offer_many(vec![item("1", 100), item("2", 200)]);
+
+		assert_eq!(transfer_payouts(), 2);
+		assert_eq!(transfers(), vec![(accounts(2), 50)]);
+		assert!(get_sale(&contract, "1").is_none());
+		assert!(get_sale(&contract, "2").is_none());
+	}
+
+	#[test]
+	fn items_that_can_not_be_bought_are_skipped() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+		list(&mut contract, accounts(1), "2", r#"{"sale_conditions":"200"}"#);
+		list(&mut contract, accounts(2), "3", r#"{"sale_conditions":"100"}"#);
+		list(&mut contract, accounts(1), "4", r#"{"sale_conditions":"50"}"#);
+
+		// "1" is above its max price, "missing" isn't listed, "3" is the buyer's own sale, "2" is above what is left
+		set_context(accounts(2), 150);
+		contract.offer_many(vec![item("1", 90), item("missing", 100), item("3", 100), item("2", 200), item("4", 50)]);
+
+		assert_eq!(transfer_payouts(), 1);
+		assert_eq!(transfers(), vec![(accounts(2), 100)]);
+		assert!(get_sale(&contract, "1").is_some());
+		assert!(get_sale(&contract, "2").is_some());
+		assert!(get_sale(&contract, "3").is_some());
+		assert!(get_sale(&contract, "4").is_none());
+	}
+
+	#[test]
+	fn every_item_is_settled_on_its_own() {
+		let mut contract = setup();
+
+		set_callback_context(vec![payout_result(&[(accounts(1), 100)]), PromiseResult::Failed]);
+		let prices = contract.resolve_cart_purchase(
+			vec![purchase(accounts(2), accounts(1), "1", 100), purchase(accounts(2), accounts(1), "2", 200)],
+			vec![U128(0), U128(0)],
+		);
+
+		assert_eq!(prices, vec![U128(100), U128(200)]);
+		assert_eq!(transfers(), vec![(accounts(1), 100), (accounts(2), 200)]);
+	}
+
+	#[test]
+	fn a_full_cart_fits_in_300_tgas() {
+		let mut contract = setup();
+		let items: Vec<StructCartItem> = (0..MAX_CART_LEN)
+			.map(|index| {
+				list(&mut contract, accounts(1), &index.to_string(), r#"{"sale_conditions":"100"}"#);
+				item(&index.to_string(), 100)
+			})
+			.collect();
+
+		// creating the calls panics if they don't fit
+		set_context(accounts(2), 100 * MAX_CART_LEN as u128);
+		contract.offer_many(items);
+
+		assert_eq!(transfer_payouts(), MAX_CART_LEN);
+	}
+
+	#[test]
+	fn a_full_cart_with_the_longest_payouts_fits_in_the_gas_of_resolve_cart_purchase() {
+		let mut contract = setup();
+		let payout: Vec<(AccountId, u128)> = (0..MAX_PAYOUT_LEN)
+			.map(|index| (format!("royalty{}", index).parse().unwrap(), 10))
+			.collect();
+		let price = 10 * payout.len() as u128;
+
+		set_callback_context_with_gas(
+			Gas(CONST_GAS_FOR_RESOLVE_CART_ITEM.0 * MAX_CART_LEN as u64),
+			(0..MAX_CART_LEN).map(|_| payout_result(&payout)).collect(),
+		);
+		contract.resolve_cart_purchase(
+			(0..MAX_CART_LEN)
+				.map(|index| purchase(accounts(2), accounts(1), &index.to_string(), price))
+				.collect(),
+			vec![U128(0); MAX_CART_LEN],
+		);
+
+		assert_eq!(transfers().len(), MAX_CART_LEN * MAX_PAYOUT_LEN as usize);
+	}
+}
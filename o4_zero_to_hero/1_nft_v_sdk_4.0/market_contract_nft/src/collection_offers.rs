@@ -0,0 +1,552 @@
+use crate::*;
+
+/*
+	A bid on any token of an NFT contract, optionally only tokens of a given token type (series). The buyer escrows
+	`amount * quantity` plus CONST_STORAGE_PER_OFFER. Every fill buys one token and lowers the quantity by one.
+*/
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructCollectionOffer {
+	pub offer_id: U64,
+	pub buyer_id: AccountId,
+	pub nft_contract_id: AccountId,
+	// only tokens of this token type can fill the offer. None means any token of the contract
+	pub token_type: Option<String>,
+	// the price paid per token
+	pub amount: U128,
+	// how many more tokens the buyer wants
+	pub quantity: u32,
+	// when the offer stops being valid, in nanoseconds. None means it never expires
+	pub expires_at: Option<U64>,
+}
+
+impl StructCollectionOffer {
+	pub fn is_expired(&self) -> bool {
+		self.expires_at
+			.map(|expires_at| env::block_timestamp() >= expires_at.0)
+			.unwrap_or(false)
+	}
+}
+
+// msg passed to nft_approve by a holder to sell the token into a collection offer
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FillCollectionOfferArgs {
+	pub fill_collection_offer: FillCollectionOffer,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FillCollectionOffer {
+	pub offer_id: U64,
+	// the price the holder expects, so the buyer can't lower it while the approval is in flight
+	pub amount: U128,
+}
+
+// everything needed to finish a fill once the NFT contract told us the token type
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionOfferFill {
+	pub offer_id: U64,
+	pub buyer_id: AccountId,
+	pub seller_id: AccountId,
+	pub nft_contract_id: AccountId,
+	pub token_id: TypeTokenId,
+	pub approval_id: u64,
+	pub amount: U128,
+	pub token_type: String,
+}
+
+// the part of the NFT contract's JsonToken we need to check the token type
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct JsonTokenType {
+	pub token_type: Option<String>,
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		Place an escrowed bid on `quantity` tokens of an NFT contract. The attached deposit must cover
+		`amount * quantity` plus the storage for the offer. Returns the offer ID holders use to fill it.
+	*/
+	#[payable]
+	pub fn place_collection_offer(
+		&mut self,
+		nft_contract_id: AccountId,
+		amount: U128,
+		quantity: u32,
+		token_type: Option<String>,
+		expires_at: Option<U64>,
+	) -> U64 {
+		let deposit = env::attached_deposit();
+		assert!(amount.0 > 0, "Offer amount must be greater than 0");
+		assert!(quantity > 0, "Quantity must be greater than 0");
+
+		let escrow = amount.0 * u128::from(quantity);
+		assert!(
+			deposit >= escrow + CONST_STORAGE_PER_OFFER,
+			"Attached deposit must cover amount * quantity plus {} for storage",
+			CONST_STORAGE_PER_OFFER
+		);
+		if let Some(expires_at) = expires_at {
+			assert!(
+				expires_at.0 > env::block_timestamp(),
+				"Offer must expire in the future"
+			);
+		}
+
+		let buyer_id = env::predecessor_account_id();
+
+		// refund anything attached on top of the escrow and storage
+		let refund = deposit - escrow - CONST_STORAGE_PER_OFFER;
+		if refund > 0 {
+			Promise::new(buyer_id.clone()).transfer(refund);
+		}
+
+		let offer_id = self.next_collection_offer_id;
+		self.next_collection_offer_id += 1;
+
+		self.collection_offers.insert(
+			&offer_id,
+			&StructCollectionOffer {
+				offer_id: U64(offer_id),
+				buyer_id,
+				nft_contract_id: nft_contract_id.clone(),
+				token_type: token_type.clone(),
+				amount,
+				quantity,
+				expires_at,
+			},
+		);
+
+		let mut by_nft_contract_id = self
+			.collection_offers_by_nft_contract_id
+			.get(&nft_contract_id)
+			.unwrap_or_else(|| {
+				UnorderedSet::new(
+					EnumStorageKey::CollectionOffersByNFTContractIdInner {
+						account_id_hash: hash_account_id(&nft_contract_id),
+					}
+					.try_to_vec()
+					.unwrap(),
+				)
+			});
+		by_nft_contract_id.insert(&offer_id);
+		self.collection_offers_by_nft_contract_id
+			.insert(&nft_contract_id, &by_nft_contract_id);
+
+		let type_key = StructCollectionOfferTypeKey::new(&nft_contract_id, token_type.as_ref());
+		let mut by_price = self.collection_offers_by_price.get(&type_key).unwrap_or_else(|| {
+			TreeMap::new(
+				EnumStorageKey::CollectionOffersByPriceInner {
+					type_key_hash: hash_collection_offer_type_key(&type_key),
+				}
+				.try_to_vec()
+				.unwrap(),
+			)
+		});
+		by_price.insert(&(amount.0, offer_id), &());
+		self.collection_offers_by_price.insert(&type_key, &by_price);
+
+		U64(offer_id)
+	}
+
+	/*
+		Cancel a collection offer and get the remaining escrow back. The buyer can cancel at any time, anyone else can
+		only clean up offers that have expired. The escrow always goes back to the buyer.
+	*/
+	#[payable]
+	pub fn cancel_collection_offer(&mut self, offer_id: U64) {
+		assert_one_yocto();
+
+		let offer = self.internal_remove_collection_offer(offer_id.0);
+
+		assert!(
+			env::predecessor_account_id() == offer.buyer_id || offer.is_expired(),
+			"Only the buyer can cancel an offer that hasn't expired"
+		);
+
+		Promise::new(offer.buyer_id)
+			.transfer(offer.amount.0 * u128::from(offer.quantity) + CONST_STORAGE_PER_OFFER);
+	}
+
+	/*
+		private callback used when the collection offer only accepts one token type. If the NFT contract says the
+		token has that type, the token is bought. Otherwise the reserved unit goes back to the offer, or to the buyer
+		if the offer is gone.
+	*/
+	#[private]
+	pub fn resolve_collection_offer_fill(&mut self, fill: CollectionOfferFill) -> PromiseOrValue<U128> {
+		let token_type = promise_result_as_success()
+			.and_then(|value| {
+				near_sdk::serde_json::from_slice::<Option<JsonTokenType>>(&value)
+					.ok()
+					.flatten()
+			})
+			.and_then(|token| token.token_type);
+
+		if token_type.as_ref() == Some(&fill.token_type) {
+			return PromiseOrValue::Promise(self.internal_transfer_payout(
+				StructPurchase {
+					buyer_id: fill.buyer_id,
+					seller_id: fill.seller_id,
+					nft_contract_id: fill.nft_contract_id,
+					token_id: fill.token_id,
+					price: fill.amount,
+					ft_token_id: None,
+					referrer_id: None,
+					referral_bounty: U128(0),
+				},
+				fill.approval_id,
+			));
+		}
+
+		env::log_str("Token type doesn't match the collection offer");
+		if let Some(mut offer) = self.collection_offers.get(&fill.offer_id.0) {
+			offer.quantity += 1;
+			self.collection_offers.insert(&fill.offer_id.0, &offer);
+		} else {
+			Promise::new(fill.buyer_id).transfer(fill.amount.0);
+		}
+
+		PromiseOrValue::Value(U128(0))
+	}
+
+	pub fn get_collection_offer(&self, offer_id: U64) -> Option<StructCollectionOffer> {
+		self.collection_offers.get(&offer_id.0)
+	}
+
+	pub fn get_supply_collection_offers(&self, nft_contract_id: AccountId) -> U64 {
+		let by_nft_contract_id = self.collection_offers_by_nft_contract_id.get(&nft_contract_id);
+
+		if let Some(by_nft_contract_id) = by_nft_contract_id {
+			U64(by_nft_contract_id.len())
+		} else {
+			U64(0)
+		}
+	}
+
+	// Returns paginated collection offers for an NFT contract
+	pub fn get_collection_offers(
+		&self,
+		nft_contract_id: AccountId,
+		from_index: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<StructCollectionOffer> {
+		let offer_ids = if let Some(offer_ids) = self.collection_offers_by_nft_contract_id.get(&nft_contract_id) {
+			offer_ids
+		} else {
+			return vec![];
+		};
+
+		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+		let start = u128::from(from_index.unwrap_or(U128(0)));
+
+		offer_ids
+			.as_vector()
+			.iter()
+			.skip(start as usize)
+			.take(limit.unwrap_or(CONST_DEFAULT_PAGE_LIMIT) as usize)
+			.map(|offer_id| self.collection_offers.get(&offer_id).unwrap())
+			.collect()
+	}
+
+	/*
+		Returns the highest collection offer a token can currently be sold into. Without a token type, only offers
+		that accept any token of the contract are considered. The best offers that accept any token and that accept
+		the token type are both read from the top of their price index. Expired offers on top are skipped until
+		someone cancels them.
+	*/
+	pub fn get_best_collection_offer(
+		&self,
+		nft_contract_id: AccountId,
+		token_type: Option<String>,
+	) -> Option<StructCollectionOffer> {
+		let best_for_any_type = self.internal_best_collection_offer(&nft_contract_id, None);
+		let best_for_token_type = token_type
+			.as_ref()
+			.and_then(|token_type| self.internal_best_collection_offer(&nft_contract_id, Some(token_type)));
+
+		match (best_for_any_type, best_for_token_type) {
+			(Some(any), Some(typed)) => Some(if typed.amount.0 > any.amount.0 { typed } else { any }),
+			(any, typed) => any.or(typed),
+		}
+	}
+}
+
+impl Contract {
+	// remove a collection offer and its index entry. The caller takes care of the escrow.
+	pub(crate) fn internal_remove_collection_offer(&mut self, offer_id: u64) -> StructCollectionOffer {
+		let offer = self.collection_offers.remove(&offer_id).expect("No collection offer");
+
+		let type_key = StructCollectionOfferTypeKey::new(&offer.nft_contract_id, offer.token_type.as_ref());
+		if let Some(mut by_price) = self.collection_offers_by_price.get(&type_key) {
+			by_price.remove(&(offer.amount.0, offer_id));
+			if by_price.is_empty() {
+				self.collection_offers_by_price.remove(&type_key);
+			} else {
+				self.collection_offers_by_price.insert(&type_key, &by_price);
+			}
+		}
+
+		let mut by_nft_contract_id = self
+			.collection_offers_by_nft_contract_id
+			.get(&offer.nft_contract_id)
+			.expect("No collection offers by nft_contract_id");
+		by_nft_contract_id.remove(&offer_id);
+		if by_nft_contract_id.is_empty() {
+			self.collection_offers_by_nft_contract_id
+				.remove(&offer.nft_contract_id);
+		} else {
+			self.collection_offers_by_nft_contract_id
+				.insert(&offer.nft_contract_id, &by_nft_contract_id);
+		}
+
+		offer
+	}
+
+	// the highest offer in the price index of a contract and token type that hasn't expired
+	pub(crate) fn internal_best_collection_offer(
+		&self,
+		nft_contract_id: &AccountId,
+		token_type: Option<&String>,
+	) -> Option<StructCollectionOffer> {
+		let by_price = self
+			.collection_offers_by_price
+			.get(&StructCollectionOfferTypeKey::new(nft_contract_id, token_type))?;
+
+		let best_offer = by_price
+			.iter_rev()
+			.map(|((_, offer_id), _)| self.collection_offers.get(&offer_id).unwrap())
+			.find(|offer| !offer.is_expired());
+
+		best_offer
+	}
+
+	/*
+		called from nft_on_approve when a holder approves the market with a fill_collection_offer msg. One unit of the
+		offer is reserved for the token right away so the offer can't be overfilled.
+	*/
+	pub(crate) fn internal_fill_collection_offer(
+		&mut self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		owner_id: AccountId,
+		approval_id: u64,
+		fill: FillCollectionOffer,
+	) -> Promise {
+		let mut offer = self
+			.collection_offers
+			.get(&fill.offer_id.0)
+			.expect("No collection offer");
+
+		assert_eq!(
+			offer.nft_contract_id, nft_contract_id,
+			"Collection offer is for another NFT contract"
+		);
+		assert!(!offer.is_expired(), "Collection offer has expired");
+		assert_eq!(
+			offer.amount, fill.amount,
+			"Offer amount doesn't match the accepted amount"
+		);
+		assert_ne!(offer.buyer_id, owner_id, "Can not fill your own offer");
+
+		// reserve one unit. Once all are taken, the offer goes away and the buyer gets the storage back
+		offer.quantity -= 1;
+		if offer.quantity == 0 {
+			self.internal_remove_collection_offer(fill.offer_id.0);
+			Promise::new(offer.buyer_id.clone()).transfer(CONST_STORAGE_PER_OFFER);
+		} else {
+			self.collection_offers.insert(&fill.offer_id.0, &offer);
+		}
+
+		// like for standing offers, a listing of the token is taken down in resolve_purchase once the token changed hands
+		self.internal_assert_no_auction_bids(&nft_contract_id, &token_id);
+
+		if let Some(token_type) = offer.token_type {
+			// ask the NFT contract for the token type before buying
+			ext_contract::nft_token(
+				token_id.clone(),
+				nft_contract_id.clone(),
+				CONST_NO_DEPOSIT,
+				CONST_GAS_FOR_NFT_TOKEN,
+			)
+			.then(ext_self::resolve_collection_offer_fill(
+				CollectionOfferFill {
+					offer_id: fill.offer_id,
+					buyer_id: offer.buyer_id,
+					seller_id: owner_id,
+					nft_contract_id,
+					token_id,
+					approval_id,
+					amount: offer.amount,
+					token_type,
+				},
+				env::current_account_id(),
+				CONST_NO_DEPOSIT,
+				CONST_GAS_FOR_RESOLVE_COLLECTION_OFFER_FILL,
+			))
+		} else {
+			self.internal_transfer_payout(
+				StructPurchase {
+					buyer_id: offer.buyer_id,
+					seller_id: owner_id,
+					nft_contract_id,
+					token_id,
+					price: offer.amount,
+					ft_token_id: None,
+					referrer_id: None,
+					referral_bounty: U128(0),
+				},
+				approval_id,
+			)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::accounts;
+
+	fn place_collection_offer(
+		contract: &mut Contract,
+		buyer_id: AccountId,
+		amount: u128,
+		quantity: u32,
+		token_type: Option<&str>,
+		expires_at: Option<U64>,
+	) -> U64 {
+		set_context(buyer_id, amount * u128::from(quantity) + CONST_STORAGE_PER_OFFER);
+		contract.place_collection_offer(
+			nft_id(),
+			U128(amount),
+			quantity,
+			token_type.map(|token_type| token_type.to_string()),
+			expires_at,
+		)
+	}
+
+	fn best_amount(contract: &Contract, token_type: Option<&str>) -> Option<u128> {
+		contract
+			.get_best_collection_offer(nft_id(), token_type.map(|token_type| token_type.to_string()))
+			.map(|offer| offer.amount.0)
+	}
+
+	fn fill_msg(offer_id: U64, amount: u128) -> String {
+		format!(
+			r#"{{"fill_collection_offer":{{"offer_id":"{}","amount":"{}"}}}}"#,
+			offer_id.0, amount
+		)
+	}
+
+	#[test]
+	fn the_best_offer_accepts_the_token_type_and_has_not_expired() {
+		let mut contract = setup();
+		place_collection_offer(&mut contract, accounts(2), 100, 1, None, None);
+		place_collection_offer(&mut contract, accounts(2), 150, 1, Some("gold"), None);
+		place_collection_offer(&mut contract, accounts(3), 200, 1, Some("silver"), None);
+		place_collection_offer(&mut contract, accounts(3), 120, 1, None, Some(U64(1_000)));
+
+		assert_eq!(best_amount(&contract, None), Some(120));
+		assert_eq!(best_amount(&contract, Some("gold")), Some(150));
+		assert_eq!(best_amount(&contract, Some("bronze")), Some(120));
+
+		set_context_at(accounts(2), 0, 1_000);
+		assert_eq!(best_amount(&contract, None), Some(100));
+		assert_eq!(best_amount(&contract, Some("silver")), Some(200));
+	}
+
+	#[test]
+	fn a_cancelled_offer_leaves_the_price_index() {
+		let mut contract = setup();
+		place_collection_offer(&mut contract, accounts(2), 100, 1, None, None);
+		let offer_id = place_collection_offer(&mut contract, accounts(3), 200, 2, None, None);
+
+		set_context(accounts(3), 1);
+		contract.cancel_collection_offer(offer_id);
+
+		assert_eq!(transfers(), vec![(accounts(3), 400 + CONST_STORAGE_PER_OFFER)]);
+		assert_eq!(best_amount(&contract, None), Some(100));
+		assert_eq!(contract.get_supply_collection_offers(nft_id()), U64(1));
+	}
+
+	#[test]
+	fn every_fill_takes_one_unit_until_the_offer_is_used_up() {
+		let mut contract = setup();
+		let offer_id = place_collection_offer(&mut contract, accounts(2), 100, 2, None, None);
+
+		approve(&mut contract, accounts(1), "1", &fill_msg(offer_id, 100));
+		assert!(function_calls().contains(&(nft_id(), "nft_transfer_payout".to_string())));
+		assert_eq!(contract.get_collection_offer(offer_id).unwrap().quantity, 1);
+
+		approve(&mut contract, accounts(3), "2", &fill_msg(offer_id, 100));
+		assert!(contract.get_collection_offer(offer_id).is_none());
+		assert!(transfers().contains(&(accounts(2), CONST_STORAGE_PER_OFFER)));
+		assert_eq!(best_amount(&contract, None), None);
+	}
+
+	#[test]
+	fn a_token_of_another_type_gives_the_unit_back_to_the_offer() {
+		let mut contract = setup();
+		let offer_id = place_collection_offer(&mut contract, accounts(2), 100, 2, Some("gold"), None);
+		approve(&mut contract, accounts(1), "1", &fill_msg(offer_id, 100));
+		assert_eq!(contract.get_collection_offer(offer_id).unwrap().quantity, 1);
+
+		set_callback_context(vec![json_result(&JsonTokenType {
+			token_type: Some("silver".to_string()),
+		})]);
+		contract.resolve_collection_offer_fill(CollectionOfferFill {
+			offer_id,
+			buyer_id: accounts(2),
+			seller_id: accounts(1),
+			nft_contract_id: nft_id(),
+			token_id: "1".to_string(),
+			approval_id: 0,
+			amount: U128(100),
+			token_type: "gold".to_string(),
+		});
+
+		assert_eq!(contract.get_collection_offer(offer_id).unwrap().quantity, 2);
+		assert!(function_calls().is_empty());
+	}
+
+	#[test]
+	fn a_token_of_the_type_is_bought_with_the_gas_of_the_callback() {
+		let mut contract = setup();
+		let offer_id = place_collection_offer(&mut contract, accounts(2), 100, 1, Some("gold"), None);
+		approve(&mut contract, accounts(1), "1", &fill_msg(offer_id, 100));
+
+		// creating the transfer and its callback panics if they don't fit
+		set_callback_context_with_gas(
+			CONST_GAS_FOR_RESOLVE_COLLECTION_OFFER_FILL,
+			vec![json_result(&JsonTokenType {
+				token_type: Some("gold".to_string()),
+			})],
+		);
+		contract.resolve_collection_offer_fill(CollectionOfferFill {
+			offer_id,
+			buyer_id: accounts(2),
+			seller_id: accounts(1),
+			nft_contract_id: nft_id(),
+			token_id: "1".to_string(),
+			approval_id: 0,
+			amount: U128(100),
+			token_type: "gold".to_string(),
+		});
+
+		assert_eq!(function_calls().len(), 2);
+		assert!(function_calls().contains(&(nft_id(), "nft_transfer_payout".to_string())));
+	}
+
+	#[test]
+	#[should_panic(expected = "Offer amount doesn't match the accepted amount")]
+	fn an_offer_can_not_be_filled_at_another_amount() {
+		let mut contract = setup();
+		let offer_id = place_collection_offer(&mut contract, accounts(2), 100, 1, None, None);
+
+		approve(&mut contract, accounts(1), "1", &fill_msg(offer_id, 90));
+	}
+}
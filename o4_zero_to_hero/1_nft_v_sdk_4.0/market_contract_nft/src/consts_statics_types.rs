@@ -3,6 +3,58 @@ use crate::*;
 // GAS constants to attach to calls
 pub const CONST_GAS_FOR_ROYALTIES: Gas = Gas(115_000_000_000_000);
 pub const CONST_GAS_FOR_NFT_TRANSFER: Gas = Gas(15_000_000_000_000);
+pub const CONST_GAS_FOR_NFT_TOKEN: Gas = Gas(10_000_000_000_000);
+pub const CONST_GAS_FOR_FT_TRANSFER: Gas = Gas(10_000_000_000_000);
+pub const CONST_GAS_FOR_NFT_IS_APPROVED: Gas = Gas(10_000_000_000_000);
+pub const CONST_GAS_FOR_RESOLVE_PRUNE_SALE: Gas = Gas(10_000_000_000_000);
+pub const CONST_GAS_FOR_NFT_PAYOUT: Gas = Gas(10_000_000_000_000);
+/*
+	resolve_escrow_payout transfers the escrowed token and then resolves the purchase, paying for both receipts and
+	the data receipt between them
+*/
+pub const CONST_GAS_FOR_RESOLVE_ESCROW_PAYOUT: Gas = Gas(
+    10_000_000_000_000
+        + CONST_GAS_FOR_NFT_TRANSFER.0
+        + CONST_GAS_FOR_ROYALTIES.0
+        + 2 * CONST_GAS_FOR_RECEIPT.0
+        + CONST_GAS_FOR_DATA_RECEIPT.0,
+);
+// resolve_cart_purchase gets this much GAS for every item in the cart
+pub const CONST_GAS_FOR_RESOLVE_CART_ITEM: Gas = Gas(20_000_000_000_000);
+/*
+	the callback checking the token type of a collection offer fill goes on to transfer and pay out royalties, paying
+	for both receipts and the data receipt between them
+*/
+pub const CONST_GAS_FOR_RESOLVE_COLLECTION_OFFER_FILL: Gas = Gas(
+    10_000_000_000_000
+        + CONST_GAS_FOR_NFT_TRANSFER.0
+        + CONST_GAS_FOR_ROYALTIES.0
+        + 2 * CONST_GAS_FOR_RECEIPT.0
+        + CONST_GAS_FOR_DATA_RECEIPT.0,
+);
+// resolve_return_escrowed_token only records a token that couldn't be given back
+pub const CONST_GAS_FOR_RESOLVE_RETURN_TOKEN: Gas = Gas(5_000_000_000_000);
+// resolve_sale_token_type only indexes the sale under the token type the NFT contract reported
+pub const CONST_GAS_FOR_RESOLVE_SALE_TOKEN_TYPE: Gas = Gas(10_000_000_000_000);
+// resolve_ft_payouts only records the FT payouts that couldn't be sent
+pub const CONST_GAS_FOR_RESOLVE_FT_PAYOUTS: Gas = Gas(10_000_000_000_000);
+// the send and execution fees of a function call receipt, burnt by the caller on top of the GAS it attaches
+pub const CONST_GAS_FOR_RECEIPT: Gas = Gas(5_000_000_000_000);
+// the send and execution fees of the data receipt that carries the result of a call to the callback waiting on it
+pub const CONST_GAS_FOR_DATA_RECEIPT: Gas = Gas(10_000_000_000_000);
+
+// The maximum amount of accounts the market pays out for a sale in NEAR (this is limited by GAS)
+pub const MAX_PAYOUT_LEN: u32 = 10;
+/*
+	The maximum amount of accounts the market pays out for a sale in a fungible token. Every one of them costs an
+	ft_transfer, its receipt and the data receipt taking its result to resolve_ft_payouts out of the
+	CONST_GAS_FOR_ROYALTIES of the resolving callback, which also needs about 20 TGas for itself and resolve_ft_payouts.
+*/
+pub const MAX_FT_PAYOUT_LEN: u32 = ((CONST_GAS_FOR_ROYALTIES.0
+    - 20_000_000_000_000
+    - CONST_GAS_FOR_RESOLVE_FT_PAYOUTS.0
+    - CONST_GAS_FOR_RECEIPT.0)
+    / (CONST_GAS_FOR_FT_TRANSFER.0 + CONST_GAS_FOR_RECEIPT.0 + CONST_GAS_FOR_DATA_RECEIPT.0)) as u32;
 
 // Constant used to attach 0 NEAR to a call
 pub const CONST_NO_DEPOSIT: Balance = 0;
@@ -10,10 +62,182 @@ pub const CONST_NO_DEPOSIT: Balance = 0;
 // The minimum storage to have a sale on the contract.
 pub const CONST_STORAGE_PER_SALE: u128 = 1000 * STORAGE_PRICE_PER_BYTE;
 
-// Every sale will have a unique ID which is `CONTRACT + DELIMITER + TOKEN_ID`
+// Paid out of the seller's storage balance to whoever prunes a stale sale of theirs
+pub const CONST_PRUNE_REWARD: u128 = CONST_STORAGE_PER_SALE / 10;
+
+/*
+	The maximum number of sales prune_sales checks at once. Every one of them costs an nft_is_approved call, its
+	resolve_prune_sale callback, their receipts and the data receipt between them out of 300 TGas, which also has to
+	leave about 20 TGas for prune_sales itself.
+*/
+pub const MAX_PRUNE_BATCH_LEN: usize = ((300_000_000_000_000
+    - 20_000_000_000_000)
+    / (CONST_GAS_FOR_NFT_IS_APPROVED.0
+        + CONST_GAS_FOR_RESOLVE_PRUNE_SALE.0
+        + 2 * CONST_GAS_FOR_RECEIPT.0
+        + CONST_GAS_FOR_DATA_RECEIPT.0)) as usize;
+
+// The maximum number of sales migrate_sales moves at once (this is limited by GAS)
+pub const MAX_MIGRATE_BATCH_LEN: u64 = 50;
+
+// The storage a buyer pays on top of the amount when placing a standing offer. It is refunded with the offer.
+pub const CONST_STORAGE_PER_OFFER: u128 = 1000 * STORAGE_PRICE_PER_BYTE;
+
+// Protocol fees are expressed in basis points, 10_000 is 100%
+pub const CONST_BASIS_POINTS_DENOMINATOR: u128 = 10_000;
+
+// The highest protocol fee the owner can set (10%)
+pub const MAX_PROTOCOL_FEE_BASIS_POINTS: u16 = 1_000;
+
+// The highest referral bounty a seller can set (20%)
+pub const MAX_REFERRAL_BOUNTY_BASIS_POINTS: u16 = 2_000;
+
+// Number of items returned by paginated views when no limit is passed in
+pub const CONST_DEFAULT_PAGE_LIMIT: u64 = 50;
+
+// Bids placed within this window (10 minutes, in nanoseconds) before the end of an auction extend it by the same amount
+pub const CONST_AUCTION_EXTENSION: u64 = 600_000_000_000;
+
+/*
+	The maximum number of items offer_many buys at once. Every one of them costs an nft_transfer_payout call, its
+	receipt, the data receipt taking its result to resolve_cart_purchase and CONST_GAS_FOR_RESOLVE_CART_ITEM out of
+	300 TGas, which also has to leave about 20 TGas for offer_many itself and the receipt of resolve_cart_purchase.
+*/
+pub const MAX_CART_LEN: usize = ((300_000_000_000_000
+    - 20_000_000_000_000
+    - CONST_GAS_FOR_RECEIPT.0)
+    / (CONST_GAS_FOR_NFT_TRANSFER.0
+        + CONST_GAS_FOR_RECEIPT.0
+        + CONST_GAS_FOR_DATA_RECEIPT.0
+        + CONST_GAS_FOR_RESOLVE_CART_ITEM.0)) as usize;
+
+/*
+	The maximum number of tokens in a bundle. Buying a bundle takes 4 steps of cross contract calls, buy_bundle_gas
+	for more tokens doesn't fit in 300 TGas. Every bundle locks the storage of this many sales.
+*/
+pub const MAX_BUNDLE_LEN: usize = 2;
+pub const CONST_STORAGE_PER_BUNDLE: u128 = MAX_BUNDLE_LEN as u128 * CONST_STORAGE_PER_SALE;
+
+/*
+	The maximum number of tokens in a swap, both sides together. Accepting a swap takes 3 steps of cross contract
+	calls, accept_swap_gas for more tokens doesn't fit in 300 TGas.
+*/
+pub const MAX_SWAP_LEN: usize = 3;
+// The storage the proposer pays on top of the amount when proposing a swap. It is refunded with the swap.
+pub const CONST_STORAGE_PER_SWAP: u128 = 2000 * STORAGE_PRICE_PER_BYTE;
+
+// The number of sales kept in the history of a collection and of a token
+pub const MAX_COLLECTION_SALES_HISTORY_LEN: usize = 50;
+pub const MAX_TOKEN_SALES_HISTORY_LEN: usize = 10;
+
+/*
+	The number of unverified collections and of tokens the market keeps sales history for. Once full, the one that
+	was tracked first makes room for the next one. Verified collections are always tracked.
+*/
+pub const MAX_TRACKED_COLLECTIONS_LEN: u64 = 1_000;
+pub const MAX_TRACKED_TOKENS_LEN: u64 = 10_000;
+
+// The maximum number of bids kept in the history of an auction
+pub const MAX_BID_HISTORY_LEN: usize = 10;
+
+// The standard and version of the events logged by the market
+pub const CONST_MARKET_STANDARD_NAME: &str = "market";
+pub const CONST_MARKET_EVENT_VERSION: &str = "1.0.0";
+
+// Legacy sale IDs are `CONTRACT + DELIMITER + TOKEN_ID`
 pub static STATIC_DELIMITER: &str = ".";
 
 // Creating custom types to use within the contract. This makes things more readable.
+// the price is in yoctoNEAR, or in the smallest unit of the fungible token the sale is listed in
 pub type TypeSalePriceInYoctoNear = U128;
 pub type TypeTokenId = String;
 pub type TypeContractAndTokenId = String;
+// key of the price index. Sorting by token ID second keeps keys unique and gives a stable cursor
+pub type TypePriceAndTokenId = (u128, TypeTokenId);
+// key of the collection offer price index. Sorting by offer ID second keeps keys unique
+pub type TypePriceAndOfferId = (u128, u64);
+
+/*
+	Every sale has a unique key made of the NFT contract and token ID. Both parts are stored length-prefixed by borsh,
+	so unlike `CONTRACT + DELIMITER + TOKEN_ID` two different pairs can never end up with the same key.
+*/
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StructSaleKey {
+	pub nft_contract_id: AccountId,
+	pub token_id: TypeTokenId,
+}
+
+impl StructSaleKey {
+	pub fn new(nft_contract_id: &AccountId, token_id: &TypeTokenId) -> Self {
+		Self {
+			nft_contract_id: nft_contract_id.clone(),
+			token_id: token_id.clone(),
+		}
+	}
+}
+
+// Token types (series) are only unique within their NFT contract, so sales are indexed by both
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StructTokenTypeKey {
+	pub nft_contract_id: AccountId,
+	pub token_type: String,
+}
+
+impl StructTokenTypeKey {
+	pub fn new(nft_contract_id: &AccountId, token_type: &str) -> Self {
+		Self {
+			nft_contract_id: nft_contract_id.clone(),
+			token_type: token_type.to_string(),
+		}
+	}
+}
+
+// Collection offers are indexed by the token type they accept within their NFT contract. None means any token type
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StructCollectionOfferTypeKey {
+	pub nft_contract_id: AccountId,
+	pub token_type: Option<String>,
+}
+
+impl StructCollectionOfferTypeKey {
+	pub fn new(nft_contract_id: &AccountId, token_type: Option<&String>) -> Self {
+		Self {
+			nft_contract_id: nft_contract_id.clone(),
+			token_type: token_type.cloned(),
+		}
+	}
+}
+
+// Every standing offer has a unique key made of the NFT contract, token ID and buyer, length-prefixed like StructSaleKey
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StructOfferKey {
+	pub nft_contract_id: AccountId,
+	pub token_id: TypeTokenId,
+	pub buyer_id: AccountId,
+}
+
+// An FT payout the market couldn't send is kept under the receiver and the fungible token it was paid in
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct StructFtPayoutKey {
+	pub account_id: AccountId,
+	pub ft_token_id: AccountId,
+}
+
+impl StructFtPayoutKey {
+	pub fn new(account_id: &AccountId, ft_token_id: &AccountId) -> Self {
+		Self {
+			account_id: account_id.clone(),
+			ft_token_id: ft_token_id.clone(),
+		}
+	}
+}
+
+impl StructOfferKey {
+	pub fn new(nft_contract_id: &AccountId, token_id: &TypeTokenId, buyer_id: &AccountId) -> Self {
+		Self {
+			nft_contract_id: nft_contract_id.clone(),
+			token_id: token_id.clone(),
+			buyer_id: buyer_id.clone(),
+		}
+	}
+}
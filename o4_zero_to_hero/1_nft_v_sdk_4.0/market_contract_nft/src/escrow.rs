@@ -0,0 +1,361 @@
+use crate::*;
+
+/*
+	trait that will be used as the callback from the NFT contract. When nft_transfer_call is called with the market
+	as the receiver, the token is transferred to the market and this is the function that is invoked. The market
+	then holds the token in escrow until it is sold or the sale is removed.
+*/
+pub trait NonFungibleTokenReceiver {
+	fn nft_on_transfer(
+		&mut self,
+		sender_id: AccountId,
+		previous_owner_id: AccountId,
+		token_id: TypeTokenId,
+		msg: String,
+	) -> PromiseOrValue<bool>;
+}
+
+#[near_bindgen]
+impl NonFungibleTokenReceiver for Contract {
+	/*
+		list a token that was sent to the market with nft_transfer_call. `msg` holds the same SaleArgs as nft_on_approve.
+		The sale belongs to the previous owner, who also pays for its storage. If listing fails the call panics and the
+		NFT contract gives the token back.
+	*/
+	fn nft_on_transfer(
+		&mut self,
+		sender_id: AccountId,
+		previous_owner_id: AccountId,
+		token_id: TypeTokenId,
+		msg: String,
+	) -> PromiseOrValue<bool> {
+		let nft_contract_id = env::predecessor_account_id();
+		let signer_id = env::signer_account_id();
+
+		assert_ne!(
+			nft_contract_id, signer_id,
+			"nft_on_transfer should only be called via cross-contract call"
+		);
+		assert_eq!(
+			sender_id, previous_owner_id,
+			"Only the token owner can escrow a token"
+		);
+
+		// Make sure the previous owner is the signer.
+		assert_eq!(previous_owner_id, signer_id, "previous_owner_id should be signer_id");
+
+		// the market owns the token now, so there is no approval ID
+		self.internal_list_sale(nft_contract_id, token_id, previous_owner_id, 0, &msg, true);
+
+		// false means the market keeps the token
+		PromiseOrValue::Value(false)
+	}
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		private callback for buying an escrowed token. nft_payout told us how to split the payout balance.
+		The market owns the token, so its share of the payout belongs to the seller. Then the market transfers the token
+		to the buyer. If the payout is faulty the buyer is refunded and the token goes back to the seller.
+	*/
+	#[private]
+	pub fn resolve_escrow_payout(&mut self, purchase: StructPurchase, fee: U128) -> Promise {
+		let payout = promise_result_as_success().and_then(|value| parse_payout(&value, purchase.payout_balance(fee).0, purchase.max_len_payout()));
+
+		let mut payout = if let Some(payout) = payout {
+			payout
+		} else {
+			let StructPurchase {
+				nft_contract_id,
+				token_id,
+				seller_id,
+				..
+			} = purchase.clone();
+			self.internal_resolve_purchase(purchase, fee, None);
+			return self.internal_return_escrowed_token(nft_contract_id, token_id, seller_id);
+		};
+
+		if let Some(amount) = payout.remove(&env::current_account_id()) {
+			let seller_amount = payout.get(&purchase.seller_id).map_or(0, |seller_amount| seller_amount.0);
+			payout.insert(purchase.seller_id.clone(), U128(seller_amount + amount.0));
+		}
+
+		ext_contract::nft_transfer(
+			purchase.buyer_id.clone(),
+			purchase.token_id.clone(),
+			None,
+			Some("payout from market".to_string()),
+			purchase.nft_contract_id.clone(),
+			1,
+			CONST_GAS_FOR_NFT_TRANSFER,
+		)
+		.then(ext_self::resolve_escrow_purchase(
+			purchase,
+			fee,
+			Payout { payout },
+			env::current_account_id(),
+			CONST_NO_DEPOSIT,
+			CONST_GAS_FOR_ROYALTIES,
+		))
+	}
+
+	/*
+		private callback for the transfer of an escrowed token to the buyer. If it went through, the accounts in the
+		payout are paid just like resolve_purchase does. Otherwise the buyer is refunded and the token goes back to the seller.
+	*/
+	#[private]
+	pub fn resolve_escrow_purchase(&mut self, purchase: StructPurchase, fee: U128, payout: Payout) -> U128 {
+		if is_promise_success() {
+			let payout_result = near_sdk::serde_json::to_vec(&payout).ok();
+			return self.internal_resolve_purchase(purchase, fee, payout_result);
+		}
+
+		self.internal_return_escrowed_token(
+			purchase.nft_contract_id.clone(),
+			purchase.token_id.clone(),
+			purchase.seller_id.clone(),
+		);
+		self.internal_resolve_purchase(purchase, fee, None)
+	}
+
+	/*
+		private callback for giving an escrowed token back. If the transfer failed, the token is recorded so the account
+		it belongs to can claim it with claim_unreturned_token. Returns whether the token was given back.
+	*/
+	#[private]
+	pub fn resolve_return_escrowed_token(
+		&mut self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		owner_id: AccountId,
+	) -> bool {
+		if is_promise_success() {
+			return true;
+		}
+
+		env::log_str(&format!(
+			"Token {} of {} could not be returned to {}, it can be claimed",
+			token_id, nft_contract_id, owner_id
+		));
+		self.unreturned_tokens
+			.insert(&StructSaleKey::new(&nft_contract_id, &token_id), &owner_id);
+
+		false
+	}
+
+	// Claim an escrowed token the market couldn't give back to you
+	#[payable]
+	pub fn claim_unreturned_token(&mut self, nft_contract_id: AccountId, token_id: TypeTokenId) -> Promise {
+		assert_one_yocto();
+
+		let owner_id = self
+			.unreturned_tokens
+			.remove(&StructSaleKey::new(&nft_contract_id, &token_id))
+			.expect("No unreturned token");
+		assert_eq!(
+			env::predecessor_account_id(),
+			owner_id,
+			"Only the owner can claim the token"
+		);
+
+		self.internal_return_escrowed_token(nft_contract_id, token_id, owner_id)
+	}
+
+	// Returns the account that can claim an escrowed token the market couldn't give back, if any
+	pub fn get_unreturned_token_owner(&self, nft_contract_id: AccountId, token_id: TypeTokenId) -> Option<AccountId> {
+		self.unreturned_tokens
+			.get(&StructSaleKey::new(&nft_contract_id, &token_id))
+	}
+}
+
+impl Contract {
+	/*
+		buy an escrowed token. The market asks the NFT contract how to split the payout balance of the purchase with
+		nft_payout and resolve_escrow_payout transfers the token itself.
+	*/
+	pub(crate) fn internal_escrow_transfer_payout(&self, purchase: StructPurchase) -> Promise {
+		let fee = self.internal_protocol_fee(purchase.price);
+
+		ext_contract::nft_payout(
+			purchase.token_id.clone(),
+			purchase.payout_balance(fee),
+			purchase.max_len_payout(), // the maximum amount of accounts the market can payout at once (this is limited by GAS)
+			purchase.nft_contract_id.clone(),
+			CONST_NO_DEPOSIT,
+			CONST_GAS_FOR_NFT_PAYOUT,
+		)
+		.then(ext_self::resolve_escrow_payout(
+			purchase,
+			fee,
+			env::current_account_id(),
+			CONST_NO_DEPOSIT,
+			CONST_GAS_FOR_RESOLVE_ESCROW_PAYOUT,
+		))
+	}
+
+	/*
+		transfer a token the market holds to an account, e.g. back to its owner when an escrowed sale is removed.
+		If the transfer fails, resolve_return_escrowed_token keeps a record so the account can claim the token later.
+	*/
+	pub(crate) fn internal_return_escrowed_token(
+		&self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		owner_id: AccountId,
+	) -> Promise {
+		ext_contract::nft_transfer(
+			owner_id.clone(),
+			token_id.clone(),
+			None,
+			Some("returned from market".to_string()),
+			nft_contract_id.clone(),
+			1,
+			CONST_GAS_FOR_NFT_TRANSFER,
+		)
+		.then(ext_self::resolve_return_escrowed_token(
+			nft_contract_id,
+			token_id,
+			owner_id,
+			env::current_account_id(),
+			CONST_NO_DEPOSIT,
+			CONST_GAS_FOR_RESOLVE_RETURN_TOKEN,
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::{accounts, VMContextBuilder};
+	use near_sdk::testing_env;
+
+	// bob pays for the storage of a sale and sends token "1" to the market with nft_transfer_call
+	fn escrow(contract: &mut Contract, msg: &str) -> PromiseOrValue<bool> {
+		set_context(accounts(1), CONST_STORAGE_PER_SALE);
+		contract.storage_deposit(None, None);
+
+		testing_env!(VMContextBuilder::new()
+			.current_account_id(market_id())
+			.signer_account_id(accounts(1))
+			.predecessor_account_id(nft_id())
+			.build());
+		contract.nft_on_transfer(accounts(1), accounts(1), "1".to_string(), msg.to_string())
+	}
+
+	fn setup_escrowed_sale() -> Contract {
+		let mut contract = setup();
+		escrow(&mut contract, r#"{"sale_conditions":"100"}"#);
+
+		contract
+	}
+
+	fn nft_transfers() -> usize {
+		function_calls()
+			.into_iter()
+			.filter(|call| call == &(nft_id(), "nft_transfer".to_string()))
+			.count()
+	}
+
+	#[test]
+	fn a_token_sent_to_the_market_is_listed_for_its_previous_owner() {
+		let mut contract = setup();
+
+		let keep_token = match escrow(&mut contract, r#"{"sale_conditions":"100"}"#) {
+			PromiseOrValue::Value(return_token) => !return_token,
+			PromiseOrValue::Promise(_) => panic!("Expected the market to keep the token"),
+		};
+
+		assert!(keep_token);
+		let sale = get_sale(&contract, "1").unwrap();
+		assert!(sale.escrowed);
+		assert_eq!(sale.owner_id, accounts(1));
+	}
+
+	#[test]
+	#[should_panic(expected = "Only the token owner can escrow a token")]
+	fn an_approved_account_can_not_escrow_the_token() {
+		let mut contract = setup();
+		set_context(accounts(1), CONST_STORAGE_PER_SALE);
+		contract.storage_deposit(None, None);
+
+		testing_env!(VMContextBuilder::new()
+			.current_account_id(market_id())
+			.signer_account_id(accounts(1))
+			.predecessor_account_id(nft_id())
+			.build());
+		contract.nft_on_transfer(accounts(3), accounts(1), "1".to_string(), r#"{"sale_conditions":"100"}"#.to_string());
+	}
+
+	#[test]
+	fn removing_an_escrowed_sale_gives_the_token_back() {
+		let mut contract = setup_escrowed_sale();
+
+		set_context(accounts(1), 1);
+		contract.remove_sale(nft_id(), "1".to_string());
+
+		assert!(get_sale(&contract, "1").is_none());
+		assert_eq!(nft_transfers(), 1);
+	}
+
+	#[test]
+	fn buying_an_escrowed_token_asks_for_the_payout_and_then_transfers_it() {
+		let mut contract = setup_escrowed_sale();
+
+		set_context(accounts(2), 100);
+		contract.offer(nft_id(), "1".to_string(), None);
+		assert!(function_calls().contains(&(nft_id(), "nft_payout".to_string())));
+
+		// the market's share of the payout goes to the seller
+		set_callback_context_with_gas(
+			CONST_GAS_FOR_RESOLVE_ESCROW_PAYOUT,
+			vec![payout_result(&[(market_id(), 90), (accounts(1), 5), (accounts(3), 5)])],
+		);
+		contract.resolve_escrow_payout(purchase(accounts(2), accounts(1), "1", 100), U128(0));
+		assert_eq!(nft_transfers(), 1);
+
+		set_callback_context(vec![PromiseResult::Successful(vec![])]);
+		contract.resolve_escrow_purchase(
+			purchase(accounts(2), accounts(1), "1", 100),
+			U128(0),
+			Payout {
+				payout: [(accounts(1), U128(95)), (accounts(3), U128(5))].into_iter().collect(),
+			},
+		);
+		let mut paid = transfers();
+		paid.sort();
+		assert_eq!(paid, vec![(accounts(1), 95), (accounts(3), 5)]);
+	}
+
+	#[test]
+	fn a_failed_transfer_to_the_buyer_refunds_the_buyer_and_returns_the_token() {
+		let mut contract = setup();
+
+		set_callback_context_with_gas(CONST_GAS_FOR_ROYALTIES, vec![PromiseResult::Failed]);
+		contract.resolve_escrow_purchase(
+			purchase(accounts(2), accounts(1), "1", 100),
+			U128(0),
+			Payout {
+				payout: [(accounts(1), U128(100))].into_iter().collect(),
+			},
+		);
+
+		assert_eq!(transfers(), vec![(accounts(2), 100)]);
+		assert_eq!(nft_transfers(), 1);
+	}
+
+	#[test]
+	fn a_token_that_could_not_be_returned_can_be_claimed_by_its_owner() {
+		let mut contract = setup();
+
+		set_callback_context(vec![PromiseResult::Failed]);
+		assert!(!contract.resolve_return_escrowed_token(nft_id(), "1".to_string(), accounts(1)));
+		assert_eq!(contract.get_unreturned_token_owner(nft_id(), "1".to_string()), Some(accounts(1)));
+
+		set_context(accounts(1), 1);
+		contract.claim_unreturned_token(nft_id(), "1".to_string());
+		assert_eq!(nft_transfers(), 1);
+		assert_eq!(contract.get_unreturned_token_owner(nft_id(), "1".to_string()), None);
+	}
+}
@@ -0,0 +1,234 @@
+use crate::*;
+use std::fmt::{Display, Error, Formatter, Result};
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleListedLog {
+	pub owner_id: String,
+	pub nft_contract_id: String,
+	pub token_id: TypeTokenId,
+	pub approval_id: u64,
+	pub sale_conditions: U128,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ft_token_id: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub auction: Option<String>,
+	// whether the market holds the token itself instead of an approval
+	pub escrowed: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub reserved_for: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub expires_at: Option<U64>,
+	pub referral_bounty_basis_points: u16,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleUpdatedLog {
+	pub owner_id: String,
+	pub nft_contract_id: String,
+	pub token_id: TypeTokenId,
+	pub sale_conditions: U128,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleRemovedLog {
+	pub owner_id: String,
+	pub nft_contract_id: String,
+	pub token_id: TypeTokenId,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SalePurchasedLog {
+	pub buyer_id: String,
+	pub nft_contract_id: String,
+	pub token_id: TypeTokenId,
+	pub price: U128,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ft_token_id: Option<String>,
+	pub protocol_fee: U128,
+	// how the price minus the protocol fee and referral bounty was split between the seller and royalties
+	pub payout: HashMap<AccountId, U128>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub referrer_id: Option<String>,
+	// the share of the protocol fee plus the referral bounty paid to the referrer
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub referral_amount: Option<U128>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseRefundedLog {
+	pub buyer_id: String,
+	pub nft_contract_id: String,
+	pub token_id: TypeTokenId,
+	pub price: U128,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ft_token_id: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BundleListedLog {
+	pub bundle_id: U64,
+	pub owner_id: String,
+	pub items: Vec<StructBundleItem>,
+	pub price: U128,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BundleRemovedLog {
+	pub bundle_id: U64,
+	pub owner_id: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapProposedLog {
+	pub swap_id: U64,
+	pub proposer_id: String,
+	pub counterparty_id: String,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapAcceptedLog {
+	pub swap_id: U64,
+	pub proposer_id: String,
+	pub counterparty_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+#[serde(crate = "near_sdk::serde")]
+#[non_exhaustive]
+pub enum EventLogVariant {
+	SaleListed(Vec<SaleListedLog>),
+	SaleUpdated(Vec<SaleUpdatedLog>),
+	SaleRemoved(Vec<SaleRemovedLog>),
+	SalePurchased(Vec<SalePurchasedLog>),
+	PurchaseRefunded(Vec<PurchaseRefundedLog>),
+	BundleListed(Vec<BundleListedLog>),
+	BundleRemoved(Vec<BundleRemovedLog>),
+	SwapProposed(Vec<SwapProposedLog>),
+	SwapAccepted(Vec<SwapAcceptedLog>),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct EventLog {
+	pub standard: String,
+	pub version: String,
+
+	// `flatten` to not have "event": {<EventLogVariant>} in the JSON, just have the contents of {<EventLogVariant>}.
+	#[serde(flatten)]
+	pub event: EventLogVariant,
+}
+
+impl Display for EventLog {
+	fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+		f.write_fmt(format_args!(
+			"EVENT_JSON: {}",
+			near_sdk::serde_json::to_string(self).map_err(|_| Error)?
+		))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::serde_json::{json, Value};
+	use near_sdk::test_utils::{accounts, get_logs};
+
+	// the events the last call logged
+	fn events() -> Vec<Value> {
+		get_logs()
+			.iter()
+			.filter_map(|log| log.strip_prefix("EVENT_JSON: "))
+			.map(|event| near_sdk::serde_json::from_str(event).unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn listing_a_token_logs_sale_listed() {
+		let mut contract = setup();
+
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+
+		assert_eq!(
+			events(),
+			vec![json!({
+				"standard": "market",
+				"version": "1.0.0",
+				"event": "sale_listed",
+				"data": [{
+					"owner_id": "bob",
+					"nft_contract_id": "nft",
+					"token_id": "1",
+					"approval_id": 0,
+					"sale_conditions": "100",
+					"escrowed": false,
+					"referral_bounty_basis_points": 0
+				}]
+			})]
+		);
+	}
+
+	#[test]
+	fn updating_and_removing_a_sale_log_their_events() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+
+		set_context(accounts(1), 1);
+		contract.update_price(nft_id(), "1".to_string(), U128(80));
+		assert_eq!(events()[0]["event"], "sale_updated");
+		assert_eq!(events()[0]["data"][0]["sale_conditions"], "80");
+
+		set_context(accounts(1), 1);
+		contract.remove_sale(nft_id(), "1".to_string());
+		assert_eq!(events()[0]["event"], "sale_removed");
+		assert_eq!(
+			events()[0]["data"][0],
+			json!({ "owner_id": "bob", "nft_contract_id": "nft", "token_id": "1" })
+		);
+	}
+
+	#[test]
+	fn a_purchase_logs_sale_purchased_with_the_payout() {
+		let mut contract = setup();
+
+		set_callback_context(vec![payout_result(&[(accounts(1), 100)])]);
+		contract.resolve_purchase(purchase(accounts(2), accounts(1), "1", 100), U128(0));
+
+		let event = &events()[0];
+		assert_eq!(event["event"], "sale_purchased");
+		assert_eq!(
+			event["data"][0],
+			json!({
+				"buyer_id": "charlie",
+				"nft_contract_id": "nft",
+				"token_id": "1",
+				"price": "100",
+				"protocol_fee": "0",
+				"payout": { "bob": "100" }
+			})
+		);
+	}
+
+	#[test]
+	fn a_refunded_purchase_logs_purchase_refunded() {
+		let mut contract = setup();
+
+		set_callback_context(vec![PromiseResult::Failed]);
+		contract.resolve_purchase(purchase(accounts(2), accounts(1), "1", 100), U128(0));
+
+		assert_eq!(events().len(), 1);
+		assert_eq!(events()[0]["event"], "purchase_refunded");
+		assert_eq!(events()[0]["data"][0]["buyer_id"], "charlie");
+	}
+}
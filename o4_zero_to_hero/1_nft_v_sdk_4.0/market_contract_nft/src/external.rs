@@ -20,4 +20,22 @@ trait ExContract {
 		// The maximum amount of accounts the market can payout at once (this is limited by GAS)
 		max_len_payout: u32,
 	);
+
+	// view the token, used to check its token type for collection offers
+	fn nft_token(&self, token_id: TypeTokenId);
+
+	// check that the market still holds the approval a sale was listed with
+	fn nft_is_approved(&self, token_id: TypeTokenId, approved_account_id: AccountId, approval_id: Option<u64>);
+
+	// view how the price of an escrowed token has to be split between the seller and royalties
+	fn nft_payout(&self, token_id: TypeTokenId, balance: U128, max_len_payout: u32);
+
+	// transfer an escrowed token, the market owns it so no approval ID is needed
+	fn nft_transfer(&mut self, receiver_id: AccountId, token_id: TypeTokenId, approval_id: Option<u64>, memo: Option<String>);
+}
+
+// pay out (or refund) sales that are priced in a NEP-141 fungible token
+#[ext_contract(ext_ft)]
+trait ExtFungibleToken {
+	fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
 }
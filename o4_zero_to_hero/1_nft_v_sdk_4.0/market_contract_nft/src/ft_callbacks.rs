@@ -0,0 +1,227 @@
+use crate::*;
+
+// msg passed to ft_transfer_call by the buyer to purchase a sale priced in a fungible token
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseArgs {
+	pub nft_contract_id: AccountId,
+	pub token_id: TypeTokenId,
+	// only accepted to reject it explicitly, referrers can't be paid in fungible tokens
+	pub referrer_id: Option<AccountId>,
+}
+
+/*
+	trait that will be used as the callback from a NEP-141 FT contract. When ft_transfer_call is
+	called, the tokens are sent to this marketplace and this is the function that is invoked.
+*/
+pub trait FungibleTokenReceiver {
+	fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> PromiseOrValue<U128>;
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Contract {
+	/*
+		Buy a sale priced in the fungible token that was sent. The amount must cover the current price, the rest is
+		returned as unused so the FT contract refunds it to the buyer. Referral earnings are kept in NEAR, so purchases
+		with a referrer_id are rejected instead of dropping the referrer.
+	*/
+	fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> PromiseOrValue<U128> {
+		// the FT contract calls this method, so the predecessor is the fungible token that was sent
+		let ft_token_id = env::predecessor_account_id();
+
+		let PurchaseArgs {
+			nft_contract_id,
+			token_id,
+			referrer_id,
+		} = near_sdk::serde_json::from_str(&msg).expect("No valid PurchaseArgs");
+		assert!(
+			referrer_id.is_none(),
+			"Sales priced in a fungible token don't pay referrers"
+		);
+
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
+		let sale = self.sales.get(&sale_key).expect("No sale");
+
+		assert_ne!(sale.owner_id, sender_id, "Can not bid on your own sale.");
+		sale.assert_can_be_bought_by(&sender_id);
+		assert_eq!(
+			sale.ft_token_id.as_ref(),
+			Some(&ft_token_id),
+			"Sale is not priced in {}",
+			ft_token_id
+		);
+
+		// the price of a Dutch auction keeps falling, so the buyer only pays the current price
+		let price = sale.current_price().0;
+		assert!(
+			amount.0 >= price,
+			"Transferred amount must be greater than or equal to the current price: {:?}",
+			price
+		);
+
+		self.process_purchase(nft_contract_id, token_id, U128(price), sender_id, None);
+
+		PromiseOrValue::Value(U128(amount.0 - price))
+	}
+}
+
+#[near_bindgen]
+impl Contract {
+	// Accept the given fungible tokens as payment for sales. Only the owner of the market can call this.
+	pub fn add_ft_token_ids(&mut self, ft_token_ids: Vec<AccountId>) {
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the owner can add fungible tokens"
+		);
+
+		for ft_token_id in ft_token_ids {
+			self.ft_token_ids.insert(&ft_token_id);
+		}
+	}
+
+	// Stop accepting a fungible token for new sales. Sales already listed in it can still be bought.
+	pub fn remove_ft_token_id(&mut self, ft_token_id: AccountId) {
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the owner can remove fungible tokens"
+		);
+
+		self.ft_token_ids.remove(&ft_token_id);
+	}
+
+	// Returns the fungible tokens sales can be priced in, besides NEAR
+	pub fn supported_ft_token_ids(&self) -> Vec<AccountId> {
+		self.ft_token_ids.to_vec()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::accounts;
+
+	fn usdc_id() -> AccountId {
+		"usdc".parse().unwrap()
+	}
+
+	// bob lists token "1" for 100 USDC
+	fn setup_ft_sale() -> Contract {
+		let mut contract = setup();
+		set_context(owner_id(), 0);
+		contract.add_ft_token_ids(vec![usdc_id()]);
+		list(
+			&mut contract,
+			accounts(1),
+			"1",
+			&format!(r#"{{"sale_conditions":"100","ft_token_id":"{}"}}"#, usdc_id()),
+		);
+
+		contract
+	}
+
+	fn ft_purchase(price: u128) -> StructPurchase {
+		StructPurchase {
+			ft_token_id: Some(usdc_id()),
+			..purchase(accounts(2), accounts(1), "1", price)
+		}
+	}
+
+	// the receivers of the ft_transfer calls the last call made
+	fn ft_transfers() -> Vec<AccountId> {
+		function_calls()
+			.into_iter()
+			.filter(|(_, function_name)| function_name == "ft_transfer")
+			.map(|(receiver_id, _)| receiver_id)
+			.collect()
+	}
+
+	fn buy(contract: &mut Contract, ft_token_id: AccountId, amount: u128) -> PromiseOrValue<U128> {
+		set_context(ft_token_id, 0);
+		contract.ft_on_transfer(
+			accounts(2),
+			U128(amount),
+			format!(r#"{{"nft_contract_id":"{}","token_id":"1"}}"#, nft_id()),
+		)
+	}
+
+	#[test]
+	fn transferring_the_fungible_token_buys_the_sale_and_returns_the_rest() {
+		let mut contract = setup_ft_sale();
+
+		let unused_amount = match buy(&mut contract, usdc_id(), 150) {
+			PromiseOrValue::Value(unused_amount) => unused_amount,
+			PromiseOrValue::Promise(_) => panic!("Expected the unused amount"),
+		};
+
+		assert_eq!(unused_amount, U128(50));
+		assert!(get_sale(&contract, "1").is_none());
+		assert!(function_calls().contains(&(nft_id(), "nft_transfer_payout".to_string())));
+	}
+
+	#[test]
+	#[should_panic(expected = "Sale is not priced in dai")]
+	fn another_fungible_token_can_not_buy_the_sale() {
+		let mut contract = setup_ft_sale();
+
+		buy(&mut contract, "dai".parse().unwrap(), 100);
+	}
+
+	#[test]
+	fn the_payout_is_sent_with_ft_transfer() {
+		let mut contract = setup_ft_sale();
+
+		set_callback_context(vec![payout_result(&[(accounts(1), 90), (accounts(3), 10)])]);
+		contract.resolve_purchase(ft_purchase(100), U128(0));
+
+		assert_eq!(ft_transfers(), vec![usdc_id(), usdc_id()]);
+		assert!(transfers().is_empty());
+	}
+
+	#[test]
+	fn a_payout_to_as_many_receivers_as_allowed_fits_in_the_gas_of_resolve_purchase() {
+		let mut contract = setup_ft_sale();
+		let payout: Vec<(AccountId, u128)> = (0..MAX_FT_PAYOUT_LEN)
+			.map(|index| (format!("royalty{}", index).parse().unwrap(), 10))
+			.collect();
+		let price = 10 * payout.len() as u128;
+
+		// creating the ft_transfer calls and their callback panics if they don't fit
+		set_callback_context_with_gas(CONST_GAS_FOR_ROYALTIES, vec![payout_result(&payout)]);
+		contract.resolve_purchase(ft_purchase(price), U128(0));
+
+		assert_eq!(ft_transfers().len(), MAX_FT_PAYOUT_LEN as usize);
+	}
+
+	#[test]
+	fn a_payout_to_more_receivers_than_the_gas_covers_refunds_the_buyer() {
+		let mut contract = setup_ft_sale();
+		let payout: Vec<(AccountId, u128)> = (0..=MAX_FT_PAYOUT_LEN)
+			.map(|index| (format!("royalty{}", index).parse().unwrap(), 10))
+			.collect();
+		let price = 10 * payout.len() as u128;
+
+		set_callback_context(vec![payout_result(&payout)]);
+		contract.resolve_purchase(ft_purchase(price), U128(0));
+
+		// the only ft_transfer is the refund to the buyer
+		assert_eq!(ft_transfers(), vec![usdc_id()]);
+	}
+
+	#[test]
+	fn payouts_that_could_not_be_sent_can_be_claimed() {
+		let mut contract = setup_ft_sale();
+
+		set_callback_context(vec![PromiseResult::Successful(vec![]), PromiseResult::Failed]);
+		contract.resolve_ft_payouts(usdc_id(), vec![(accounts(1), U128(90)), (accounts(3), U128(10))]);
+		assert_eq!(contract.get_unclaimed_ft_payout(accounts(1), usdc_id()), U128(0));
+		assert_eq!(contract.get_unclaimed_ft_payout(accounts(3), usdc_id()), U128(10));
+
+		set_context(accounts(3), 1);
+		contract.claim_ft_payout(usdc_id());
+		assert_eq!(ft_transfers(), vec![usdc_id()]);
+		assert_eq!(contract.get_unclaimed_ft_payout(accounts(3), usdc_id()), U128(0));
+	}
+}
@@ -7,17 +7,96 @@ pub(crate) fn hash_account_id(account_id: &AccountId) -> CryptoHash {
 	hash
 }
 
+pub(crate) fn hash_token_type_key(token_type_key: &StructTokenTypeKey) -> CryptoHash {
+	let mut hash = CryptoHash::default();
+	hash.copy_from_slice(&env::sha256(&token_type_key.try_to_vec().unwrap()));
+
+	hash
+}
+
+pub(crate) fn hash_collection_offer_type_key(type_key: &StructCollectionOfferTypeKey) -> CryptoHash {
+	let mut hash = CryptoHash::default();
+	hash.copy_from_slice(&env::sha256(&type_key.try_to_vec().unwrap()));
+
+	hash
+}
+
+pub(crate) fn hash_sale_key(sale_key: &StructSaleKey) -> CryptoHash {
+	let mut hash = CryptoHash::default();
+	hash.copy_from_slice(&env::sha256(&sale_key.try_to_vec().unwrap()));
+
+	hash
+}
+
 impl Contract {
+	// internal method for adding a sale to the market and to every index the view calls use
+	pub(crate) fn internal_add_sale(&mut self, sale_key: &StructSaleKey, sale: &StructSale) {
+		self.sales.insert(sale_key, sale);
+
+		// Get the sales by owner ID for the given owner. If there are none, we create a new empty set
+		let mut by_owner_id = self.by_owner_id.get(&sale.owner_id).unwrap_or_else(|| {
+			UnorderedSet::new(
+				EnumStorageKey::ByOwnerIdByKeyInner {
+					account_id_hash: hash_account_id(&sale.owner_id),
+				}
+				.try_to_vec()
+				.unwrap(),
+			)
+		});
+		// insert the unique sale ID into the set
+		by_owner_id.insert(sale_key);
+		// insert that set back into the collection for the owner
+		self.by_owner_id.insert(&sale.owner_id, &by_owner_id);
+
+		// Get the token IDs for the given nft contract ID. If there are none, we create a new empty set
+		let mut by_nft_contract_id = self
+			.by_nft_contract_id
+			.get(&sale_key.nft_contract_id)
+			.unwrap_or_else(|| {
+				UnorderedSet::new(
+					EnumStorageKey::ByNFTContractIdInner {
+						account_id_hash: hash_account_id(&sale_key.nft_contract_id),
+					}
+					.try_to_vec()
+					.unwrap(),
+				)
+			});
+
+		// insert the token ID into the set
+		by_nft_contract_id.insert(&sale_key.token_id);
+		self.by_nft_contract_id
+			.insert(&sale_key.nft_contract_id, &by_nft_contract_id);
+
+		self.internal_add_sale_to_price_index(&sale_key.nft_contract_id, sale);
+
+		self.internal_add_sale_to_token_type_index(sale_key, sale);
+
+		// index the sale under the buyer it is reserved for, so they can find it
+		if let Some(reserved_for) = sale.reserved_for.as_ref() {
+			let mut by_reserved_for = self.by_reserved_for.get(reserved_for).unwrap_or_else(|| {
+				UnorderedSet::new(
+					EnumStorageKey::ByReservedForInner {
+						account_id_hash: hash_account_id(reserved_for),
+					}
+					.try_to_vec()
+					.unwrap(),
+				)
+			});
+			by_reserved_for.insert(sale_key);
+			self.by_reserved_for.insert(reserved_for, &by_reserved_for);
+		}
+	}
+
 	// internal method for removing a sale from the market. This returns the previously removed sale object
 	pub(crate) fn internal_remove_sale(
 		&mut self,
 		nft_contract_id: AccountId,
 		token_id: TypeTokenId,
 	) -> StructSale {
-		let contract_and_token_id = format!("{}{}{}", &nft_contract_id, STATIC_DELIMITER, token_id);
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
 
 		// Get the sale object by removing the unique sale ID. If there was no sale, panic
-		let sale = self.sales.remove(&contract_and_token_id).expect("No sale");
+		let sale = self.sales.remove(&sale_key).expect("No sale");
 
 		// Get the set of sales for the sale's owner. If there's no sale, panic.
 		let mut by_owner_id = self
@@ -25,7 +104,7 @@ impl Contract {
 			.get(&sale.owner_id)
 			.expect("No sale by_owner_id");
 
-		by_owner_id.remove(&contract_and_token_id);
+		by_owner_id.remove(&sale_key);
 
 		if by_owner_id.is_empty() {
 			self.by_owner_id.remove(&sale.owner_id);
@@ -47,6 +126,199 @@ impl Contract {
 				.insert(&nft_contract_id, &by_nft_contract_id);
 		}
 
+		self.internal_remove_sale_from_price_index(&nft_contract_id, &sale);
+
+		if let Some(token_type) = sale.token_type.as_ref() {
+			let token_type_key = StructTokenTypeKey::new(&nft_contract_id, token_type);
+			let mut by_nft_token_type = self
+				.by_nft_token_type
+				.get(&token_type_key)
+				.expect("No sale by nft_token_type");
+			by_nft_token_type.remove(&sale_key);
+
+			if by_nft_token_type.is_empty() {
+				self.by_nft_token_type.remove(&token_type_key);
+			} else {
+				self.by_nft_token_type.insert(&token_type_key, &by_nft_token_type);
+			}
+		}
+
+		if let Some(reserved_for) = sale.reserved_for.as_ref() {
+			let mut by_reserved_for = self
+				.by_reserved_for
+				.get(reserved_for)
+				.expect("No sale by reserved_for");
+			by_reserved_for.remove(&sale_key);
+
+			if by_reserved_for.is_empty() {
+				self.by_reserved_for.remove(reserved_for);
+			} else {
+				self.by_reserved_for.insert(reserved_for, &by_reserved_for);
+			}
+		}
+
+		let sale_removed_log: EventLog = EventLog {
+			standard: CONST_MARKET_STANDARD_NAME.to_string(),
+			version: CONST_MARKET_EVENT_VERSION.to_string(),
+			event: EventLogVariant::SaleRemoved(vec![SaleRemovedLog {
+				owner_id: sale.owner_id.to_string(),
+				nft_contract_id: nft_contract_id.to_string(),
+				token_id,
+			}]),
+		};
+
+		env::log_str(&sale_removed_log.to_string());
+
+		sale
+	}
+
+	/*
+		remove a sale that isn't being bought, e.g. when it is taken down or replaced. An escrowed token goes back to
+		the seller since the market holds it.
+	*/
+	pub(crate) fn internal_take_down_sale(&mut self, nft_contract_id: AccountId, token_id: TypeTokenId) -> StructSale {
+		let sale = self.internal_remove_sale(nft_contract_id.clone(), token_id.clone());
+
+		if sale.escrowed {
+			self.internal_return_escrowed_token(nft_contract_id, token_id, sale.owner_id.clone());
+		}
+
 		sale
 	}
+
+	/*
+		add a fixed price sale in NEAR to the price index of its contract. Auctions, FT sales and sales reserved for a
+		buyer aren't indexed.
+	*/
+	pub(crate) fn internal_add_sale_to_price_index(&mut self, nft_contract_id: &AccountId, sale: &StructSale) {
+		if sale.auction.is_some() || sale.ft_token_id.is_some() || sale.reserved_for.is_some() {
+			return;
+		}
+
+		let mut by_price = self
+			.by_nft_contract_id_by_price
+			.get(nft_contract_id)
+			.unwrap_or_else(|| {
+				TreeMap::new(
+					EnumStorageKey::ByNFTContractIdByPriceInner {
+						account_id_hash: hash_account_id(nft_contract_id),
+					}
+					.try_to_vec()
+					.unwrap(),
+				)
+			});
+		by_price.insert(&(sale.sale_conditions.0, sale.token_id.clone()), &());
+		self.by_nft_contract_id_by_price
+			.insert(nft_contract_id, &by_price);
+	}
+
+	// index the sale under the token type of its NFT contract, once the NFT contract reported one
+	pub(crate) fn internal_add_sale_to_token_type_index(&mut self, sale_key: &StructSaleKey, sale: &StructSale) {
+		let token_type = if let Some(token_type) = sale.token_type.as_ref() {
+			token_type
+		} else {
+			return;
+		};
+
+		let token_type_key = StructTokenTypeKey::new(&sale_key.nft_contract_id, token_type);
+		let mut by_nft_token_type = self.by_nft_token_type.get(&token_type_key).unwrap_or_else(|| {
+			UnorderedSet::new(
+				EnumStorageKey::ByNFTTokenTypeInner {
+					token_type_key_hash: hash_token_type_key(&token_type_key),
+				}
+				.try_to_vec()
+				.unwrap(),
+			)
+		});
+		by_nft_token_type.insert(sale_key);
+		self.by_nft_token_type.insert(&token_type_key, &by_nft_token_type);
+	}
+
+	// take a sale out of the price index of its contract, if it is in there
+	pub(crate) fn internal_remove_sale_from_price_index(&mut self, nft_contract_id: &AccountId, sale: &StructSale) {
+		let mut by_price = if let Some(by_price) = self.by_nft_contract_id_by_price.get(nft_contract_id) {
+			by_price
+		} else {
+			return;
+		};
+
+		by_price.remove(&(sale.sale_conditions.0, sale.token_id.clone()));
+
+		if by_price.is_empty() {
+			self.by_nft_contract_id_by_price.remove(nft_contract_id);
+		} else {
+			self.by_nft_contract_id_by_price
+				.insert(nft_contract_id, &by_price);
+		}
+	}
+
+	// remove the sale for a token that is about to be sold some other way. Auctions with escrowed bids can't be removed.
+	pub(crate) fn internal_remove_sale_if_listed(&mut self, nft_contract_id: AccountId, token_id: TypeTokenId) {
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
+
+		if self.sales.get(&sale_key).is_some() {
+			self.internal_assert_no_auction_bids(&nft_contract_id, &token_id);
+			self.internal_take_down_sale(nft_contract_id, token_id);
+		}
+	}
+
+	// panics if the token is listed in an English auction that already has a bid
+	pub(crate) fn internal_assert_no_auction_bids(&self, nft_contract_id: &AccountId, token_id: &TypeTokenId) {
+		if let Some(sale) = self.sales.get(&StructSaleKey::new(nft_contract_id, token_id)) {
+			if let Some(Auction::English(auction)) = sale.auction.as_ref() {
+				assert!(
+					auction.highest_bid().is_none(),
+					"Token is in an auction with bids"
+				);
+			}
+		}
+	}
+
+	// the protocol fee the market takes from a sale at the given price
+	pub(crate) fn internal_protocol_fee(&self, price: U128) -> U128 {
+		U128(price.0 * u128::from(self.protocol_fee_basis_points) / CONST_BASIS_POINTS_DENOMINATOR)
+	}
+
+	/*
+		transfer the token to the buyer with nft_transfer_payout and get the payout object back. The protocol fee and the
+		referral bounty come off the top, the NFT contract splits what is left between the seller and royalties.
+	*/
+	pub(crate) fn internal_nft_transfer_payout(&self, purchase: &StructPurchase, approval_id: u64, fee: U128) -> Promise {
+		ext_contract::nft_transfer_payout(
+			purchase.buyer_id.clone(),         // purchaser (person to transfer the NFT to)
+			purchase.token_id.clone(),         // token ID to transfer
+			approval_id, // market contract's approval ID in order to transfer the token on behalf of the owner
+			"payout from martket".to_string(), // memo (to include some context)
+			/*
+				the price that the token was purchased for, minus the protocol fee and referral bounty. This will be used in
+				conjunction with the royalty percentages for the token in order to determine how much money should go to
+				which account.
+			*/
+			purchase.payout_balance(fee),
+			purchase.max_len_payout(), // the maximum amount of accounts the market can payout at once (this is limited by GAS)
+			purchase.nft_contract_id.clone(), // contract to initiate the cross contract call to
+			1,  // yoctoNEAR to attach to the call
+			CONST_GAS_FOR_NFT_TRANSFER, // GAS to attach to the call
+		)
+	}
+
+	/*
+		transfer the token to the buyer and resolve the purchase. resolve_purchase then pays the accounts in the payout,
+		or refunds the buyer the price if something went wrong. The price is in NEAR, or in the fungible token
+		`ft_token_id` if it is set.
+	*/
+	pub(crate) fn internal_transfer_payout(&self, purchase: StructPurchase, approval_id: u64) -> Promise {
+		let fee = self.internal_protocol_fee(purchase.price);
+
+		self.internal_nft_transfer_payout(&purchase, approval_id, fee)
+			// after the transfer payout has been initiated, we resolve the promise by calling our own resolve_purchase function.
+			// resolve purchase will take the payout object returned from the nft_transfer_payout and actually pay the accounts
+			.then(ext_self::resolve_purchase(
+				purchase,
+				fee,
+				env::current_account_id(), // We are invoking this function on the current contract
+				CONST_NO_DEPOSIT,          // don't attach any deposit
+				CONST_GAS_FOR_ROYALTIES,   // GAS attached to the call to payout royalties
+			))
+	}
 }
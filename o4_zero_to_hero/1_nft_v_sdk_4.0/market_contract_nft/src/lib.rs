@@ -1,27 +1,59 @@
 use near_sdk::{
     assert_one_yocto,
     borsh::{self, BorshDeserialize, BorshSerialize},
-    collections::{LookupMap, UnorderedMap, UnorderedSet},
+    collections::{LookupMap, TreeMap, UnorderedMap, UnorderedSet, Vector},
     env,
     env::STORAGE_PRICE_PER_BYTE,
     ext_contract,
     json_types::{U128, U64},
-    near_bindgen, promise_result_as_success,
+    is_promise_success, near_bindgen, promise_result_as_success,
     serde::{Deserialize, Serialize},
-    AccountId, Balance, BorshStorageKey, CryptoHash, Gas, PanicOnDefault, Promise,
+    AccountId, Balance, BorshStorageKey, CryptoHash, Gas, PanicOnDefault, Promise, PromiseOrValue,
+    PromiseResult,
 };
 use std::collections::HashMap;
+use std::ops::Bound;
 
+use crate::auction::*;
+pub use crate::bundle::*;
 use crate::consts_statics_types::*;
+use crate::events::*;
 use crate::external::*;
 use crate::internal::*;
+pub use crate::collection_offers::*;
+pub use crate::escrow::*;
+pub use crate::ft_callbacks::*;
+pub use crate::migrate::*;
+pub use crate::nft_callbacks::*;
+pub use crate::offers::*;
+pub use crate::referrals::*;
+pub use crate::sales_history::*;
+pub use crate::swap::*;
+pub use crate::verified_collections::*;
 use crate::sale::*;
+mod auction;
+mod bundle;
+mod cart;
+mod collection_offers;
 mod consts_statics_types;
+mod escrow;
+mod events;
 mod external;
+mod ft_callbacks;
 mod internal;
+mod migrate;
 mod nft_callbacks;
+mod offers;
+mod prune;
+mod referrals;
 mod sale;
 mod sale_views;
+mod sales_history;
+mod swap;
+mod treasury;
+mod verified_collections;
+#[cfg(test)]
+mod test_utils;
 
 // Defines the payout type we'll be parsing from the NFT contract as a part of the royalty standard.
 #[derive(Serialize, Deserialize)]
@@ -30,22 +62,105 @@ pub struct Payout {
     pub payout: HashMap<AccountId, U128>,
 }
 
+// NEP-145 storage balance of an account. `available` is what isn't locked by open sales.
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StorageBalanceBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
     pub owner_id: AccountId,
     /*
-        to keep track of the sales, we map the TypeContractAndTokenId to a Sale.
-        the TypeContractAndTokenId is the unique identifier for every sale. It is made
-        up of the `contract ID + DELIMITER + token ID`
+        to keep track of the sales, we map the StructSaleKey to a Sale.
+        the StructSaleKey is the unique identifier for every sale. It is made
+        up of the contract ID and token ID
     */
-    pub sales: UnorderedMap<TypeContractAndTokenId, StructSale>,
-    // keep track of all the Sale IDs for every account ID
-    pub by_owner_id: LookupMap<AccountId, UnorderedSet<TypeContractAndTokenId>>,
+    pub sales: UnorderedMap<StructSaleKey, StructSale>,
+    // keep track of all the sale keys for every account ID
+    pub by_owner_id: LookupMap<AccountId, UnorderedSet<StructSaleKey>>,
     // keep track of all the token IDs for sale for a given contract
     pub by_nft_contract_id: LookupMap<AccountId, UnorderedSet<TypeTokenId>>,
+    // fixed price sales in NEAR for a given contract, sorted by price and then token ID
+    pub by_nft_contract_id_by_price: LookupMap<AccountId, TreeMap<TypePriceAndTokenId, ()>>,
+    // keep track of all the sale keys for a given token type (series) of an NFT contract
+    pub by_nft_token_type: LookupMap<StructTokenTypeKey, UnorderedSet<StructSaleKey>>,
+    // keep track of all the sale keys reserved for a given buyer
+    pub by_reserved_for: LookupMap<AccountId, UnorderedSet<StructSaleKey>>,
     // keep track of the storage that accounts have payed
     pub storage_deposits: LookupMap<AccountId, Balance>,
+    // standing offers, keyed by contract ID, token ID and buyer ID
+    pub offers: UnorderedMap<StructOfferKey, StructOffer>,
+    // keep track of the offer IDs placed on every token (keyed by `contract ID + DELIMITER + token ID`)
+    pub offers_by_token: LookupMap<StructSaleKey, UnorderedSet<StructOfferKey>>,
+    // keep track of the offer IDs placed by every buyer
+    pub offers_by_buyer: LookupMap<AccountId, UnorderedSet<StructOfferKey>>,
+    // bids on any token of an NFT contract, keyed by offer ID
+    pub collection_offers: UnorderedMap<u64, StructCollectionOffer>,
+    // keep track of all the collection offer IDs for a given contract
+    pub collection_offers_by_nft_contract_id: LookupMap<AccountId, UnorderedSet<u64>>,
+    // collection offers sorted by amount, for every contract and token type they accept
+    pub collection_offers_by_price: LookupMap<StructCollectionOfferTypeKey, TreeMap<TypePriceAndOfferId, ()>>,
+    // the ID given to the next collection offer
+    pub next_collection_offer_id: u64,
+    // the NEP-141 fungible tokens sales can be priced in, besides NEAR
+    pub ft_token_ids: UnorderedSet<AccountId>,
+    // the cut the market takes from every sale, in basis points
+    pub protocol_fee_basis_points: u16,
+    // protocol fees collected in NEAR and not withdrawn yet
+    pub treasury_balance: Balance,
+    // protocol fees collected in every fungible token and not withdrawn yet
+    pub ft_treasury_balances: LookupMap<AccountId, Balance>,
+    // the share of the protocol fee paid to the referrer of a purchase, in basis points of the fee
+    pub referral_fee_basis_points: u16,
+    // what every referrer earned, in NEAR
+    pub referral_earnings: LookupMap<AccountId, StructReferralEarnings>,
+    // the NFT contracts the owner verified, with the policy their listings follow
+    pub verified_collections: UnorderedMap<AccountId, StructCollectionPolicy>,
+    // whether NFT contracts that aren't verified can list
+    pub allow_unverified_listings: bool,
+    // the last sales of every collection
+    pub sales_history_by_nft_contract_id: LookupMap<AccountId, Vec<StructSaleRecord>>,
+    // the last sales of every token
+    pub sales_history_by_token: LookupMap<StructSaleKey, Vec<StructSaleRecord>>,
+    // sale counters for every collection, keyed by contract ID. The counters of the whole market use the empty key
+    pub sales_stats: LookupMap<String, StructSalesStats>,
+    // the unverified collections with a sales history, in the order they are replaced once the list is full
+    pub tracked_collections: Vector<AccountId>,
+    // the slot of tracked_collections the next collection goes into, counting up forever
+    pub next_tracked_collection_slot: u64,
+    // the tokens with a sales history, in the order they are replaced once the list is full
+    pub tracked_tokens: Vector<StructSaleKey>,
+    // the slot of tracked_tokens the next token goes into, counting up forever
+    pub next_tracked_token_slot: u64,
+    // several tokens sold together at one price, keyed by bundle ID
+    pub bundles: UnorderedMap<u64, StructBundle>,
+    // keep track of all the bundle IDs for every account ID
+    pub bundles_by_owner_id: LookupMap<AccountId, UnorderedSet<u64>>,
+    // the ID given to the next bundle
+    pub next_bundle_id: u64,
+    // NFT-for-NFT trades waiting for the counterparty, keyed by swap ID
+    pub swaps: UnorderedMap<u64, StructSwap>,
+    // keep track of the swap IDs every account proposed or was asked to accept
+    pub swaps_by_account_id: LookupMap<AccountId, UnorderedSet<u64>>,
+    // the ID given to the next swap
+    pub next_swap_id: u64,
+    // escrowed tokens the market couldn't give back, with the account that can claim them
+    pub unreturned_tokens: UnorderedMap<StructSaleKey, AccountId>,
+    // FT payouts and refunds the market couldn't send, by receiver and fungible token
+    pub unclaimed_ft_payouts: LookupMap<StructFtPayoutKey, Balance>,
+    // the old sale collections of a migrated contract, until all of their sales have been moved over
+    pub legacy_sales: Option<LegacySales>,
 }
 
 #[derive(BorshStorageKey, BorshSerialize)]
@@ -56,9 +171,47 @@ pub enum EnumStorageKey {
     ByNFTContractId,
     ByNFTContractIdInner { account_id_hash: CryptoHash },
     ByNFTTokenType,
-    ByNFTTokenTypeInner { token_type_hash: CryptoHash },
+    ByNFTTokenTypeInner { token_type_key_hash: CryptoHash },
     FTTokenIds,
+    FTTreasuryBalances,
     StorageDeposits,
+    Offers,
+    OffersByToken,
+    OffersByTokenInner { contract_and_token_id_hash: CryptoHash },
+    OffersByBuyer,
+    OffersByBuyerInner { account_id_hash: CryptoHash },
+    CollectionOffers,
+    CollectionOffersByNFTContractId,
+    CollectionOffersByNFTContractIdInner { account_id_hash: CryptoHash },
+    SalesByKey,
+    ByOwnerIdByKey,
+    ByOwnerIdByKeyInner { account_id_hash: CryptoHash },
+    ByNFTContractIdByPrice,
+    ByNFTContractIdByPriceInner { account_id_hash: CryptoHash },
+    SalesHistoryByNFTContractId,
+    SalesHistoryByToken,
+    SalesStats,
+    Bundles,
+    BundlesByOwnerId,
+    BundlesByOwnerIdInner { account_id_hash: CryptoHash },
+    Swaps,
+    SwapsByAccountId,
+    SwapsByAccountIdInner { account_id_hash: CryptoHash },
+    ByReservedFor,
+    ByReservedForInner { account_id_hash: CryptoHash },
+    ReferralEarnings,
+    VerifiedCollections,
+    UnreturnedTokens,
+    OffersByKey,
+    OffersByTokenByKey,
+    OffersByTokenByKeyInner { sale_key_hash: CryptoHash },
+    OffersByBuyerByKey,
+    OffersByBuyerByKeyInner { account_id_hash: CryptoHash },
+    UnclaimedFtPayouts,
+    TrackedCollections,
+    TrackedTokens,
+    CollectionOffersByPrice,
+    CollectionOffersByPriceInner { type_key_hash: CryptoHash },
 }
 
 #[near_bindgen]
@@ -67,63 +220,184 @@ impl Contract {
     pub fn new(owner_id: AccountId) -> Self {
         Self {
             owner_id,
-            sales: UnorderedMap::new(EnumStorageKey::Sales),
-            by_owner_id: LookupMap::new(EnumStorageKey::ByOwnerId),
+            sales: UnorderedMap::new(EnumStorageKey::SalesByKey),
+            by_owner_id: LookupMap::new(EnumStorageKey::ByOwnerIdByKey),
             by_nft_contract_id: LookupMap::new(EnumStorageKey::ByNFTContractId),
+            by_nft_contract_id_by_price: LookupMap::new(EnumStorageKey::ByNFTContractIdByPrice),
+            by_nft_token_type: LookupMap::new(EnumStorageKey::ByNFTTokenType),
+            by_reserved_for: LookupMap::new(EnumStorageKey::ByReservedFor),
             storage_deposits: LookupMap::new(EnumStorageKey::StorageDeposits),
+            offers: UnorderedMap::new(EnumStorageKey::OffersByKey),
+            offers_by_token: LookupMap::new(EnumStorageKey::OffersByTokenByKey),
+            offers_by_buyer: LookupMap::new(EnumStorageKey::OffersByBuyerByKey),
+            collection_offers: UnorderedMap::new(EnumStorageKey::CollectionOffers),
+            collection_offers_by_nft_contract_id: LookupMap::new(
+                EnumStorageKey::CollectionOffersByNFTContractId,
+            ),
+            collection_offers_by_price: LookupMap::new(EnumStorageKey::CollectionOffersByPrice),
+            next_collection_offer_id: 0,
+            ft_token_ids: UnorderedSet::new(EnumStorageKey::FTTokenIds),
+            protocol_fee_basis_points: 0,
+            treasury_balance: 0,
+            ft_treasury_balances: LookupMap::new(EnumStorageKey::FTTreasuryBalances),
+            referral_fee_basis_points: 0,
+            referral_earnings: LookupMap::new(EnumStorageKey::ReferralEarnings),
+            verified_collections: UnorderedMap::new(EnumStorageKey::VerifiedCollections),
+            allow_unverified_listings: true,
+            sales_history_by_nft_contract_id: LookupMap::new(
+                EnumStorageKey::SalesHistoryByNFTContractId,
+            ),
+            sales_history_by_token: LookupMap::new(EnumStorageKey::SalesHistoryByToken),
+            sales_stats: LookupMap::new(EnumStorageKey::SalesStats),
+            tracked_collections: Vector::new(EnumStorageKey::TrackedCollections),
+            next_tracked_collection_slot: 0,
+            tracked_tokens: Vector::new(EnumStorageKey::TrackedTokens),
+            next_tracked_token_slot: 0,
+            bundles: UnorderedMap::new(EnumStorageKey::Bundles),
+            bundles_by_owner_id: LookupMap::new(EnumStorageKey::BundlesByOwnerId),
+            next_bundle_id: 0,
+            swaps: UnorderedMap::new(EnumStorageKey::Swaps),
+            swaps_by_account_id: LookupMap::new(EnumStorageKey::SwapsByAccountId),
+            next_swap_id: 0,
+            unreturned_tokens: UnorderedMap::new(EnumStorageKey::UnreturnedTokens),
+            unclaimed_ft_payouts: LookupMap::new(EnumStorageKey::UnclaimedFtPayouts),
+            legacy_sales: None,
         }
     }
 
-    // Allows users to deposit storage. This is to cover the cost of storing sale objects on the contract
-    // Optional account ID is to users can pay for storage for other people.
-    pub fn storage_deposits(&mut self, account_id: Option<AccountId>) {
+    /*
+        NEP-145 storage deposit. The storage balance covers the market's storage for the account's sales and bundles,
+        each sale locks CONST_STORAGE_PER_SALE and each bundle CONST_STORAGE_PER_BUNDLE. The optional account ID is so users can pay for storage for other people.
+        With registration_only, only the minimum balance is kept for a new account and the rest is refunded.
+    */
+    #[payable]
+    pub fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
         // get the account ID to pay for storage for
-        let storage_account_id = account_id
-            // convert the valid account ID into an account ID
-            .map(|item| item.into())
-            .unwrap_or_else(env::predecessor_account_id);
+        let storage_account_id = account_id.unwrap_or_else(env::predecessor_account_id);
 
         let deposit = env::attached_deposit();
-        assert!(
-            deposit >= CONST_STORAGE_PER_SALE,
-            "Requires minimum deposit of {}",
+        let balance = self.storage_deposits.get(&storage_account_id);
+
+        let amount = if registration_only.unwrap_or(false) {
+            // an account that is already registered gets the whole deposit back
+            let amount = if balance.is_some() { 0 } else { CONST_STORAGE_PER_SALE };
+            assert!(
+                deposit >= amount,
+                "Requires minimum deposit of {}",
+                CONST_STORAGE_PER_SALE
+            );
+            if deposit > amount {
+                Promise::new(env::predecessor_account_id()).transfer(deposit - amount);
+            }
+            amount
+        } else {
+            assert!(
+                balance.is_some() || deposit >= CONST_STORAGE_PER_SALE,
+                "Requires minimum deposit of {}",
+                CONST_STORAGE_PER_SALE
+            );
             deposit
-        );
-        let mut balance: u128 = self.storage_deposits.get(&storage_account_id).unwrap_or(0);
-        balance += deposit;
-        self.storage_deposits.insert(&storage_account_id, &balance);
+        };
+
+        self.storage_deposits
+            .insert(&storage_account_id, &(balance.unwrap_or(0) + amount));
+
+        self.storage_balance_of(storage_account_id).unwrap()
     }
 
-    // Allows users to withdraw any excess storage that they're not using. Say Bob pays 0.01N for 1 sale
-    // Alice then buys Bob's token. This means bob has paid 0.01N for a sale that's no longer on the marketplace
-    // Bob could then withdraw this 0.01N back into his account.
+    /*
+        NEP-145 storage withdraw. Withdraws the given amount, or everything that isn't locked by open sales.
+        Say Bob pays 0.01N for 1 sale and Alice then buys Bob's token. Bob's 0.01N isn't locked anymore
+        and he can withdraw it back into his account.
+    */
     #[payable]
-    pub fn storage_withdraw(&mut self) {
+    pub fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
         // make sure the user attaches exactly 1 yoctoNEAR for security purposes.
         // this will redirect them to the NEAR wallet (or requires a full access key).
         assert_one_yocto();
 
         let owner_id = env::predecessor_account_id();
-        let mut amount = self.storage_deposits.remove(&owner_id).unwrap_or(0);
-        // how many sales is that user taking up currently. This returns a set
-        let sales = self.by_owner_id.get(&owner_id);
-        // get the length of that set.
-        let len = sales.map(|item| item.len()).unwrap_or_default();
-        // how much NEAR is being used up for all the current sales on the account
-        let diff = u128::from(len) * CONST_STORAGE_PER_SALE;
-
-        // the excess to withdraw is the total storage paid - storage being used up.
-        amount -= diff;
-
-        // if that excess to withdraw is > 0, we transfer the amount to the user.
+        let storage_balance = self
+            .storage_balance_of(owner_id.clone())
+            .unwrap_or_else(|| env::panic_str(&format!("The account {} is not registered", owner_id)));
+
+        let amount = amount.unwrap_or(storage_balance.available).0;
+        assert!(
+            amount <= storage_balance.available.0,
+            "The amount is greater than the available storage balance of {}",
+            storage_balance.available.0
+        );
+
         if amount > 0 {
+            self.storage_deposits
+                .insert(&owner_id, &(storage_balance.total.0 - amount));
             Promise::new(owner_id.clone()).transfer(amount);
         }
-        // we need to add back the storage being used up into the map if it's greater than 0.
-        // this is so that if the user had 500 sales on the market, we insert that value here so
-        // if those sales get taken down, the user can then go and withdraw 500 sales worth of storage.
-        if diff > 0 {
-            self.storage_deposits.insert(&owner_id, &diff);
+
+        self.storage_balance_of(owner_id).unwrap()
+    }
+
+    /*
+        NEP-145 storage unregister. Refunds the whole storage balance. An account with open sales or bundles can
+        only unregister with force, which takes all of them down. Returns false if the account wasn't registered.
+    */
+    #[payable]
+    pub fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        assert_one_yocto();
+
+        let owner_id = env::predecessor_account_id();
+        let balance = if let Some(balance) = self.storage_deposits.get(&owner_id) {
+            balance
+        } else {
+            return false;
+        };
+
+        if let Some(sales) = self.by_owner_id.get(&owner_id) {
+            assert!(
+                force.unwrap_or(false),
+                "Can not unregister an account with open sales without force"
+            );
+
+            for sale_key in sales.to_vec() {
+                let sale = self.sales.get(&sale_key).unwrap();
+                // bids are escrowed on the market, so an auction with bids has to be settled first
+                if let Some(Auction::English(auction)) = sale.auction.as_ref() {
+                    assert!(
+                        auction.highest_bid().is_none(),
+                        "Can not remove an auction with bids"
+                    );
+                }
+                // escrowed tokens go back to the owner
+                self.internal_take_down_sale(sale_key.nft_contract_id, sale_key.token_id);
+            }
+        }
+
+        if let Some(bundles) = self.bundles_by_owner_id.get(&owner_id) {
+            assert!(
+                force.unwrap_or(false),
+                "Can not unregister an account with open bundles without force"
+            );
+
+            for bundle_id in bundles.to_vec() {
+                self.internal_remove_bundle(bundle_id);
+            }
+        }
+
+        self.storage_deposits.remove(&owner_id);
+        Promise::new(owner_id).transfer(balance);
+
+        true
+    }
+
+    // NEP-145 storage bounds. An account needs the storage for at least 1 sale and can deposit as much as it wants.
+    pub fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        StorageBalanceBounds {
+            min: U128(CONST_STORAGE_PER_SALE),
+            max: None,
         }
     }
 
@@ -132,8 +406,105 @@ impl Contract {
         U128(CONST_STORAGE_PER_SALE)
     }
 
-    // Return how much storage an account has paid for
-    pub fn storage_balance_of(&self, account_id: AccountId) -> U128 {
-        U128(self.storage_deposits.get(&account_id).unwrap_or(0))
+    // Return how much storage an account has paid for, and how much of it isn't locked by open sales and bundles
+    pub fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        let total = self.storage_deposits.get(&account_id)?;
+        // how much NEAR is being used up for all the current sales and bundles on the account
+        let locked = u128::from(self.get_supply_by_owner_id(account_id.clone()).0) * CONST_STORAGE_PER_SALE
+            + self.internal_bundle_storage_locked(&account_id);
+
+        Some(StorageBalance {
+            total: U128(total),
+            // saturating, since balances withdrawn with the old storage_withdraw can be below what is locked
+            available: U128(total.saturating_sub(locked)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use near_sdk::test_utils::accounts;
+
+    #[test]
+    fn registration_only_keeps_the_minimum_and_refunds_the_rest() {
+        let mut contract = setup();
+
+        set_context(accounts(1), 3 * CONST_STORAGE_PER_SALE);
+        let storage_balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(storage_balance.total, U128(CONST_STORAGE_PER_SALE));
+        assert_eq!(transfers(), vec![(accounts(1), 2 * CONST_STORAGE_PER_SALE)]);
+
+        // an account that is already registered gets the whole deposit back
+        set_context(accounts(1), CONST_STORAGE_PER_SALE);
+        let storage_balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(storage_balance.total, U128(CONST_STORAGE_PER_SALE));
+        assert_eq!(transfers(), vec![(accounts(1), CONST_STORAGE_PER_SALE)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires minimum deposit of")]
+    fn the_first_deposit_has_to_cover_a_sale() {
+        let mut contract = setup();
+
+        set_context(accounts(1), CONST_STORAGE_PER_SALE - 1);
+        contract.storage_deposit(None, None);
+    }
+
+    #[test]
+    fn open_sales_lock_their_storage() {
+        let mut contract = setup();
+        set_context(accounts(1), CONST_STORAGE_PER_SALE);
+        contract.storage_deposit(None, None);
+        list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+
+        let storage_balance = contract.storage_balance_of(accounts(1)).unwrap();
+        assert_eq!(storage_balance.total, U128(2 * CONST_STORAGE_PER_SALE));
+        assert_eq!(storage_balance.available, U128(CONST_STORAGE_PER_SALE));
+
+        // withdrawing without an amount withdraws what isn't locked
+        set_context(accounts(1), 1);
+        let storage_balance = contract.storage_withdraw(None);
+        assert_eq!(storage_balance.total, U128(CONST_STORAGE_PER_SALE));
+        assert_eq!(storage_balance.available, U128(0));
+        assert_eq!(transfers(), vec![(accounts(1), CONST_STORAGE_PER_SALE)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount is greater than the available storage balance of 0")]
+    fn storage_locked_by_a_sale_can_not_be_withdrawn() {
+        let mut contract = setup();
+        list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+
+        set_context(accounts(1), 1);
+        contract.storage_withdraw(Some(U128(1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Can not unregister an account with open sales without force")]
+    fn an_account_with_open_sales_only_unregisters_with_force() {
+        let mut contract = setup();
+        list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+
+        set_context(accounts(1), 1);
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    fn unregistering_with_force_takes_down_the_sales_and_refunds_the_balance() {
+        let mut contract = setup();
+        list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+
+        set_context(accounts(1), 1);
+        assert!(contract.storage_unregister(Some(true)));
+
+        assert!(get_sale(&contract, "1").is_none());
+        assert!(contract.storage_balance_of(accounts(1)).is_none());
+        assert_eq!(transfers(), vec![(accounts(1), CONST_STORAGE_PER_SALE)]);
+
+        // there is nothing left to unregister
+        set_context(accounts(1), 1);
+        assert!(!contract.storage_unregister(None));
     }
 }
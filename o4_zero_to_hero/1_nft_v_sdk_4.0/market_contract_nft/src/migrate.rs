@@ -0,0 +1,225 @@
+use crate::*;
+
+// a sale from before sales had auctions, fungible token prices and the rest of the StructSale fields
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct OldStructSale {
+	pub owner_id: AccountId,
+	pub approval_id: u64,
+	pub nft_contract_id: String,
+	pub token_id: TypeTokenId,
+	pub sale_conditions: TypeSalePriceInYoctoNear,
+}
+
+// the contract state from before sales were keyed by StructSaleKey
+#[derive(BorshDeserialize)]
+pub struct OldContract {
+	pub owner_id: AccountId,
+	pub sales: UnorderedMap<TypeContractAndTokenId, OldStructSale>,
+	pub by_owner_id: LookupMap<AccountId, UnorderedSet<TypeContractAndTokenId>>,
+	pub by_nft_contract_id: LookupMap<AccountId, UnorderedSet<TypeTokenId>>,
+	pub storage_deposits: LookupMap<AccountId, Balance>,
+}
+
+// the old sale collections that still hold sales which haven't been moved over by migrate_sales
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct LegacySales {
+	pub sales: UnorderedMap<TypeContractAndTokenId, OldStructSale>,
+	pub by_owner_id: LookupMap<AccountId, UnorderedSet<TypeContractAndTokenId>>,
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		Switch a contract deployed with the old state over to the new one. Call this right after deploying the new code.
+		The sales themselves are moved from the old `contract ID + DELIMITER + token ID` keys to StructSaleKey in
+		batches by migrate_sales. A sale can't be bought or removed until it was moved.
+	*/
+	#[private]
+	#[init(ignore_state)]
+	pub fn migrate() -> Self {
+		let old: OldContract = env::state_read().expect("Failed to read the old state");
+
+		Self {
+			owner_id: old.owner_id,
+			sales: UnorderedMap::new(EnumStorageKey::SalesByKey),
+			by_owner_id: LookupMap::new(EnumStorageKey::ByOwnerIdByKey),
+			// the token IDs of every contract stay the same, so this index is kept as it is
+			by_nft_contract_id: old.by_nft_contract_id,
+			by_nft_contract_id_by_price: LookupMap::new(EnumStorageKey::ByNFTContractIdByPrice),
+			by_nft_token_type: LookupMap::new(EnumStorageKey::ByNFTTokenType),
+			by_reserved_for: LookupMap::new(EnumStorageKey::ByReservedFor),
+			storage_deposits: old.storage_deposits,
+			offers: UnorderedMap::new(EnumStorageKey::OffersByKey),
+			offers_by_token: LookupMap::new(EnumStorageKey::OffersByTokenByKey),
+			offers_by_buyer: LookupMap::new(EnumStorageKey::OffersByBuyerByKey),
+			collection_offers: UnorderedMap::new(EnumStorageKey::CollectionOffers),
+			collection_offers_by_nft_contract_id: LookupMap::new(EnumStorageKey::CollectionOffersByNFTContractId),
+			collection_offers_by_price: LookupMap::new(EnumStorageKey::CollectionOffersByPrice),
+			next_collection_offer_id: 0,
+			ft_token_ids: UnorderedSet::new(EnumStorageKey::FTTokenIds),
+			protocol_fee_basis_points: 0,
+			treasury_balance: 0,
+			ft_treasury_balances: LookupMap::new(EnumStorageKey::FTTreasuryBalances),
+			referral_fee_basis_points: 0,
+			referral_earnings: LookupMap::new(EnumStorageKey::ReferralEarnings),
+			verified_collections: UnorderedMap::new(EnumStorageKey::VerifiedCollections),
+			allow_unverified_listings: true,
+			sales_history_by_nft_contract_id: LookupMap::new(EnumStorageKey::SalesHistoryByNFTContractId),
+			sales_history_by_token: LookupMap::new(EnumStorageKey::SalesHistoryByToken),
+			sales_stats: LookupMap::new(EnumStorageKey::SalesStats),
+			tracked_collections: Vector::new(EnumStorageKey::TrackedCollections),
+			next_tracked_collection_slot: 0,
+			tracked_tokens: Vector::new(EnumStorageKey::TrackedTokens),
+			next_tracked_token_slot: 0,
+			bundles: UnorderedMap::new(EnumStorageKey::Bundles),
+			bundles_by_owner_id: LookupMap::new(EnumStorageKey::BundlesByOwnerId),
+			next_bundle_id: 0,
+			swaps: UnorderedMap::new(EnumStorageKey::Swaps),
+			swaps_by_account_id: LookupMap::new(EnumStorageKey::SwapsByAccountId),
+			next_swap_id: 0,
+			unreturned_tokens: UnorderedMap::new(EnumStorageKey::UnreturnedTokens),
+			unclaimed_ft_payouts: LookupMap::new(EnumStorageKey::UnclaimedFtPayouts),
+			legacy_sales: Some(LegacySales {
+				sales: old.sales,
+				by_owner_id: old.by_owner_id,
+			}),
+		}
+	}
+
+	/*
+		Move up to `limit` sales (at most MAX_MIGRATE_BATCH_LEN) from the old state over to StructSaleKey and the new
+		indexes. Call this until it returns 0, the number of sales that are left to move. A token that was listed
+		again since the upgrade keeps its new sale. Only the owner of the market can call this.
+	*/
+	pub fn migrate_sales(&mut self, limit: Option<u64>) -> U64 {
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the owner can migrate sales"
+		);
+
+		let mut legacy_sales = self.legacy_sales.take().expect("No sales left to migrate");
+
+		for _ in 0..limit.unwrap_or(MAX_MIGRATE_BATCH_LEN).min(MAX_MIGRATE_BATCH_LEN) {
+			// take the sales from the end so removing them doesn't shuffle the rest of the old collection
+			let len = legacy_sales.sales.len();
+			if len == 0 {
+				break;
+			}
+			let old_sale_id = legacy_sales.sales.keys_as_vector().get(len - 1).unwrap();
+			let old_sale = legacy_sales.sales.remove(&old_sale_id).unwrap();
+
+			// drop the sale from the owner's old set, and the set itself once it is empty
+			if let Some(mut old_by_owner_id) = legacy_sales.by_owner_id.get(&old_sale.owner_id) {
+				old_by_owner_id.remove(&old_sale_id);
+				if old_by_owner_id.is_empty() {
+					legacy_sales.by_owner_id.remove(&old_sale.owner_id);
+				} else {
+					legacy_sales.by_owner_id.insert(&old_sale.owner_id, &old_by_owner_id);
+				}
+			}
+
+			let sale_key = StructSaleKey::new(&AccountId::new_unchecked(old_sale.nft_contract_id.clone()), &old_sale.token_id);
+			if self.sales.get(&sale_key).is_some() {
+				continue;
+			}
+
+			self.internal_add_sale(
+				&sale_key,
+				&StructSale {
+					owner_id: old_sale.owner_id,
+					approval_id: old_sale.approval_id,
+					nft_contract_id: old_sale.nft_contract_id,
+					token_id: old_sale.token_id,
+					sale_conditions: old_sale.sale_conditions,
+					auction: None,
+					ft_token_id: None,
+					token_type: None,
+					escrowed: false,
+					referral_bounty_basis_points: 0,
+					reserved_for: None,
+					expires_at: None,
+				},
+			);
+		}
+
+		let remaining = legacy_sales.sales.len();
+		if remaining > 0 {
+			self.legacy_sales = Some(legacy_sales);
+		}
+
+		U64(remaining)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::accounts;
+
+	fn old_sale(nft_contract_id: &str, token_id: &str) -> OldStructSale {
+		OldStructSale {
+			owner_id: accounts(1),
+			approval_id: 0,
+			nft_contract_id: nft_contract_id.to_string(),
+			token_id: token_id.to_string(),
+			sale_conditions: U128(100),
+		}
+	}
+
+	// a market that was just migrated, with sales left under their old `contract ID + DELIMITER + token ID` keys
+	fn setup_legacy_sales(old_sales: Vec<OldStructSale>) -> Contract {
+		let mut contract = setup();
+		let mut legacy_sales = LegacySales {
+			sales: UnorderedMap::new(b"legacy_sales".to_vec()),
+			by_owner_id: LookupMap::new(b"legacy_by_owner_id".to_vec()),
+		};
+		let mut old_by_owner_id = UnorderedSet::new(b"legacy_by_owner_id_inner".to_vec());
+		for old_sale in old_sales {
+			let old_sale_id = format!("{}{}{}", old_sale.nft_contract_id, STATIC_DELIMITER, old_sale.token_id);
+			old_by_owner_id.insert(&old_sale_id);
+			legacy_sales.sales.insert(&old_sale_id, &old_sale);
+		}
+		legacy_sales.by_owner_id.insert(&accounts(1), &old_by_owner_id);
+		contract.legacy_sales = Some(legacy_sales);
+
+		contract
+	}
+
+	#[test]
+	fn sales_are_moved_to_their_new_keys_in_batches() {
+		let mut contract = setup_legacy_sales(vec![old_sale("nft", "1"), old_sale("nft", "2")]);
+
+		set_context(owner_id(), 0);
+		assert_eq!(contract.migrate_sales(Some(1)), U64(1));
+		assert_eq!(contract.get_supply_sales(), U64(1));
+
+		set_context(owner_id(), 0);
+		assert_eq!(contract.migrate_sales(None), U64(0));
+		assert!(contract.legacy_sales.is_none());
+		assert_eq!(get_sale(&contract, "1").unwrap().sale_conditions, U128(100));
+		assert_eq!(get_sale(&contract, "2").unwrap().sale_conditions, U128(100));
+		assert_eq!(contract.get_supply_by_owner_id(accounts(1)), U64(2));
+	}
+
+	#[test]
+	fn a_token_listed_again_since_the_upgrade_keeps_its_new_sale() {
+		let mut contract = setup_legacy_sales(vec![old_sale("nft", "1")]);
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"500"}"#);
+
+		set_context(owner_id(), 0);
+		contract.migrate_sales(None);
+
+		assert_eq!(get_sale(&contract, "1").unwrap().sale_conditions, U128(500));
+	}
+
+	#[test]
+	#[should_panic(expected = "Only the owner can migrate sales")]
+	fn only_the_owner_can_migrate_sales() {
+		let mut contract = setup_legacy_sales(vec![old_sale("nft", "1")]);
+
+		set_context(accounts(1), 0);
+		contract.migrate_sales(None);
+	}
+}
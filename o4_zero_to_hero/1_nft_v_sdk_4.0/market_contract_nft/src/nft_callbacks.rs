@@ -4,6 +4,16 @@ use crate::*;
 #[serde(crate = "near_sdk::serde")]
 pub struct SaleArgs {
 	pub sale_conditions: TypeSalePriceInYoctoNear,
+	// list the token as an auction instead of at a fixed price
+	pub auction: Option<AuctionArgs>,
+	// price the sale in one of the accepted fungible tokens instead of NEAR
+	pub ft_token_id: Option<AccountId>,
+	// only let this account buy the token, for deals arranged off the market
+	pub reserved_for: Option<AccountId>,
+	// when the sale stops being valid, in nanoseconds
+	pub expires_at: Option<U64>,
+	// the part of the price paid to whoever referred the buyer, in basis points
+	pub referral_bounty_basis_points: Option<u16>,
 }
 
 /*
@@ -11,7 +21,7 @@ pub struct SaleArgs {
 	called, it will fire a cross contract call to this marketplace and this is the function
 	that is invoked.
 */
-trait NonFungibleTokenApprovalsReceiver {
+pub trait NonFungibleTokenApprovalsReceiver {
 	fn nft_on_approve(
 		&mut self,
 		token_id: TypeTokenId,
@@ -43,17 +53,65 @@ impl NonFungibleTokenApprovalsReceiver for Contract {
 		// Make sure the owner ID is the signer.
 		assert_eq!(owner_id, signer_id, "owner_id should be signer_id");
 
+		// the owner can approve the market to accept a standing offer instead of listing the token
+		if let Ok(AcceptOfferArgs { accept_offer }) = near_sdk::serde_json::from_str(&msg) {
+			self.internal_accept_offer(nft_contract_id, token_id, owner_id, approval_id, accept_offer);
+			return;
+		}
+
+		// or to sell the token into a collection offer
+		if let Ok(FillCollectionOfferArgs {
+			fill_collection_offer,
+		}) = near_sdk::serde_json::from_str(&msg)
+		{
+			self.internal_fill_collection_offer(
+				nft_contract_id,
+				token_id,
+				owner_id,
+				approval_id,
+				fill_collection_offer,
+			);
+			return;
+		}
+
+		// or only to put the token into a bundle with list_bundle, which refers to this approval
+		if let Ok(BundleApprovalArgs { bundle: true }) = near_sdk::serde_json::from_str(&msg) {
+			env::log_str(&format!(
+				"Token {} of {} approved for a bundle with approval ID {}",
+				token_id, nft_contract_id, approval_id
+			));
+			return;
+		}
+
+		self.internal_list_sale(nft_contract_id, token_id, owner_id, approval_id, &msg, false);
+	}
+}
+
+impl Contract {
+	/*
+		create the sale for a token from the SaleArgs in `msg`. Called from nft_on_approve, and from nft_on_transfer for
+		escrowed sales where the market holds the token itself.
+	*/
+	pub(crate) fn internal_list_sale(
+		&mut self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		owner_id: AccountId,
+		approval_id: u64,
+		msg: &str,
+		escrowed: bool,
+	) {
 		// We need to enforce that the user has enough storage for 1 EXTRA sale.
 
 		// Get the storage for a sale. dot 0 converts from U128 to u128
 		let storage_amount = self.storage_minimum_balance().0;
 
 		// Get the total storage paid by the owner
-		let owner_paid_storage = self.storage_deposits.get(&signer_id).unwrap_or(0);
+		let owner_paid_storage = self.storage_deposits.get(&owner_id).unwrap_or(0);
 
-		// Get the storage required which is simply the storage for the number of sales they have + 1
-		let signer_storage_required =
-			(self.get_supply_by_owner_id(signer_id).0 + 1) as u128 * storage_amount;
+		// Get the storage required which is simply the storage for the number of sales they have + 1, plus their bundles
+		let signer_storage_required = (self.get_supply_by_owner_id(owner_id.clone()).0 + 1) as u128 * storage_amount
+			+ self.internal_bundle_storage_locked(&owner_id);
 
 		// Make sure that the total paid is >= the required storage
 		assert!(
@@ -65,57 +123,237 @@ impl NonFungibleTokenApprovalsReceiver for Contract {
 		);
 
 		// if all these checks pass we can create the sale conditions object.
-		let SaleArgs { sale_conditions } =
-			near_sdk::serde_json::from_str(&msg).expect("No valid SaleArgs");
+		let SaleArgs {
+			sale_conditions,
+			auction,
+			ft_token_id,
+			reserved_for,
+			expires_at,
+			referral_bounty_basis_points,
+		} = near_sdk::serde_json::from_str(msg).expect("No valid SaleArgs");
+		let referral_bounty_basis_points = referral_bounty_basis_points.unwrap_or(0);
+		assert!(
+			referral_bounty_basis_points <= MAX_REFERRAL_BOUNTY_BASIS_POINTS,
+			"Referral bounty can't be more than {} basis points",
+			MAX_REFERRAL_BOUNTY_BASIS_POINTS
+		);
 
-		// Create the unique sale ID which is the contract + DELIMITER + token ID
+		if let Some(expires_at) = expires_at {
+			assert!(
+				expires_at.0 > env::block_timestamp(),
+				"Sale must expire in the future"
+			);
+		}
+		assert_ne!(
+			reserved_for.as_ref(),
+			Some(&owner_id),
+			"Can not reserve a sale for yourself"
+		);
+
+		// verified collections can restrict the currency and price and charge a listing fee out of the storage balance
+		let listing_fee = self.internal_assert_listing_policy(&nft_contract_id, sale_conditions, ft_token_id.as_ref());
+		if listing_fee > 0 {
+			assert!(
+				owner_paid_storage >= signer_storage_required + listing_fee,
+				"Insufficient storage paid: {}, for the storage of the sale plus a listing fee of {}",
+				owner_paid_storage,
+				listing_fee
+			);
+			self.storage_deposits
+				.insert(&owner_id, &(owner_paid_storage - listing_fee));
+			self.internal_collect_fee(None, listing_fee);
+		}
+
+		if let Some(ft_token_id) = ft_token_id.as_ref() {
+			assert!(
+				self.ft_token_ids.contains(ft_token_id),
+				"Fungible token {} is not accepted",
+				ft_token_id
+			);
+			// bids on English auctions are escrowed in NEAR
+			assert!(
+				!matches!(auction, Some(AuctionArgs::English { .. })),
+				"English auctions can only be priced in NEAR"
+			);
+		}
+
+		// Create the unique sale key from the contract and token ID
 		// The sale conditions come from the msg field. The market assumes that the user passed in a proper msg. If they didn't, it panics.
-		let contract_and_token_id = format!("{}{}{}", nft_contract_id, STATIC_DELIMITER, token_id);
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
 
-		self.sales.insert(
-			&contract_and_token_id,
-			&StructSale {
-				owner_id: owner_id.clone(),
-				approval_id,
+		// re-approving replaces the sale, so the old one is taken out of every index first. That isn't allowed while
+		// an auction holds escrowed bids
+		self.internal_remove_sale_if_listed(nft_contract_id.clone(), token_id.clone());
+
+		let sale = StructSale {
+			owner_id: owner_id.clone(),
+			approval_id,
+			nft_contract_id: nft_contract_id.to_string(),
+			token_id: token_id.clone(),
+			sale_conditions,
+			auction: auction.map(|auction| auction.into_auction(sale_conditions)),
+			ft_token_id,
+			// filled in by resolve_sale_token_type, the seller can't pick the series the sale is browsed under
+			token_type: None,
+			escrowed,
+			referral_bounty_basis_points,
+			reserved_for,
+			expires_at,
+		};
+		self.internal_add_sale(&sale_key, &sale);
+
+		let sale_listed_log: EventLog = EventLog {
+			standard: CONST_MARKET_STANDARD_NAME.to_string(),
+			version: CONST_MARKET_EVENT_VERSION.to_string(),
+			event: EventLogVariant::SaleListed(vec![SaleListedLog {
+				owner_id: owner_id.to_string(),
 				nft_contract_id: nft_contract_id.to_string(),
 				token_id: token_id.clone(),
+				approval_id,
 				sale_conditions,
-			},
+				ft_token_id: sale.ft_token_id.as_ref().map(|ft_token_id| ft_token_id.to_string()),
+				auction: sale.auction.as_ref().map(|auction| match auction {
+					Auction::English(_) => "english".to_string(),
+					Auction::Dutch(_) => "dutch".to_string(),
+				}),
+				escrowed,
+				reserved_for: sale.reserved_for.as_ref().map(|reserved_for| reserved_for.to_string()),
+				expires_at,
+				referral_bounty_basis_points,
+			}]),
+		};
+
+		env::log_str(&sale_listed_log.to_string());
+
+		// ask the NFT contract for the token type (series) of the token, so the sale can be browsed by series
+		ext_contract::nft_token(token_id.clone(), nft_contract_id.clone(), CONST_NO_DEPOSIT, CONST_GAS_FOR_NFT_TOKEN).then(
+			ext_self::resolve_sale_token_type(
+				nft_contract_id,
+				token_id,
+				approval_id,
+				env::current_account_id(),
+				CONST_NO_DEPOSIT,
+				CONST_GAS_FOR_RESOLVE_SALE_TOKEN_TYPE,
+			),
 		);
+	}
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		private callback for the nft_token call of internal_list_sale. If the sale is still the one that was listed, it
+		is indexed under the token type the NFT contract reported, if any.
+	*/
+	#[private]
+	pub fn resolve_sale_token_type(&mut self, nft_contract_id: AccountId, token_id: TypeTokenId, approval_id: u64) {
+		let token_type = promise_result_as_success()
+			.and_then(|value| {
+				near_sdk::serde_json::from_slice::<Option<JsonTokenType>>(&value)
+					.ok()
+					.flatten()
+			})
+			.and_then(|token| token.token_type);
+		let token_type = if let Some(token_type) = token_type {
+			token_type
+		} else {
+			return;
+		};
+
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
+		let mut sale = if let Some(sale) = self.sales.get(&sale_key) {
+			sale
+		} else {
+			return;
+		};
+		// the sale was taken down or listed again in the meantime
+		if sale.approval_id != approval_id || sale.token_type.is_some() {
+			return;
+		}
+
+		sale.token_type = Some(token_type);
+		self.sales.insert(&sale_key, &sale);
+		self.internal_add_sale_to_token_type_index(&sale_key, &sale);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::serde_json::json;
+	use near_sdk::test_utils::accounts;
+
+	// the result of nft_token for a token of the given token type
+	fn token_result(token_type: Option<&str>) -> PromiseResult {
+		json_result(&json!({ "token_id": "1", "owner_id": "bob", "token_type": token_type }))
+	}
+
+	fn token_type_ids(contract: &Contract, token_type: &str) -> Vec<TypeTokenId> {
+		contract
+			.get_sales_by_nft_token_type(nft_id(), token_type.to_string(), None, None)
+			.into_iter()
+			.map(|sale| sale.sale.token_id)
+			.collect()
+	}
+
+	#[test]
+	fn a_listed_sale_is_indexed_under_the_token_type_the_nft_contract_reports() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+		assert!(function_calls().contains(&(nft_id(), "nft_token".to_string())));
+		assert_eq!(get_sale(&contract, "1").unwrap().token_type, None);
+
+		set_callback_context(vec![token_result(Some("series"))]);
+		contract.resolve_sale_token_type(nft_id(), "1".to_string(), 0);
+
+		assert_eq!(get_sale(&contract, "1").unwrap().token_type, Some("series".to_string()));
+		assert_eq!(contract.get_supply_by_nft_token_type(nft_id(), "series".to_string()), U64(1));
+		assert_eq!(token_type_ids(&contract, "series"), vec!["1".to_string()]);
+		// token types are only unique within their NFT contract
+		assert_eq!(contract.get_supply_by_nft_token_type("other".parse().unwrap(), "series".to_string()), U64(0));
+	}
+
+	#[test]
+	fn a_token_without_a_token_type_is_not_indexed() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+
+		set_callback_context(vec![token_result(None)]);
+		contract.resolve_sale_token_type(nft_id(), "1".to_string(), 0);
+
+		assert_eq!(get_sale(&contract, "1").unwrap().token_type, None);
+	}
+
+	#[test]
+	fn a_sale_taken_down_or_listed_again_in_the_meantime_is_not_indexed() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+		list(&mut contract, accounts(1), "2", r#"{"sale_conditions":"100"}"#);
+
+		set_context(accounts(1), 1);
+		contract.remove_sale(nft_id(), "1".to_string());
+		set_callback_context(vec![token_result(Some("series"))]);
+		contract.resolve_sale_token_type(nft_id(), "1".to_string(), 0);
+
+		// the callback is for approval ID 1, the sale is still the one listed with approval ID 0
+		set_callback_context(vec![token_result(Some("series"))]);
+		contract.resolve_sale_token_type(nft_id(), "2".to_string(), 1);
+
+		assert_eq!(contract.get_supply_by_nft_token_type(nft_id(), "series".to_string()), U64(0));
+	}
+
+	#[test]
+	fn removing_a_sale_removes_it_from_its_token_type() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+		set_callback_context(vec![token_result(Some("series"))]);
+		contract.resolve_sale_token_type(nft_id(), "1".to_string(), 0);
+
+		set_context(accounts(1), 1);
+		contract.remove_sale(nft_id(), "1".to_string());
 
-		// Extra functionality that populates collections necessary for the view calls
-		// Get the sales by owner ID for the given owner. If there are none, we create a new empty set
-		let mut by_owner_id = self.by_owner_id.get(&owner_id).unwrap_or_else(|| {
-			UnorderedSet::new(
-				EnumStorageKey::ByOwnerIdInner {
-					account_id_hash: hash_account_id(&owner_id),
-				}
-				.try_to_vec()
-				.unwrap(),
-			)
-		});
-		// insert the unique sale ID into the set
-		by_owner_id.insert(&contract_and_token_id);
-		// insert that set back into the collection for the owner
-		self.by_owner_id.insert(&owner_id, &by_owner_id);
-
-		// Get the token IDs for the given nft contract ID. If there are none, we create a new empty set
-		let mut by_nft_contract_id = self
-			.by_nft_contract_id
-			.get(&nft_contract_id)
-			.unwrap_or_else(|| {
-				UnorderedSet::new(
-					EnumStorageKey::ByNFTContractIdInner {
-						account_id_hash: hash_account_id(&nft_contract_id),
-					}
-					.try_to_vec()
-					.unwrap(),
-				)
-			});
-
-		// insert the token ID into the set
-		by_nft_contract_id.insert(&token_id);
-		self.by_nft_contract_id
-			.insert(&nft_contract_id, &by_nft_contract_id);
+		assert_eq!(contract.get_supply_by_nft_token_type(nft_id(), "series".to_string()), U64(0));
+		assert!(token_type_ids(&contract, "series").is_empty());
 	}
 }
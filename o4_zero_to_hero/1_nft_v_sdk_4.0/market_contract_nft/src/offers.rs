@@ -0,0 +1,375 @@
+use crate::*;
+
+/*
+	A standing offer from a buyer on any token, listed or not. The offered amount is escrowed on the market
+	together with CONST_STORAGE_PER_OFFER to cover the storage, both are returned when the offer goes away.
+*/
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructOffer {
+	pub buyer_id: AccountId,
+	pub nft_contract_id: AccountId,
+	pub token_id: TypeTokenId,
+	pub amount: U128,
+	// when the offer stops being valid, in nanoseconds. None means it never expires
+	pub expires_at: Option<U64>,
+}
+
+impl StructOffer {
+	pub fn is_expired(&self) -> bool {
+		self.expires_at
+			.map(|expires_at| env::block_timestamp() >= expires_at.0)
+			.unwrap_or(false)
+	}
+}
+
+// msg passed to nft_approve by the token owner to accept a standing offer instead of listing the token
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AcceptOfferArgs {
+	pub accept_offer: AcceptOffer,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AcceptOffer {
+	pub buyer_id: AccountId,
+	// the amount the owner expects, so the buyer can't lower the offer while the approval is in flight
+	pub amount: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		Place an escrowed offer on any token. The attached deposit must cover the amount plus the storage for the
+		offer. Placing a new offer on the same token replaces and refunds the previous one.
+	*/
+	#[payable]
+	pub fn place_offer(
+		&mut self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		amount: U128,
+		expires_at: Option<U64>,
+	) {
+		let deposit = env::attached_deposit();
+		assert!(amount.0 > 0, "Offer amount must be greater than 0");
+		assert!(
+			deposit >= amount.0 + CONST_STORAGE_PER_OFFER,
+			"Attached deposit must cover the amount plus {} for storage",
+			CONST_STORAGE_PER_OFFER
+		);
+		if let Some(expires_at) = expires_at {
+			assert!(
+				expires_at.0 > env::block_timestamp(),
+				"Offer must expire in the future"
+			);
+		}
+
+		let buyer_id = env::predecessor_account_id();
+		let offer_key = StructOfferKey::new(&nft_contract_id, &token_id, &buyer_id);
+
+		// replace any previous offer from the buyer on the same token and give the escrow back to whoever placed it
+		if self.offers.get(&offer_key).is_some() {
+			let previous_offer = self.internal_remove_offer(&offer_key);
+			Promise::new(previous_offer.buyer_id)
+				.transfer(previous_offer.amount.0 + CONST_STORAGE_PER_OFFER);
+		}
+
+		// refund anything attached on top of the amount and storage
+		let refund = deposit - amount.0 - CONST_STORAGE_PER_OFFER;
+		if refund > 0 {
+			Promise::new(buyer_id.clone()).transfer(refund);
+		}
+
+		self.offers.insert(
+			&offer_key,
+			&StructOffer {
+				buyer_id: buyer_id.clone(),
+				nft_contract_id: nft_contract_id.clone(),
+				token_id: token_id.clone(),
+				amount,
+				expires_at,
+			},
+		);
+
+		self.internal_add_offer_to_indexes(offer_key);
+	}
+
+	/*
+		Withdraw an offer and get the escrow back. The buyer can withdraw at any time, anyone else can only clean
+		up offers that have expired. The escrow always goes back to the buyer.
+	*/
+	#[payable]
+	pub fn withdraw_offer(&mut self, nft_contract_id: AccountId, token_id: TypeTokenId, buyer_id: AccountId) {
+		assert_one_yocto();
+
+		let offer = self.internal_remove_offer(&StructOfferKey::new(&nft_contract_id, &token_id, &buyer_id));
+
+		assert!(
+			env::predecessor_account_id() == offer.buyer_id || offer.is_expired(),
+			"Only the buyer can withdraw an offer that hasn't expired"
+		);
+
+		Promise::new(offer.buyer_id).transfer(offer.amount.0 + CONST_STORAGE_PER_OFFER);
+	}
+
+	pub fn get_offer(
+		&self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		buyer_id: AccountId,
+	) -> Option<StructOffer> {
+		self.offers
+			.get(&StructOfferKey::new(&nft_contract_id, &token_id, &buyer_id))
+	}
+
+	// Returns paginated offers placed on a token
+	pub fn get_offers_by_token(
+		&self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		from_index: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<StructOffer> {
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
+
+		let offer_keys = if let Some(offer_keys) = self.offers_by_token.get(&sale_key) {
+			offer_keys
+		} else {
+			return vec![];
+		};
+
+		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+		let start = u128::from(from_index.unwrap_or(U128(0)));
+
+		offer_keys
+			.as_vector()
+			.iter()
+			.skip(start as usize)
+			.take(limit.unwrap_or(CONST_DEFAULT_PAGE_LIMIT) as usize)
+			.map(|offer_key| self.offers.get(&offer_key).unwrap())
+			.collect()
+	}
+
+	// Returns paginated offers placed by a buyer
+	pub fn get_offers_by_buyer(
+		&self,
+		account_id: AccountId,
+		from_index: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<StructOffer> {
+		let offer_keys = if let Some(offer_keys) = self.offers_by_buyer.get(&account_id) {
+			offer_keys
+		} else {
+			return vec![];
+		};
+
+		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+		let start = u128::from(from_index.unwrap_or(U128(0)));
+
+		offer_keys
+			.as_vector()
+			.iter()
+			.skip(start as usize)
+			.take(limit.unwrap_or(CONST_DEFAULT_PAGE_LIMIT) as usize)
+			.map(|offer_key| self.offers.get(&offer_key).unwrap())
+			.collect()
+	}
+}
+
+impl Contract {
+	// add an offer that is already in `offers` to the by token and by buyer indexes
+	pub(crate) fn internal_add_offer_to_indexes(&mut self, offer_key: StructOfferKey) {
+		let sale_key = StructSaleKey::new(&offer_key.nft_contract_id, &offer_key.token_id);
+		let mut by_token = self.offers_by_token.get(&sale_key).unwrap_or_else(|| {
+			UnorderedSet::new(
+				EnumStorageKey::OffersByTokenByKeyInner {
+					sale_key_hash: hash_sale_key(&sale_key),
+				}
+				.try_to_vec()
+				.unwrap(),
+			)
+		});
+		by_token.insert(&offer_key);
+		self.offers_by_token.insert(&sale_key, &by_token);
+
+		let mut by_buyer = self.offers_by_buyer.get(&offer_key.buyer_id).unwrap_or_else(|| {
+			UnorderedSet::new(
+				EnumStorageKey::OffersByBuyerByKeyInner {
+					account_id_hash: hash_account_id(&offer_key.buyer_id),
+				}
+				.try_to_vec()
+				.unwrap(),
+			)
+		});
+		by_buyer.insert(&offer_key);
+		self.offers_by_buyer.insert(&offer_key.buyer_id, &by_buyer);
+	}
+
+	// remove an offer and its index entries. Returns the removed offer, the caller takes care of the escrow.
+	pub(crate) fn internal_remove_offer(&mut self, offer_key: &StructOfferKey) -> StructOffer {
+		let offer = self.offers.remove(offer_key).expect("No offer");
+
+		let sale_key = StructSaleKey::new(&offer.nft_contract_id, &offer.token_id);
+		let mut by_token = self
+			.offers_by_token
+			.get(&sale_key)
+			.expect("No offers by token");
+		by_token.remove(offer_key);
+		if by_token.is_empty() {
+			self.offers_by_token.remove(&sale_key);
+		} else {
+			self.offers_by_token.insert(&sale_key, &by_token);
+		}
+
+		let mut by_buyer = self
+			.offers_by_buyer
+			.get(&offer.buyer_id)
+			.expect("No offers by buyer");
+		by_buyer.remove(offer_key);
+		if by_buyer.is_empty() {
+			self.offers_by_buyer.remove(&offer.buyer_id);
+		} else {
+			self.offers_by_buyer.insert(&offer.buyer_id, &by_buyer);
+		}
+
+		offer
+	}
+
+	/*
+		called from nft_on_approve when the owner approves the market with an accept_offer msg. The offer is
+		removed and the token is bought with the escrowed amount through nft_transfer_payout + resolve_purchase.
+	*/
+	pub(crate) fn internal_accept_offer(
+		&mut self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		owner_id: AccountId,
+		approval_id: u64,
+		accept_offer: AcceptOffer,
+	) -> Promise {
+		let offer = self.internal_remove_offer(&StructOfferKey::new(&nft_contract_id, &token_id, &accept_offer.buyer_id));
+
+		assert!(!offer.is_expired(), "Offer has expired");
+		assert_eq!(
+			offer.amount, accept_offer.amount,
+			"Offer amount doesn't match the accepted amount"
+		);
+		assert_ne!(offer.buyer_id, owner_id, "Can not accept your own offer");
+
+		// the buyer gets their storage deposit back now, the amount is paid out after the transfer
+		Promise::new(offer.buyer_id.clone()).transfer(CONST_STORAGE_PER_OFFER);
+
+		// bidders on an open auction of the token can't be outbid by an offer. Any other listing is taken down in
+		// resolve_purchase once the token changed hands
+		self.internal_assert_no_auction_bids(&nft_contract_id, &token_id);
+
+		self.internal_transfer_payout(
+			StructPurchase {
+				buyer_id: offer.buyer_id,
+				seller_id: owner_id,
+				nft_contract_id,
+				token_id,
+				price: offer.amount,
+				ft_token_id: None,
+				referrer_id: None,
+				referral_bounty: U128(0),
+			},
+			approval_id,
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::accounts;
+
+	fn place_offer(contract: &mut Contract, buyer_id: AccountId, amount: u128, expires_at: Option<U64>) {
+		set_context(buyer_id, amount + CONST_STORAGE_PER_OFFER);
+		contract.place_offer(nft_id(), "1".to_string(), U128(amount), expires_at);
+	}
+
+	fn accept_offer_msg(buyer_id: &AccountId, amount: u128) -> String {
+		format!(r#"{{"accept_offer":{{"buyer_id":"{}","amount":"{}"}}}}"#, buyer_id, amount)
+	}
+
+	#[test]
+	fn a_new_offer_replaces_and_refunds_the_previous_one() {
+		let mut contract = setup();
+
+		place_offer(&mut contract, accounts(2), 100, None);
+		place_offer(&mut contract, accounts(2), 200, None);
+
+		assert_eq!(transfers(), vec![(accounts(2), 100 + CONST_STORAGE_PER_OFFER)]);
+		let offers = contract.get_offers_by_token(nft_id(), "1".to_string(), None, None);
+		assert_eq!(offers.len(), 1);
+		assert_eq!(offers[0].amount, U128(200));
+		assert_eq!(contract.get_offers_by_buyer(accounts(2), None, None).len(), 1);
+	}
+
+	#[test]
+	#[should_panic(expected = "Only the buyer can withdraw an offer that hasn't expired")]
+	fn only_the_buyer_can_withdraw_an_offer_before_it_expires() {
+		let mut contract = setup();
+		place_offer(&mut contract, accounts(2), 100, Some(U64(1_000)));
+
+		set_context_at(accounts(3), 1, 999);
+		contract.withdraw_offer(nft_id(), "1".to_string(), accounts(2));
+	}
+
+	#[test]
+	fn anyone_can_withdraw_an_expired_offer_for_the_buyer() {
+		let mut contract = setup();
+		place_offer(&mut contract, accounts(2), 100, Some(U64(1_000)));
+
+		set_context_at(accounts(3), 1, 1_000);
+		contract.withdraw_offer(nft_id(), "1".to_string(), accounts(2));
+
+		assert_eq!(transfers(), vec![(accounts(2), 100 + CONST_STORAGE_PER_OFFER)]);
+		assert!(contract.get_offer(nft_id(), "1".to_string(), accounts(2)).is_none());
+	}
+
+	#[test]
+	fn an_accepted_offer_takes_down_the_listing_once_the_purchase_went_through() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"1000"}"#);
+		place_offer(&mut contract, accounts(2), 100, None);
+
+		approve(&mut contract, accounts(1), "1", &accept_offer_msg(&accounts(2), 100));
+		assert!(function_calls().contains(&(nft_id(), "nft_transfer_payout".to_string())));
+		assert!(contract.get_offer(nft_id(), "1".to_string(), accounts(2)).is_none());
+		// the listing stays up until the token changed hands
+		assert!(get_sale(&contract, "1").is_some());
+
+		set_callback_context(vec![payout_result(&[(accounts(1), 100)])]);
+		contract.resolve_purchase(purchase(accounts(2), accounts(1), "1", 100), U128(0));
+		assert_eq!(transfers(), vec![(accounts(1), 100)]);
+		assert!(get_sale(&contract, "1").is_none());
+	}
+
+	#[test]
+	fn a_failed_accepted_offer_keeps_the_listing_and_refunds_the_buyer() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"1000"}"#);
+		place_offer(&mut contract, accounts(2), 100, None);
+		approve(&mut contract, accounts(1), "1", &accept_offer_msg(&accounts(2), 100));
+
+		set_callback_context(vec![PromiseResult::Failed]);
+		contract.resolve_purchase(purchase(accounts(2), accounts(1), "1", 100), U128(0));
+
+		assert_eq!(transfers(), vec![(accounts(2), 100)]);
+		assert!(get_sale(&contract, "1").is_some());
+	}
+
+	#[test]
+	#[should_panic(expected = "Offer amount doesn't match the accepted amount")]
+	fn an_offer_can_not_be_accepted_at_another_amount() {
+		let mut contract = setup();
+		place_offer(&mut contract, accounts(2), 100, None);
+
+		approve(&mut contract, accounts(1), "1", &accept_offer_msg(&accounts(2), 200));
+	}
+}
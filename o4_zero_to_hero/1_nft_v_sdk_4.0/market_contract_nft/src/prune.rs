@@ -0,0 +1,277 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+	/*
+		Check with the NFT contract that the market still holds the approval a sale was listed with. If the token was
+		transferred or the approval revoked, the sale is removed. Expired sales are removed right away, an escrowed
+		token goes back to the seller. Anyone can call this, the caller gets CONST_PRUNE_REWARD out of the seller's
+		storage balance. Resolves to true if the sale was removed.
+	*/
+	pub fn prune_sale(&mut self, nft_contract_id: AccountId, token_id: TypeTokenId) -> PromiseOrValue<bool> {
+		self.internal_prune_sale(nft_contract_id, token_id)
+	}
+
+	/*
+		Check up to MAX_PRUNE_BATCH_LEN sales of an NFT contract at once, see prune_sale. Sales that can't be pruned
+		(not listed, escrowed and not expired, or expired auctions with bids) are skipped instead of failing the batch.
+	*/
+	pub fn prune_sales(&mut self, nft_contract_id: AccountId, token_ids: Vec<TypeTokenId>) {
+		assert!(
+			token_ids.len() <= MAX_PRUNE_BATCH_LEN,
+			"Can not prune more than {} sales at once",
+			MAX_PRUNE_BATCH_LEN
+		);
+
+		for token_id in token_ids {
+			let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
+			let sale = if let Some(sale) = self.sales.get(&sale_key) {
+				sale
+			} else {
+				env::log_str(&format!("Skipping {}: no sale", token_id));
+				continue;
+			};
+			if let Err(reason) = sale.check_can_be_pruned() {
+				env::log_str(&format!("Skipping {}: {}", token_id, reason));
+				continue;
+			}
+
+			self.internal_prune_sale(nft_contract_id.clone(), token_id);
+		}
+	}
+
+	/*
+		private callback for prune_sale. The sale is only removed if nft_is_approved returned false, unless it was
+		relisted with a new approval in the meantime. A call that failed (e.g. ran out of GAS) proves nothing, so the
+		sale stays up.
+	*/
+	#[private]
+	pub fn resolve_prune_sale(
+		&mut self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		approval_id: u64,
+		pruner_id: AccountId,
+	) -> bool {
+		let is_revoked = promise_result_as_success()
+			.and_then(|value| near_sdk::serde_json::from_slice::<bool>(&value).ok())
+			.map(|is_approved| !is_approved)
+			.unwrap_or(false);
+		if !is_revoked {
+			return false;
+		}
+
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
+		match self.sales.get(&sale_key) {
+			Some(sale) if sale.approval_id == approval_id => {}
+			_ => return false,
+		}
+
+		self.internal_remove_pruned_sale(nft_contract_id, token_id, pruner_id);
+
+		true
+	}
+}
+
+impl StructSale {
+	// why the sale can't be pruned, if it can't
+	pub(crate) fn check_can_be_pruned(&self) -> Result<(), &'static str> {
+		if self.is_expired() {
+			// an English auction with a bid can still be settled
+			if let Some(Auction::English(auction)) = self.auction.as_ref() {
+				if auction.highest_bid().is_some() {
+					return Err("Can not prune an auction with bids, settle it instead");
+				}
+			}
+			return Ok(());
+		}
+
+		// the market owns escrowed tokens, so their sales can't go stale before they expire
+		if self.escrowed {
+			return Err("Escrowed sales can not be pruned before they expire");
+		}
+
+		Ok(())
+	}
+}
+
+impl Contract {
+	/*
+		remove an expired sale right away, otherwise ask the NFT contract about the sale's approval and resolve it with
+		resolve_prune_sale
+	*/
+	pub(crate) fn internal_prune_sale(
+		&mut self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+	) -> PromiseOrValue<bool> {
+		let sale_key = StructSaleKey::new(&nft_contract_id, &token_id);
+		let sale = self.sales.get(&sale_key).expect("No sale");
+		if let Err(reason) = sale.check_can_be_pruned() {
+			env::panic_str(reason);
+		}
+
+		if sale.is_expired() {
+			self.internal_remove_pruned_sale(nft_contract_id, token_id, env::predecessor_account_id());
+			return PromiseOrValue::Value(true);
+		}
+
+		PromiseOrValue::Promise(ext_contract::nft_is_approved(
+			token_id.clone(),
+			env::current_account_id(),
+			Some(sale.approval_id),
+			nft_contract_id.clone(),
+			CONST_NO_DEPOSIT,
+			CONST_GAS_FOR_NFT_IS_APPROVED,
+		)
+		.then(ext_self::resolve_prune_sale(
+			nft_contract_id,
+			token_id,
+			sale.approval_id,
+			env::predecessor_account_id(),
+			env::current_account_id(),
+			CONST_NO_DEPOSIT,
+			CONST_GAS_FOR_RESOLVE_PRUNE_SALE,
+		)))
+	}
+
+	/*
+		take down a pruned sale and pay the pruner CONST_PRUNE_REWARD out of the seller's storage balance. An escrowed
+		token goes back to the seller and an escrowed bid back to the bidder.
+	*/
+	pub(crate) fn internal_remove_pruned_sale(
+		&mut self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		pruner_id: AccountId,
+	) {
+		let sale = self.internal_take_down_sale(nft_contract_id, token_id);
+
+		// the token can't be sold anymore, so an escrowed bid goes back to the bidder
+		if let Some(Auction::English(auction)) = sale.auction.as_ref() {
+			if let Some(highest_bid) = auction.highest_bid() {
+				Promise::new(highest_bid.bidder_id.clone()).transfer(highest_bid.amount.0);
+			}
+		}
+
+		// the reward comes out of the storage the seller locked for the sale
+		let balance = self.storage_deposits.get(&sale.owner_id).unwrap_or(0);
+		let reward = balance.min(CONST_PRUNE_REWARD);
+		if reward > 0 {
+			self.storage_deposits.insert(&sale.owner_id, &(balance - reward));
+			Promise::new(pruner_id).transfer(reward);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::accounts;
+
+	fn is_approved_result(is_approved: bool) -> PromiseResult {
+		json_result(&is_approved)
+	}
+
+	#[test]
+	fn a_sale_whose_approval_was_revoked_is_pruned_and_the_pruner_rewarded() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+
+		set_context(accounts(3), 0);
+		contract.prune_sale(nft_id(), "1".to_string());
+		assert!(function_calls().contains(&(nft_id(), "nft_is_approved".to_string())));
+
+		set_callback_context(vec![is_approved_result(false)]);
+		assert!(contract.resolve_prune_sale(nft_id(), "1".to_string(), 0, accounts(3)));
+
+		assert!(get_sale(&contract, "1").is_none());
+		assert_eq!(transfers(), vec![(accounts(3), CONST_PRUNE_REWARD)]);
+		assert_eq!(
+			contract.storage_balance_of(accounts(1)).unwrap().total,
+			U128(CONST_STORAGE_PER_SALE - CONST_PRUNE_REWARD)
+		);
+	}
+
+	#[test]
+	fn a_sale_that_is_still_approved_or_could_not_be_checked_stays_up() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+
+		set_callback_context(vec![is_approved_result(true)]);
+		assert!(!contract.resolve_prune_sale(nft_id(), "1".to_string(), 0, accounts(3)));
+
+		set_callback_context(vec![PromiseResult::Failed]);
+		assert!(!contract.resolve_prune_sale(nft_id(), "1".to_string(), 0, accounts(3)));
+
+		assert!(get_sale(&contract, "1").is_some());
+	}
+
+	#[test]
+	fn a_sale_relisted_with_a_new_approval_stays_up() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+
+		// the callback checked approval ID 0, the sale was relisted with approval ID 1 in the meantime
+		set_callback_context(vec![is_approved_result(false)]);
+		let mut sale = get_sale(&contract, "1").unwrap();
+		sale.approval_id = 1;
+		contract.sales.insert(&StructSaleKey::new(&nft_id(), &"1".to_string()), &sale);
+
+		assert!(!contract.resolve_prune_sale(nft_id(), "1".to_string(), 0, accounts(3)));
+		assert!(get_sale(&contract, "1").is_some());
+	}
+
+	#[test]
+	fn an_expired_sale_is_pruned_right_away() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100","expires_at":"1000"}"#);
+
+		set_context_at(accounts(3), 0, 1_000);
+		let pruned = match contract.prune_sale(nft_id(), "1".to_string()) {
+			PromiseOrValue::Value(pruned) => pruned,
+			PromiseOrValue::Promise(_) => panic!("Expected the sale to be pruned right away"),
+		};
+
+		assert!(pruned);
+		assert!(get_sale(&contract, "1").is_none());
+		assert_eq!(transfers(), vec![(accounts(3), CONST_PRUNE_REWARD)]);
+	}
+
+	#[test]
+	fn prune_sales_skips_the_sales_it_can_not_prune() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100","expires_at":"1000"}"#);
+		list(
+			&mut contract,
+			accounts(1),
+			"2",
+			r#"{"sale_conditions":"100","expires_at":"1000","auction":{"type":"english","min_bid_increment":"10","ends_at":"900"}}"#,
+		);
+		set_context_at(accounts(2), 100, 500);
+		contract.place_bid(nft_id(), "2".to_string());
+
+		set_context_at(accounts(3), 0, 1_000);
+		contract.prune_sales(nft_id(), vec!["missing".to_string(), "2".to_string(), "1".to_string()]);
+
+		// the expired sale is pruned, the expired auction with a bid is left to be settled
+		assert!(get_sale(&contract, "1").is_none());
+		assert!(get_sale(&contract, "2").is_some());
+	}
+
+	#[test]
+	fn a_full_batch_fits_in_300_tgas() {
+		let mut contract = setup();
+		let token_ids: Vec<TypeTokenId> = (0..MAX_PRUNE_BATCH_LEN).map(|index| index.to_string()).collect();
+		for token_id in token_ids.iter() {
+			list(&mut contract, accounts(1), token_id, r#"{"sale_conditions":"100"}"#);
+		}
+
+		// every sale is checked with the NFT contract, creating the calls panics if they don't fit
+		set_context(accounts(3), 0);
+		contract.prune_sales(nft_id(), token_ids);
+
+		assert_eq!(function_calls().len(), 2 * MAX_PRUNE_BATCH_LEN);
+	}
+}
@@ -0,0 +1,210 @@
+use crate::*;
+
+// what a referrer earned from the purchases it referred, in NEAR
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructReferralEarnings {
+	// earned and not withdrawn yet
+	pub balance: U128,
+	// earned since the referrer's first referral
+	pub total: U128,
+	pub referral_count: u64,
+}
+
+impl Default for StructReferralEarnings {
+	fn default() -> Self {
+		Self {
+			balance: U128(0),
+			total: U128(0),
+			referral_count: 0,
+		}
+	}
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		Set the share of the protocol fee paid to the referrer of a purchase, in basis points of the fee. Only the owner
+		of the market can call this.
+	*/
+	pub fn set_referral_fee(&mut self, fee_basis_points: u16) {
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the owner can set the referral fee"
+		);
+		assert!(
+			u128::from(fee_basis_points) <= CONST_BASIS_POINTS_DENOMINATOR,
+			"Referral fee can't be more than {} basis points",
+			CONST_BASIS_POINTS_DENOMINATOR
+		);
+
+		self.referral_fee_basis_points = fee_basis_points;
+	}
+
+	pub fn get_referral_fee(&self) -> u16 {
+		self.referral_fee_basis_points
+	}
+
+	// Returns what a referrer earned, and how much of it can still be withdrawn
+	pub fn get_referral_earnings(&self, account_id: AccountId) -> StructReferralEarnings {
+		self.referral_earnings.get(&account_id).unwrap_or_default()
+	}
+
+	// Withdraw the given amount of your referral earnings, or all of them
+	#[payable]
+	pub fn withdraw_referral_earnings(&mut self, amount: Option<U128>) -> U128 {
+		assert_one_yocto();
+
+		let referrer_id = env::predecessor_account_id();
+		let mut earnings = self
+			.referral_earnings
+			.get(&referrer_id)
+			.expect("No referral earnings");
+
+		let amount = amount.unwrap_or(earnings.balance).0;
+		assert!(
+			amount <= earnings.balance.0,
+			"Amount exceeds the referral earnings of {}",
+			earnings.balance.0
+		);
+
+		earnings.balance = U128(earnings.balance.0 - amount);
+		self.referral_earnings.insert(&referrer_id, &earnings);
+
+		if amount > 0 {
+			Promise::new(referrer_id).transfer(amount);
+		}
+
+		earnings.balance
+	}
+}
+
+impl Contract {
+	// the referrer's share of a protocol fee
+	pub(crate) fn internal_referral_fee(&self, fee: U128) -> Balance {
+		fee.0 * u128::from(self.referral_fee_basis_points) / CONST_BASIS_POINTS_DENOMINATOR
+	}
+
+	// add what a referred purchase earned to the referrer's earnings
+	pub(crate) fn internal_credit_referrer(&mut self, referrer_id: &AccountId, amount: Balance) {
+		let mut earnings = self.referral_earnings.get(referrer_id).unwrap_or_default();
+
+		earnings.balance = U128(earnings.balance.0 + amount);
+		earnings.total = U128(earnings.total.0 + amount);
+		earnings.referral_count += 1;
+
+		self.referral_earnings.insert(referrer_id, &earnings);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::accounts;
+
+	// a market taking 5% of every sale and giving 20% of it to referrers
+	fn setup_with_referral_fee() -> Contract {
+		let mut contract = setup();
+		set_context(owner_id(), 0);
+		contract.set_protocol_fee(500);
+		contract.set_referral_fee(2_000);
+
+		contract
+	}
+
+	// charlie bought token "1" of bob for 10000 yoctoNEAR through danny, who gets a referral bounty of 100
+	fn referred_purchase() -> StructPurchase {
+		StructPurchase {
+			referrer_id: Some(accounts(3)),
+			referral_bounty: U128(100),
+			..purchase(accounts(2), accounts(1), "1", 10_000)
+		}
+	}
+
+	// the NFT contract paid out what is left after the fee of 500 and the bounty
+	fn resolve_referred_purchase(contract: &mut Contract) {
+		set_callback_context(vec![payout_result(&[(accounts(1), 9_400)])]);
+		contract.resolve_purchase(referred_purchase(), U128(500));
+	}
+
+	#[test]
+	#[should_panic(expected = "Only the owner can set the referral fee")]
+	fn only_the_owner_can_set_the_referral_fee() {
+		let mut contract = setup();
+
+		set_context(accounts(1), 0);
+		contract.set_referral_fee(2_000);
+	}
+
+	#[test]
+	fn a_referrer_earns_its_share_of_the_fee_plus_the_bounty() {
+		let mut contract = setup_with_referral_fee();
+
+		resolve_referred_purchase(&mut contract);
+
+		let earnings = contract.get_referral_earnings(accounts(3));
+		assert_eq!((earnings.balance, earnings.total, earnings.referral_count), (U128(200), U128(200), 1));
+		assert_eq!(contract.get_treasury_balance(None), U128(400));
+	}
+
+	#[test]
+	fn a_refunded_purchase_earns_the_referrer_nothing() {
+		let mut contract = setup_with_referral_fee();
+
+		set_callback_context(vec![PromiseResult::Failed]);
+		contract.resolve_purchase(referred_purchase(), U128(500));
+
+		assert_eq!(contract.get_referral_earnings(accounts(3)).referral_count, 0);
+		assert_eq!(contract.get_treasury_balance(None), U128(0));
+	}
+
+	#[test]
+	fn referral_earnings_can_be_withdrawn_in_parts() {
+		let mut contract = setup_with_referral_fee();
+		resolve_referred_purchase(&mut contract);
+
+		set_context(accounts(3), 1);
+		assert_eq!(contract.withdraw_referral_earnings(Some(U128(50))), U128(150));
+		assert_eq!(transfers(), vec![(accounts(3), 50)]);
+
+		set_context(accounts(3), 1);
+		assert_eq!(contract.withdraw_referral_earnings(None), U128(0));
+		assert_eq!(transfers(), vec![(accounts(3), 150)]);
+		assert_eq!(contract.get_referral_earnings(accounts(3)).total, U128(200));
+	}
+
+	#[test]
+	#[should_panic(expected = "Amount exceeds the referral earnings of 200")]
+	fn a_referrer_can_not_withdraw_more_than_it_earned() {
+		let mut contract = setup_with_referral_fee();
+		resolve_referred_purchase(&mut contract);
+
+		set_context(accounts(3), 1);
+		contract.withdraw_referral_earnings(Some(U128(201)));
+	}
+
+	#[test]
+	#[should_panic(expected = "The buyer and seller can't be the referrer")]
+	fn the_buyer_can_not_refer_itself() {
+		let mut contract = setup();
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+
+		set_context(accounts(2), 100);
+		contract.offer(nft_id(), "1".to_string(), Some(accounts(2)));
+	}
+
+	#[test]
+	#[should_panic(expected = "Sales priced in a fungible token don't pay referrers")]
+	fn purchases_in_a_fungible_token_can_not_have_a_referrer() {
+		let mut contract = setup();
+
+		set_context("usdc".parse().unwrap(), 0);
+		contract.ft_on_transfer(
+			accounts(2),
+			U128(100),
+			format!(r#"{{"nft_contract_id":"{}","token_id":"1","referrer_id":"{}"}}"#, nft_id(), accounts(3)),
+		);
+	}
+}
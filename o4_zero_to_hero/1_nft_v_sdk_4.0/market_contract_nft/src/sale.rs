@@ -7,12 +7,125 @@ pub struct StructSale {
 	pub approval_id: u64,
 	pub nft_contract_id: String,
 	pub token_id: TypeTokenId,
+	// the fixed price of the sale, or the reserve price if the sale is an auction
 	pub sale_conditions: TypeSalePriceInYoctoNear,
+	// auction state if the token is listed as an auction instead of at a fixed price
+	pub auction: Option<Auction>,
+	// the fungible token the sale is priced in. None means the sale is priced in NEAR
+	pub ft_token_id: Option<AccountId>,
+	// the token type (series) of the token, as reported by its NFT contract after the sale was listed
+	pub token_type: Option<String>,
+	// the token was sent to the market with nft_transfer_call, so the market owns it instead of holding an approval
+	pub escrowed: bool,
+	// the part of the price the seller pays whoever referred the buyer, in basis points
+	pub referral_bounty_basis_points: u16,
+	// only this account can buy the token. None means anyone can
+	pub reserved_for: Option<AccountId>,
+	// when the sale stops being valid, in nanoseconds. None means it never expires
+	pub expires_at: Option<U64>,
+}
+
+impl StructSale {
+	pub fn is_expired(&self) -> bool {
+		self.expires_at
+			.map(|expires_at| env::block_timestamp() >= expires_at.0)
+			.unwrap_or(false)
+	}
+
+	// whether the sale is still valid and not reserved for someone else
+	pub fn can_be_bought_by(&self, buyer_id: &AccountId) -> bool {
+		!self.is_expired()
+			&& self
+				.reserved_for
+				.as_ref()
+				.map(|reserved_for| reserved_for == buyer_id)
+				.unwrap_or(true)
+	}
+
+	// panics if `buyer_id` can't buy the token, see can_be_bought_by
+	pub fn assert_can_be_bought_by(&self, buyer_id: &AccountId) {
+		assert!(!self.is_expired(), "Sale has expired");
+		if let Some(reserved_for) = self.reserved_for.as_ref() {
+			assert_eq!(reserved_for, buyer_id, "Sale is reserved for {}", reserved_for);
+		}
+	}
+
+	// what it currently costs to buy the token, based on the block timestamp for Dutch auctions
+	pub fn current_price(&self) -> U128 {
+		match self.auction.as_ref() {
+			None => self.sale_conditions,
+			Some(Auction::Dutch(auction)) => U128(
+				auction.price_at(self.sale_conditions.0, env::block_timestamp()),
+			),
+			Some(Auction::English(auction)) => auction
+				.highest_bid()
+				.map(|bid| U128(bid.amount.0 + auction.min_bid_increment.0))
+				.unwrap_or(self.sale_conditions),
+		}
+	}
+}
+
+// what resolve_purchase needs to know about a purchase once nft_transfer_payout returns
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructPurchase {
+	pub buyer_id: AccountId,
+	// the owner of the token when it was sold
+	pub seller_id: AccountId,
+	pub nft_contract_id: AccountId,
+	pub token_id: TypeTokenId,
+	// the full price paid by the buyer
+	pub price: U128,
+	// the fungible token the price is paid in. None means NEAR
+	pub ft_token_id: Option<AccountId>,
+	// the account that referred the buyer. It gets a share of the protocol fee plus the referral bounty
+	pub referrer_id: Option<AccountId>,
+	// the part of the price the seller pays the referrer
+	pub referral_bounty: U128,
+}
+
+impl StructPurchase {
+	// what the NFT contract splits between the seller and royalties: the price minus the protocol fee and referral bounty
+	pub fn payout_balance(&self, fee: U128) -> U128 {
+		U128(self.price.0 - fee.0 - self.referral_bounty.0)
+	}
+
+	// the maximum amount of accounts the NFT contract may split the payout between, FT payouts cost more GAS
+	pub fn max_len_payout(&self) -> u32 {
+		if self.ft_token_id.is_some() {
+			MAX_FT_PAYOUT_LEN
+		} else {
+			MAX_PAYOUT_LEN
+		}
+	}
 }
 
 #[ext_contract(ext_self)]
 trait ExtSelf {
-	fn resolve_purchase(&mut self, buyer_id: AccountId, price: U128) -> Promise;
+	fn resolve_purchase(&mut self, purchase: StructPurchase, fee: U128) -> Promise;
+	fn resolve_cart_purchase(&mut self, purchases: Vec<StructPurchase>, fees: Vec<U128>) -> Promise;
+	fn resolve_escrow_payout(&mut self, purchase: StructPurchase, fee: U128) -> Promise;
+	fn resolve_escrow_purchase(&mut self, purchase: StructPurchase, fee: U128, payout: Payout) -> Promise;
+	fn resolve_ft_treasury_withdrawal(&mut self, ft_token_id: AccountId, amount: U128);
+	fn resolve_sale_token_type(&mut self, nft_contract_id: AccountId, token_id: TypeTokenId, approval_id: u64);
+	fn resolve_ft_payouts(&mut self, ft_token_id: AccountId, payouts: Vec<(AccountId, U128)>);
+	fn resolve_prune_sale(
+		&mut self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		approval_id: u64,
+		pruner_id: AccountId,
+	) -> bool;
+	fn resolve_collection_offer_fill(&mut self, fill: CollectionOfferFill) -> Promise;
+	fn resolve_bundle_escrow(&mut self, bundle: StructBundle, purchases: Vec<StructPurchase>, fees: Vec<U128>);
+	fn resolve_bundle_delivery(&mut self, purchases: Vec<StructPurchase>, fees: Vec<U128>, payouts: Vec<Payout>);
+	fn resolve_swap(&mut self, swap: StructSwap, requested: Vec<StructSwapItem>) -> bool;
+	fn resolve_return_escrowed_token(
+		&mut self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		owner_id: AccountId,
+	) -> bool;
 }
 
 #[near_bindgen]
@@ -21,11 +134,18 @@ impl Contract {
 	#[payable]
 	pub fn remove_sale(&mut self, nft_contract_id: AccountId, token_id: TypeTokenId) {
 		assert_one_yocto();
-		let sale = self.internal_remove_sale(nft_contract_id, token_id);
+		// an escrowed token goes back to the seller
+		let sale = self.internal_take_down_sale(nft_contract_id, token_id);
 
-		let owner_id = env::predecessor_account_id();
+		assert_eq!(env::predecessor_account_id(), sale.owner_id, "Must be sale owner");
 
-		assert_eq!(owner_id, sale.owner_id, "Must be sale owner");
+		// bids are escrowed on the market, so an auction can't be taken down once someone has bid
+		if let Some(Auction::English(auction)) = sale.auction.as_ref() {
+			assert!(
+				auction.highest_bid().is_none(),
+				"Can not remove an auction with bids"
+			);
+		}
 	}
 
 	// Update the price for sale on the market
@@ -35,10 +155,10 @@ impl Contract {
 
 		// crate the unique sale ID from the nft contract and token
 		let contract_id: AccountId = nft_contract_id.into();
-		let contract_and_token_id = format!("{}{}{}", contract_id, STATIC_DELIMITER, token_id);
+		let sale_key = StructSaleKey::new(&contract_id, &token_id);
 
 		// Get the sale object from the unique sale ID. If there is no token, panic.
-		let mut sale = self.sales.get(&contract_and_token_id).expect("No sale");
+		let mut sale = self.sales.get(&sale_key).expect("No sale");
 
 		// Assert that caller or the function is the sale owner
 		assert_eq!(
@@ -46,31 +166,83 @@ impl Contract {
 			sale.owner_id,
 			"Must be sale owner"
 		);
-		// Set the sale conditions equal to the passed in price
+
+		match sale.auction.as_ref() {
+			// the reserve price of an auction can only change until the first bid
+			Some(Auction::English(auction)) => assert!(
+				auction.highest_bid().is_none(),
+				"Can not update the price of an auction with bids"
+			),
+			// the price curve of a Dutch auction is fixed, relist the token to change it
+			Some(Auction::Dutch(_)) => env::panic_str("Can not update the price of a Dutch auction"),
+			None => {}
+		}
+
+		// the new price has to follow the policy of the collection, without paying the listing fee again
+		self.internal_assert_listing_policy(&contract_id, price, sale.ft_token_id.as_ref());
+
+		// Set the sale conditions equal to the passed in price, and move the sale in the price index
+		self.internal_remove_sale_from_price_index(&contract_id, &sale);
 		sale.sale_conditions = price;
+		self.internal_add_sale_to_price_index(&contract_id, &sale);
 
 		// Insert the sale back into the map for the unique sale ID
-		self.sales.insert(&contract_and_token_id, &sale);
+		self.sales.insert(&sale_key, &sale);
+
+		let sale_updated_log: EventLog = EventLog {
+			standard: CONST_MARKET_STANDARD_NAME.to_string(),
+			version: CONST_MARKET_EVENT_VERSION.to_string(),
+			event: EventLogVariant::SaleUpdated(vec![SaleUpdatedLog {
+				owner_id: sale.owner_id.to_string(),
+				nft_contract_id: contract_id.to_string(),
+				token_id,
+				sale_conditions: price,
+			}]),
+		};
+
+		env::log_str(&sale_updated_log.to_string());
 	}
 
-	// Place an offer on a specific sale. The sale will go through as long as your deposit is greater than or equal to the list price
+	/*
+		Place an offer on a specific sale. The sale will go through as long as your deposit is greater than or equal to
+		the list price. Storefronts built on the market pass themselves as the referrer to earn a share of the protocol
+		fee plus the referral bounty the seller set.
+	*/
 	#[payable]
-	pub fn offer(&mut self, nft_contract_id: AccountId, token_id: TypeTokenId) {
+	pub fn offer(&mut self, nft_contract_id: AccountId, token_id: TypeTokenId, referrer_id: Option<AccountId>) {
 		let deposit = env::attached_deposit();
 		assert!(deposit > 0, "Attached deposit must be greater than 0");
 
 		// Convert the nft_contract_id from a AccountId to an AccountId
 		let contract_id: AccountId = nft_contract_id.into();
-		let contract_and_token_id = format!("{}{}{}", contract_id, STATIC_DELIMITER, token_id);
+		let sale_key = StructSaleKey::new(&contract_id, &token_id);
 
-		let sale = self.sales.get(&contract_and_token_id).expect("No sale");
+		let sale = self.sales.get(&sale_key).expect("No sale");
 
 		// Get the buyer ID which is the person who called the function and make sure they're not the owner of the sale
 		let buyer_id = env::predecessor_account_id();
 		assert_ne!(sale.owner_id, buyer_id, "Can not bid on your own sale.");
+		sale.assert_can_be_bought_by(&buyer_id);
+		if let Some(referrer_id) = referrer_id.as_ref() {
+			assert!(
+				referrer_id != &buyer_id && referrer_id != &sale.owner_id,
+				"The buyer and seller can't be the referrer"
+			);
+		}
+
+		// sales priced in a fungible token are bought by sending the token with ft_transfer_call
+		assert!(
+			sale.ft_token_id.is_none(),
+			"Sale is priced in a fungible token, use ft_transfer_call instead"
+		);
+
+		// English auctions are bought through place_bid and settle_auction
+		if let Some(Auction::English(_)) = sale.auction {
+			env::panic_str("Sale is an English auction, use place_bid instead");
+		}
 
 		// Get the u128 price of the token (dot 0 converts from U128 to u128)
-		let price = sale.sale_conditions.0;
+		let price = sale.current_price().0;
 
 		// Make sure the deposit is greater than the price
 		assert!(
@@ -79,7 +251,17 @@ impl Contract {
 			price
 		);
 
-		self.process_purchase(contract_id, token_id, U128(deposit), buyer_id);
+		// the price of a Dutch auction keeps falling, so the buyer only pays the current price and gets the rest back
+		let price = if let Some(Auction::Dutch(_)) = sale.auction {
+			if deposit > price {
+				Promise::new(buyer_id.clone()).transfer(deposit - price);
+			}
+			price
+		} else {
+			deposit
+		};
+
+		self.process_purchase(contract_id, token_id, U128(price), buyer_id, referrer_id);
 	}
 
 	// Private function used when a sale is purchased. This will remove the sale, transfer and get the payout from the nft contract, and then distribute royalties
@@ -90,89 +272,356 @@ impl Contract {
 		token_id: TypeTokenId,
 		price: U128,
 		buyer_id: AccountId,
+		referrer_id: Option<AccountId>,
 	) -> Promise {
 		// Get the sale object by removing the sale
 		let sale = self.internal_remove_sale(nft_contract_id.clone(), token_id.clone());
 
-		ext_contract::nft_transfer_payout(
-			buyer_id.clone(),                  // purchaser (person to transfer the NFT to)
-			token_id,                          // token ID to transfer
-			sale.approval_id, // market contract's approval ID in order to transfer the token on behalf of the owner
-			"payout from martket".to_string(), // memo (to include some context)
-			/*
-				the price that the token was purchased for. This will be used in conjunction with the royalty percentages
-				for the token in order to determine how much money should go to which account.
-			*/
-			price,
-			10, // the maximum amount of accounts the market can payout at once (this is limited by GAS)
-			nft_contract_id, // contract to initiate the cross contract call to
-			1,  // yoctoNEAR to attach to the call
-			CONST_GAS_FOR_NFT_TRANSFER, // GAS to attach to the call
-		)
-		// after the transfer payout has been initiated, we resolve the promise by calling our own resolve_purchase function.
-		// resolve purchase will take the payout object returned from the nft_transfer_payout and actually pay the accounts
-		.then(ext_self::resolve_purchase(
+		// referral earnings are kept in NEAR, so sales priced in a fungible token can't be bought with a referrer
+		assert!(
+			referrer_id.is_none() || sale.ft_token_id.is_none(),
+			"Sales priced in a fungible token don't pay referrers"
+		);
+		let referral_bounty = if referrer_id.is_some() {
+			price.0 * u128::from(sale.referral_bounty_basis_points) / CONST_BASIS_POINTS_DENOMINATOR
+		} else {
+			0
+		};
+
+		let purchase = StructPurchase {
 			buyer_id,
+			seller_id: sale.owner_id,
+			nft_contract_id,
+			token_id,
 			price,
-			env::current_account_id(), // We are invoking this function on the current contract
-			CONST_NO_DEPOSIT,          // don't attach any deposit
-			CONST_GAS_FOR_ROYALTIES,   // GAS attached to the call to payout royalties
-		))
+			ft_token_id: sale.ft_token_id,
+			referrer_id,
+			referral_bounty: U128(referral_bounty),
+		};
+
+		// the market owns escrowed tokens, so it transfers them itself instead of using an approval
+		if sale.escrowed {
+			self.internal_escrow_transfer_payout(purchase)
+		} else {
+			self.internal_transfer_payout(purchase, sale.approval_id)
+		}
 	}
 
 	/*
 		private method used to resolve the promise when calling nft_transfer_payout. This will take the payout object and
 		check to see if it's authentic and there's no problems. If everything is fine, it will pay the accounts. If there's a problem,
-		it will refund the buyer for the price.
+		it will refund the buyer for the price. Sales priced in a fungible token are paid and refunded with ft_transfer.
+		The protocol fee `fee` is only kept once the payout went through, the payout has to add up to the payout balance.
+		A referrer is credited its share of the fee plus the referral bounty.
+		Successful sales are added to the sales history.
 	*/
 	#[private]
-	pub fn resolve_purchase(&mut self, buyer_id: AccountId, price: U128) -> U128 {
+	pub fn resolve_purchase(&mut self, purchase: StructPurchase, fee: U128) -> U128 {
+		self.internal_resolve_purchase(purchase, fee, promise_result_as_success())
+	}
+
+	/*
+		private callback for the ft_transfer calls of internal_ft_payout, in the same order as `payouts`. Every amount
+		that couldn't be sent is added to the receiver's unclaimed balance of that fungible token.
+	*/
+	#[private]
+	pub fn resolve_ft_payouts(&mut self, ft_token_id: AccountId, payouts: Vec<(AccountId, U128)>) {
+		for (index, (receiver_id, amount)) in payouts.into_iter().enumerate() {
+			if matches!(env::promise_result(index as u64), PromiseResult::Successful(_)) {
+				continue;
+			}
+
+			env::log_str(&format!(
+				"{} {} could not be sent to {}, it can be claimed",
+				amount.0, ft_token_id, receiver_id
+			));
+			let payout_key = StructFtPayoutKey::new(&receiver_id, &ft_token_id);
+			let balance = self.unclaimed_ft_payouts.get(&payout_key).unwrap_or(0);
+			self.unclaimed_ft_payouts.insert(&payout_key, &(balance + amount.0));
+		}
+	}
+
+	// Claim the payouts and refunds in a fungible token the market couldn't send you
+	#[payable]
+	pub fn claim_ft_payout(&mut self, ft_token_id: AccountId) {
+		assert_one_yocto();
+
+		let account_id = env::predecessor_account_id();
+		let amount = self
+			.unclaimed_ft_payouts
+			.remove(&StructFtPayoutKey::new(&account_id, &ft_token_id))
+			.expect("No unclaimed payout");
+
+		self.internal_ft_payout(ft_token_id, vec![(account_id, U128(amount))], "claimed payout from market");
+	}
+
+	// Returns the payouts and refunds in a fungible token the market couldn't send to an account
+	pub fn get_unclaimed_ft_payout(&self, account_id: AccountId, ft_token_id: AccountId) -> U128 {
+		U128(
+			self.unclaimed_ft_payouts
+				.get(&StructFtPayoutKey::new(&account_id, &ft_token_id))
+				.unwrap_or(0),
+		)
+	}
+}
+
+impl Contract {
+	// settle a purchase with the result of its nft_transfer_payout call, see resolve_purchase
+	pub(crate) fn internal_resolve_purchase(
+		&mut self,
+		purchase: StructPurchase,
+		fee: U128,
+		payout_result: Option<Vec<u8>>,
+	) -> U128 {
+		let payout_balance = purchase.payout_balance(fee);
+		let max_len_payout = purchase.max_len_payout();
+		let StructPurchase {
+			buyer_id,
+			seller_id,
+			nft_contract_id,
+			token_id,
+			price,
+			ft_token_id,
+			referrer_id,
+			referral_bounty,
+		} = purchase;
+
 		// checking for payout information returned from the nft_transfer_payout method
-		let payout_option = promise_result_as_success().and_then(|value| {
-			// if we set the payout_option to None, that means something went wrong and we should refund the buyer
-			near_sdk::serde_json::from_slice::<Payout>(&value)
-				// converts the result to an optional value
-				.ok()
-				// returns None if the none. Otherwise executes the following logic
-				.and_then(|payout_object| {
-					if payout_object.payout.len() > 10 || payout_object.payout.is_empty() {
-						env::log_str("Cannot have more than 10 royalties");
-						None
-					} else {
-						// We'll keep track of how much the nft contract wants us to payout. Starting at the full price payed by the buyer
-						let mut remainder = price.0;
-						// Loop through the payout and subtract the values from the remainder.
-						for &value in payout_object.payout.values() {
-							// Checked sub checks for overflow or any errors and returns None if there are problems
-							remainder = remainder.checked_sub(value.0)?;
-						}
-						// Check to see if the NFT contract sent back a faulty payout that requires us to pay more or too little.
-						// The remainder will be 0 if the payout summed to the total price. The remainder will be 1 if the royalties
-						// we something like 3333 + 3333 + 3333.
-						if remainder == 0 || remainder == 1 {
-							// Set the payout_option to be the payout because nothing went wrong
-							Some(payout_object.payout)
-						} else {
-							// If the remainder was anything but 1 or 0, we return None
-							None
-						}
-					}
-				})
-		});
+		let payout_option = payout_result.and_then(|value| parse_payout(&value, payout_balance.0, max_len_payout));
 
 		// if the payout option was some payout, we set this payout variable equal to that some payout
 		let payout = if let Some(payout_option) = payout_option {
 			payout_option
 		} else {
-			Promise::new(buyer_id).transfer(u128::from(price));
+			let purchase_refunded_log: EventLog = EventLog {
+				standard: CONST_MARKET_STANDARD_NAME.to_string(),
+				version: CONST_MARKET_EVENT_VERSION.to_string(),
+				event: EventLogVariant::PurchaseRefunded(vec![PurchaseRefundedLog {
+					buyer_id: buyer_id.to_string(),
+					nft_contract_id: nft_contract_id.to_string(),
+					token_id,
+					price,
+					ft_token_id: ft_token_id.as_ref().map(|ft_token_id| ft_token_id.to_string()),
+				}]),
+			};
+
+			env::log_str(&purchase_refunded_log.to_string());
+
+			if let Some(ft_token_id) = ft_token_id {
+				self.internal_ft_payout(ft_token_id, vec![(buyer_id, price)], "refund from market");
+			} else {
+				Promise::new(buyer_id).transfer(u128::from(price));
+			}
 			return price;
 		};
 
-		// NEAR payouts
-		for (receiver_id, amount) in payout {
-			Promise::new(receiver_id).transfer(amount.0);
+		// the referrer gets its share of the protocol fee plus the referral bounty, the treasury keeps the rest of the fee
+		let referral_fee = if referrer_id.is_some() {
+			self.internal_referral_fee(fee)
+		} else {
+			0
+		};
+		self.internal_collect_fee(ft_token_id.as_ref(), fee.0 - referral_fee);
+		let referral_amount = referrer_id.as_ref().map(|referrer_id| {
+			let referral_amount = referral_fee + referral_bounty.0;
+			self.internal_credit_referrer(referrer_id, referral_amount);
+			U128(referral_amount)
+		});
+
+		// the token changed hands, so a listing the seller left behind for it can't be bought anymore
+		self.internal_remove_stale_sale(&nft_contract_id, &token_id, &seller_id);
+
+		self.internal_record_sale(StructSaleRecord {
+			buyer_id: buyer_id.clone(),
+			seller_id,
+			nft_contract_id: nft_contract_id.clone(),
+			token_id: token_id.clone(),
+			price,
+			ft_token_id: ft_token_id.clone(),
+			sold_at: U64(env::block_timestamp()),
+		});
+
+		let sale_purchased_log: EventLog = EventLog {
+			standard: CONST_MARKET_STANDARD_NAME.to_string(),
+			version: CONST_MARKET_EVENT_VERSION.to_string(),
+			event: EventLogVariant::SalePurchased(vec![SalePurchasedLog {
+				buyer_id: buyer_id.to_string(),
+				nft_contract_id: nft_contract_id.to_string(),
+				token_id,
+				price,
+				ft_token_id: ft_token_id.as_ref().map(|ft_token_id| ft_token_id.to_string()),
+				protocol_fee: fee,
+				payout: payout.clone(),
+				referrer_id: referrer_id.as_ref().map(|referrer_id| referrer_id.to_string()),
+				referral_amount,
+			}]),
+		};
+
+		env::log_str(&sale_purchased_log.to_string());
+
+		if let Some(ft_token_id) = ft_token_id {
+			/*
+				FT payouts. The receivers need to be registered with the FT contract to get paid, a payout that can't be
+				sent is kept for the receiver to claim with claim_ft_payout
+			*/
+			self.internal_ft_payout(ft_token_id, payout.into_iter().collect(), "payout from market");
+		} else {
+			// NEAR payouts
+			for (receiver_id, amount) in payout {
+				Promise::new(receiver_id).transfer(amount.0);
+			}
 		}
 
 		price
 	}
+
+	/*
+		send amounts of a fungible token with ft_transfer. resolve_ft_payouts keeps the amounts that couldn't be sent
+		so the receivers can claim them later.
+	*/
+	pub(crate) fn internal_ft_payout(&self, ft_token_id: AccountId, payouts: Vec<(AccountId, U128)>, memo: &str) {
+		let transfers = payouts
+			.iter()
+			.map(|(receiver_id, amount)| {
+				ext_ft::ft_transfer(
+					receiver_id.clone(),
+					*amount,
+					Some(memo.to_string()),
+					ft_token_id.clone(),
+					1,
+					CONST_GAS_FOR_FT_TRANSFER,
+				)
+			})
+			.reduce(|transfers, transfer| transfers.and(transfer));
+
+		if let Some(transfers) = transfers {
+			transfers.then(ext_self::resolve_ft_payouts(
+				ft_token_id,
+				payouts,
+				env::current_account_id(),
+				CONST_NO_DEPOSIT,
+				CONST_GAS_FOR_RESOLVE_FT_PAYOUTS,
+			));
+		}
+	}
+}
+
+/*
+	parse the payout object returned by nft_transfer_payout or nft_payout and check that it pays out exactly `amount`
+	to at most `max_len_payout` accounts. If we return None, that means something went wrong and we should refund the buyer.
+*/
+pub(crate) fn parse_payout(value: &[u8], amount: u128, max_len_payout: u32) -> Option<HashMap<AccountId, U128>> {
+	near_sdk::serde_json::from_slice::<Payout>(value)
+		// converts the result to an optional value
+		.ok()
+		// returns None if the none. Otherwise executes the following logic
+		.and_then(|payout_object| {
+			if payout_object.payout.len() > max_len_payout as usize || payout_object.payout.is_empty() {
+				env::log_str(&format!("Cannot have more than {} royalties", max_len_payout));
+				None
+			} else {
+				// We'll keep track of how much the nft contract wants us to payout. Starting at the price payed by the buyer minus the protocol fee
+				let mut remainder = amount;
+				// Loop through the payout and subtract the values from the remainder.
+				for &value in payout_object.payout.values() {
+					// Checked sub checks for overflow or any errors and returns None if there are problems
+					remainder = remainder.checked_sub(value.0)?;
+				}
+				// Check to see if the NFT contract sent back a faulty payout that requires us to pay more or too little.
+				// The remainder will be 0 if the payout summed to the total price. The remainder will be 1 if the royalties
+				// we something like 3333 + 3333 + 3333.
+				if remainder == 0 || remainder == 1 {
+					// Set the payout_option to be the payout because nothing went wrong
+					Some(payout_object.payout)
+				} else {
+					// If the remainder was anything but 1 or 0, we return None
+					None
+				}
+			}
+		})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::accounts;
+
+	const EXPIRES_AT: u64 = 1_000;
+
+	// bob lists token "1" for 100 yoctoNEAR, only for charlie and until EXPIRES_AT
+	fn list_reserved(contract: &mut Contract) {
+		list(
+			contract,
+			accounts(1),
+			"1",
+			&format!(r#"{{"sale_conditions":"100","reserved_for":"{}","expires_at":"{}"}}"#, accounts(2), EXPIRES_AT),
+		);
+	}
+
+	#[test]
+	fn the_reserved_buyer_can_buy_the_token() {
+		let mut contract = setup();
+		list_reserved(&mut contract);
+
+		set_context_at(accounts(2), 100, EXPIRES_AT - 1);
+		contract.offer(nft_id(), "1".to_string(), None);
+
+		assert!(function_calls().contains(&(nft_id(), "nft_transfer_payout".to_string())));
+	}
+
+	#[test]
+	#[should_panic(expected = "Sale is reserved for charlie")]
+	fn nobody_else_can_buy_a_reserved_token() {
+		let mut contract = setup();
+		list_reserved(&mut contract);
+
+		set_context(accounts(3), 100);
+		contract.offer(nft_id(), "1".to_string(), None);
+	}
+
+	#[test]
+	#[should_panic(expected = "Sale has expired")]
+	fn an_expired_sale_can_not_be_bought() {
+		let mut contract = setup();
+		list_reserved(&mut contract);
+
+		set_context_at(accounts(2), 100, EXPIRES_AT);
+		contract.offer(nft_id(), "1".to_string(), None);
+	}
+
+	#[test]
+	#[should_panic(expected = "Can not reserve a sale for yourself")]
+	fn a_sale_can_not_be_reserved_for_its_owner() {
+		let mut contract = setup();
+
+		list(&mut contract, accounts(1), "1", &format!(r#"{{"sale_conditions":"100","reserved_for":"{}"}}"#, accounts(1)));
+	}
+
+	#[test]
+	fn reserved_sales_are_listed_for_their_buyer_until_they_expire() {
+		let mut contract = setup();
+		list_reserved(&mut contract);
+		assert!(!get_sale(&contract, "1").unwrap().can_be_bought_by(&accounts(3)));
+
+		set_context_at(accounts(2), 0, EXPIRES_AT - 1);
+		assert_eq!(contract.get_sales_reserved_for(accounts(2), None, None)[0].sale.token_id, "1");
+		assert!(contract.get_sales_reserved_for(accounts(3), None, None).is_empty());
+
+		// expired sales are left out of the views
+		set_context_at(accounts(2), 0, EXPIRES_AT);
+		assert!(contract.get_sales_reserved_for(accounts(2), None, None).is_empty());
+		assert!(contract.get_sales_by_owner_id(accounts(1), None, None).is_empty());
+	}
+
+	#[test]
+	fn a_removed_sale_is_no_longer_reserved() {
+		let mut contract = setup();
+		list_reserved(&mut contract);
+
+		set_context(accounts(1), 1);
+		contract.remove_sale(nft_id(), "1".to_string());
+
+		assert!(contract.get_sales_reserved_for(accounts(2), None, None).is_empty());
+	}
 }
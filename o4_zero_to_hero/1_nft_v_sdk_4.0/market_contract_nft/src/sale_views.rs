@@ -1,5 +1,22 @@
 use crate::*;
 
+// where get_sales_sorted_by_price continues from: the price and token ID of the last sale of the previous page
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructPriceCursor {
+	pub price: U128,
+	pub token_id: TypeTokenId,
+}
+
+// a sale as returned by the views, with whether its NFT contract is in the registry of verified collections
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct JsonSale {
+	#[serde(flatten)]
+	pub sale: StructSale,
+	pub verified: bool,
+}
+
 #[near_bindgen]
 impl Contract {
 	// Returns the number of sales the marketplace has up (as a string)
@@ -18,13 +35,13 @@ impl Contract {
 		}
 	}
 
-	// Returns paginated sale objects for a given account. (result is a vector of sales/)
+	// Returns paginated sale objects for a given account, leaving out expired sales. (result is a vector of sales/)
 	pub fn get_sales_by_owner_id(
 		&self,
 		account_id: AccountId,
 		from_index: Option<U128>,
 		limit: Option<u64>,
-	) -> Vec<StructSale> {
+	) -> Vec<JsonSale> {
 		let by_owner_id = self.by_owner_id.get(&account_id);
 
 		let sales = if let Some(by_owner_id) = by_owner_id {
@@ -39,9 +56,11 @@ impl Contract {
 		let start = u128::from(from_index.unwrap_or(U128(0)));
 
 		keys.iter()
+			.map(|sale_key| self.sales.get(&sale_key).unwrap())
+			.filter(|sale| !sale.is_expired())
 			.skip(start as usize)
 			.take(limit.unwrap_or(0) as usize)
-			.map(|token_id| self.sales.get(&token_id).unwrap())
+			.map(|sale| self.internal_json_sale(sale))
 			.collect()
 	}
 
@@ -55,12 +74,13 @@ impl Contract {
 		}
 	}
 
+	// Returns paginated sale objects for a given NFT contract, leaving out expired sales
 	pub fn get_sales_by_nft_contract_id(
 		&self,
 		nft_contract_id: AccountId,
 		from_index: Option<U128>,
 		limit: Option<u64>,
-	) -> Vec<StructSale> {
+	) -> Vec<JsonSale> {
 		let by_nft_contract_id = self.by_nft_contract_id.get(&nft_contract_id);
 
 		let sales = if let Some(by_nft_contract_id) = by_nft_contract_id {
@@ -76,24 +96,343 @@ impl Contract {
 		let start = u128::from(from_index.unwrap_or(U128(0)));
 		//iterate through the keys vector
 		keys.iter()
+			/*
+				we'll map the token IDs which are strings into Sale objects by passing in the unique sale key (contract + token ID).
+				While a migrated contract is still moving its old sales, some of them aren't in `sales` yet and are skipped.
+			*/
+			.filter_map(|token_id| self.sales.get(&StructSaleKey::new(&nft_contract_id, &token_id)))
+			// expired sales can't be bought anymore, so they aren't listed
+			.filter(|sale| !sale.is_expired())
 			//skip to the index we specified in the start variable
 			.skip(start as usize)
 			//take the first "limit" elements in the vector. If we didn't specify a limit, use 0
 			.take(limit.unwrap_or(0) as usize)
-			//we'll map the token IDs which are strings into Sale objects by passing in the unique sale ID (contract + DELIMITER + token ID)
-			.map(|token_id| {
+			.map(|sale| self.internal_json_sale(sale))
+			//since we turned the keys into an iterator, we need to turn it back into a vector to return
+			.collect()
+	}
+
+	// Returns the lowest price of the fixed price sales in NEAR for a contract that haven't expired
+	pub fn get_floor_price(&self, nft_contract_id: AccountId) -> Option<U128> {
+		self.by_nft_contract_id_by_price
+			.get(&nft_contract_id)?
+			.iter()
+			.find(|((_, token_id), _)| !self.internal_is_sale_expired(&nft_contract_id, token_id))
+			.map(|((price, _), _)| U128(price))
+	}
+
+	/*
+		Returns the fixed price sales in NEAR for a contract sorted by price, cheapest first unless `descending` is
+		set. Pass the price and token ID of the last sale of a page as the cursor to get the next page. The price
+		range is inclusive on both ends. Auctions, sales priced in a fungible token and expired sales aren't included.
+	*/
+	pub fn get_sales_sorted_by_price(
+		&self,
+		nft_contract_id: AccountId,
+		descending: Option<bool>,
+		cursor: Option<StructPriceCursor>,
+		min_price: Option<U128>,
+		max_price: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<JsonSale> {
+		let by_price = if let Some(by_price) = self.by_nft_contract_id_by_price.get(&nft_contract_id) {
+			by_price
+		} else {
+			return vec![];
+		};
+
+		let cursor: Option<TypePriceAndTokenId> = cursor.map(|cursor| (cursor.price.0, cursor.token_id));
+		let min_price = min_price.map(|price| price.0).unwrap_or(0);
+		let max_price = max_price.map(|price| price.0).unwrap_or(u128::MAX);
+
+		let keys: Box<dyn Iterator<Item = (TypePriceAndTokenId, ())>> = if descending.unwrap_or(false) {
+			// the first key above the price range, iter_rev_from starts right below it
+			let above_range = max_price
+				.checked_add(1)
+				.and_then(|price| by_price.ceil_key(&(price, String::new())));
+			let start = match (cursor, above_range) {
+				(Some(cursor), Some(above_range)) => Some(cursor.min(above_range)),
+				(cursor, above_range) => cursor.or(above_range),
+			};
+
+			let keys = match start {
+				Some(start) => Box::new(by_price.iter_rev_from(start)) as Box<dyn Iterator<Item = _>>,
+				None => Box::new(by_price.iter_rev()),
+			};
+			Box::new(keys.take_while(move |((price, _), _)| *price >= min_price))
+		} else {
+			// the empty token ID sorts before every other one, so this includes every sale at min_price
+			let range_start = (min_price, String::new());
+			let start = match cursor {
+				Some(cursor) if cursor >= range_start => Bound::Excluded(cursor),
+				_ => Bound::Included(range_start),
+			};
+
+			Box::new(
+				by_price
+					.range((start, Bound::Unbounded))
+					.take_while(move |((price, _), _)| *price <= max_price),
+			)
+		};
+
+		keys.filter(|((_, token_id), _)| !self.internal_is_sale_expired(&nft_contract_id, token_id))
+			.take(limit.unwrap_or(CONST_DEFAULT_PAGE_LIMIT) as usize)
+			.map(|((_, token_id), _)| {
+				self.internal_json_sale(
+					self.sales
+						.get(&StructSaleKey::new(&nft_contract_id, &token_id))
+						.unwrap(),
+				)
+			})
+			.collect()
+	}
+
+	// Returns the number of sales listed under a token type (series) of an NFT contract
+	pub fn get_supply_by_nft_token_type(&self, nft_contract_id: AccountId, token_type: String) -> U64 {
+		let by_nft_token_type = self
+			.by_nft_token_type
+			.get(&StructTokenTypeKey::new(&nft_contract_id, &token_type));
+
+		if let Some(by_nft_token_type) = by_nft_token_type {
+			U64(by_nft_token_type.len())
+		} else {
+			U64(0)
+		}
+	}
+
+	// Returns paginated sale objects listed under a token type (series) of an NFT contract, leaving out expired sales
+	pub fn get_sales_by_nft_token_type(
+		&self,
+		nft_contract_id: AccountId,
+		token_type: String,
+		from_index: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<JsonSale> {
+		let by_nft_token_type = self
+			.by_nft_token_type
+			.get(&StructTokenTypeKey::new(&nft_contract_id, &token_type));
+
+		let sales = if let Some(by_nft_token_type) = by_nft_token_type {
+			by_nft_token_type
+		} else {
+			return vec![];
+		};
+
+		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+		let start = u128::from(from_index.unwrap_or(U128(0)));
+
+		sales
+			.as_vector()
+			.iter()
+			.map(|sale_key| self.sales.get(&sale_key).unwrap())
+			.filter(|sale| !sale.is_expired())
+			.skip(start as usize)
+			.take(limit.unwrap_or(CONST_DEFAULT_PAGE_LIMIT) as usize)
+			.map(|sale| self.internal_json_sale(sale))
+			.collect()
+	}
+
+	// Returns paginated sales that only the given account can buy, leaving out expired sales
+	pub fn get_sales_reserved_for(
+		&self,
+		account_id: AccountId,
+		from_index: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<JsonSale> {
+		let by_reserved_for = self.by_reserved_for.get(&account_id);
+
+		let sales = if let Some(by_reserved_for) = by_reserved_for {
+			by_reserved_for
+		} else {
+			return vec![];
+		};
+
+		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+		let start = u128::from(from_index.unwrap_or(U128(0)));
+
+		sales
+			.as_vector()
+			.iter()
+			.map(|sale_key| self.sales.get(&sale_key).unwrap())
+			.filter(|sale| !sale.is_expired())
+			.skip(start as usize)
+			.take(limit.unwrap_or(CONST_DEFAULT_PAGE_LIMIT) as usize)
+			.map(|sale| self.internal_json_sale(sale))
+			.collect()
+	}
+
+	// Returns the sale for a token
+	pub fn get_sale(&self, nft_contract_id: AccountId, token_id: TypeTokenId) -> Option<JsonSale> {
+		self.sales
+			.get(&StructSaleKey::new(&nft_contract_id, &token_id))
+			.map(|sale| self.internal_json_sale(sale))
+	}
+
+	/*
+		Returns the sale for a legacy `contract ID + DELIMITER + token ID` string. Both parts can contain dots, so every
+		split is tried and the first one that is listed wins. Prefer get_sale, which can't be ambiguous.
+	*/
+	pub fn get_sales(&self, nft_contract_token: TypeContractAndTokenId) -> Option<JsonSale> {
+		nft_contract_token
+			.match_indices(STATIC_DELIMITER)
+			.find_map(|(index, _)| {
+				let nft_contract_id: AccountId = nft_contract_token[..index].parse().ok()?;
+				let token_id = nft_contract_token[index + STATIC_DELIMITER.len()..].to_string();
+
 				self.sales
-					.get(&format!(
-						"{}{}{}",
-						nft_contract_id, STATIC_DELIMITER, token_id
-					))
-					.unwrap()
+					.get(&StructSaleKey::new(&nft_contract_id, &token_id))
+					.map(|sale| self.internal_json_sale(sale))
 			})
-			//since we turned the keys into an iterator, we need to turn it back into a vector to return
+	}
+}
+
+impl Contract {
+	// whether the listed sale of a token has expired
+	pub(crate) fn internal_is_sale_expired(&self, nft_contract_id: &AccountId, token_id: &TypeTokenId) -> bool {
+		self.sales
+			.get(&StructSaleKey::new(nft_contract_id, token_id))
+			.map(|sale| sale.is_expired())
+			.unwrap_or(false)
+	}
+
+	// add the verification status of its NFT contract to a sale
+	pub(crate) fn internal_json_sale(&self, sale: StructSale) -> JsonSale {
+		let verified = self
+			.verified_collections
+			.get(&AccountId::new_unchecked(sale.nft_contract_id.clone()))
+			.is_some();
+
+		JsonSale { sale, verified }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::{accounts, VMContextBuilder};
+	use near_sdk::testing_env;
+
+	// `nft_contract_id` calls nft_on_approve for a token of bob, who paid for the storage of the sale
+	fn list_on(contract: &mut Contract, nft_contract_id: &str, token_id: &str) {
+		set_context(accounts(1), CONST_STORAGE_PER_SALE);
+		contract.storage_deposit(None, None);
+
+		testing_env!(VMContextBuilder::new()
+			.current_account_id(market_id())
+			.signer_account_id(accounts(1))
+			.predecessor_account_id(nft_contract_id.parse().unwrap())
+			.build());
+		contract.nft_on_approve(token_id.to_string(), accounts(1), 0, r#"{"sale_conditions":"100"}"#.to_string());
+	}
+
+	#[test]
+	fn pairs_that_join_to_the_same_legacy_id_are_different_sales() {
+		let mut contract = setup();
+
+		// both are "nft.x.y" joined with the delimiter
+		list_on(&mut contract, "nft", "x.y");
+		list_on(&mut contract, "nft.x", "y");
+
+		assert_eq!(contract.get_supply_sales(), U64(2));
+		let sale = contract.get_sale(nft_id(), "x.y".to_string()).unwrap().sale;
+		assert_eq!((sale.nft_contract_id.as_str(), sale.token_id.as_str()), ("nft", "x.y"));
+		let sale = contract.get_sale("nft.x".parse().unwrap(), "y".to_string()).unwrap().sale;
+		assert_eq!((sale.nft_contract_id.as_str(), sale.token_id.as_str()), ("nft.x", "y"));
+	}
+
+	#[test]
+	fn a_legacy_id_finds_a_sale_whose_token_id_has_dots() {
+		let mut contract = setup();
+		list_on(&mut contract, "nft.near", "series.1");
+
+		let sale = contract.get_sales("nft.near.series.1".to_string()).unwrap().sale;
+		assert_eq!((sale.nft_contract_id.as_str(), sale.token_id.as_str()), ("nft.near", "series.1"));
+		assert!(contract.get_sales("nft.near.series.2".to_string()).is_none());
+	}
+
+	// fixed price sales of 300, 100, 200 and 200, one of 50 that expires at 1000 and an auction with a reserve of 10
+	fn setup_order_book() -> Contract {
+		let mut contract = setup();
+		for (token_id, price) in [("a", 300), ("b", 100), ("c", 200), ("d", 200)] {
+			list(&mut contract, accounts(1), token_id, &format!(r#"{{"sale_conditions":"{}"}}"#, price));
+		}
+		list(&mut contract, accounts(1), "e", r#"{"sale_conditions":"50","expires_at":"1000"}"#);
+		list(
+			&mut contract,
+			accounts(1),
+			"f",
+			r#"{"sale_conditions":"10","auction":{"type":"english","min_bid_increment":"10","ends_at":"2000"}}"#,
+		);
+
+		contract
+	}
+
+	fn sorted_token_ids(
+		contract: &Contract,
+		descending: bool,
+		cursor: Option<(u128, &str)>,
+		min_price: Option<u128>,
+		max_price: Option<u128>,
+		limit: Option<u64>,
+	) -> Vec<TypeTokenId> {
+		contract
+			.get_sales_sorted_by_price(
+				nft_id(),
+				Some(descending),
+				cursor.map(|(price, token_id)| StructPriceCursor {
+					price: U128(price),
+					token_id: token_id.to_string(),
+				}),
+				min_price.map(U128),
+				max_price.map(U128),
+				limit,
+			)
+			.into_iter()
+			.map(|sale| sale.sale.token_id)
 			.collect()
 	}
 
-	pub fn get_sales(&self, nft_contract_token: TypeContractAndTokenId) -> Option<StructSale> {
-		self.sales.get(&nft_contract_token)
+	#[test]
+	fn sales_are_sorted_by_price_then_token_id_without_auctions() {
+		let contract = setup_order_book();
+
+		assert_eq!(sorted_token_ids(&contract, false, None, None, None, None), vec!["e", "b", "c", "d", "a"]);
+		assert_eq!(sorted_token_ids(&contract, true, None, None, None, None), vec!["a", "d", "c", "b", "e"]);
+	}
+
+	#[test]
+	fn pages_continue_after_the_cursor() {
+		let contract = setup_order_book();
+
+		assert_eq!(sorted_token_ids(&contract, false, None, None, None, Some(2)), vec!["e", "b"]);
+		assert_eq!(sorted_token_ids(&contract, false, Some((100, "b")), None, None, Some(2)), vec!["c", "d"]);
+		assert_eq!(sorted_token_ids(&contract, false, Some((200, "d")), None, None, Some(2)), vec!["a"]);
+
+		assert_eq!(sorted_token_ids(&contract, true, Some((200, "d")), None, None, Some(2)), vec!["c", "b"]);
+	}
+
+	#[test]
+	fn the_price_range_is_inclusive() {
+		let contract = setup_order_book();
+
+		assert_eq!(sorted_token_ids(&contract, false, None, Some(100), Some(200), None), vec!["b", "c", "d"]);
+		assert_eq!(sorted_token_ids(&contract, true, None, Some(100), Some(200), None), vec!["d", "c", "b"]);
+		// a cursor outside of the range starts at its edge
+		assert_eq!(sorted_token_ids(&contract, true, Some((300, "a")), Some(150), Some(250), None), vec!["d", "c"]);
+	}
+
+	#[test]
+	fn the_floor_price_leaves_out_expired_sales_and_follows_price_updates() {
+		let mut contract = setup_order_book();
+		assert_eq!(contract.get_floor_price(nft_id()), Some(U128(50)));
+
+		set_context_at(accounts(1), 1, 1_000);
+		assert_eq!(contract.get_floor_price(nft_id()), Some(U128(100)));
+		assert_eq!(sorted_token_ids(&contract, false, None, None, None, Some(1)), vec!["b"]);
+
+		contract.update_price(nft_id(), "b".to_string(), U128(400));
+		assert_eq!(contract.get_floor_price(nft_id()), Some(U128(200)));
+		assert_eq!(sorted_token_ids(&contract, true, None, None, None, Some(1)), vec!["b"]);
 	}
 }
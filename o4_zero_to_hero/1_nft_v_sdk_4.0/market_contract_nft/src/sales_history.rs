@@ -0,0 +1,307 @@
+use crate::*;
+
+// a completed sale, kept in the sales history of the collection and of the token
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructSaleRecord {
+	pub buyer_id: AccountId,
+	pub seller_id: AccountId,
+	pub nft_contract_id: AccountId,
+	pub token_id: TypeTokenId,
+	pub price: U128,
+	// the fungible token the price was paid in. None means NEAR
+	pub ft_token_id: Option<AccountId>,
+	// block timestamp of the sale, in nanoseconds
+	pub sold_at: U64,
+}
+
+// running counters for a collection, or for the whole market
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructSalesStats {
+	pub sale_count: u64,
+	// total volume of the sales paid in NEAR
+	pub volume: U128,
+	// total volume of the sales paid in every fungible token
+	pub ft_volumes: HashMap<AccountId, U128>,
+	pub last_sale: Option<StructSaleRecord>,
+}
+
+impl Default for StructSalesStats {
+	fn default() -> Self {
+		Self {
+			sale_count: 0,
+			volume: U128(0),
+			ft_volumes: HashMap::new(),
+			last_sale: None,
+		}
+	}
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		Returns the paginated sales history of a collection, oldest first. Only the last MAX_COLLECTION_SALES_HISTORY_LEN
+		sales are kept, and only for the last MAX_TRACKED_COLLECTIONS_LEN unverified collections.
+	*/
+	pub fn get_sales_history_by_nft_contract_id(
+		&self,
+		nft_contract_id: AccountId,
+		from_index: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<StructSaleRecord> {
+		let history = self
+			.sales_history_by_nft_contract_id
+			.get(&nft_contract_id)
+			.unwrap_or_default();
+
+		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+		let start = u128::from(from_index.unwrap_or(U128(0)));
+
+		history
+			.into_iter()
+			.skip(start as usize)
+			.take(limit.unwrap_or(CONST_DEFAULT_PAGE_LIMIT) as usize)
+			.collect()
+	}
+
+	/*
+		Returns the paginated sales history of a token, oldest first. Only the last MAX_TOKEN_SALES_HISTORY_LEN sales are
+		kept, and only for the last MAX_TRACKED_TOKENS_LEN tokens that were sold.
+	*/
+	pub fn get_sales_history_by_token(
+		&self,
+		nft_contract_id: AccountId,
+		token_id: TypeTokenId,
+		from_index: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<StructSaleRecord> {
+		let history = self
+			.sales_history_by_token
+			.get(&StructSaleKey::new(&nft_contract_id, &token_id))
+			.unwrap_or_default();
+
+		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+		let start = u128::from(from_index.unwrap_or(U128(0)));
+
+		history
+			.into_iter()
+			.skip(start as usize)
+			.take(limit.unwrap_or(CONST_DEFAULT_PAGE_LIMIT) as usize)
+			.collect()
+	}
+
+	// Returns the sale count, volume and last sale of a collection, or of the whole market without a contract
+	pub fn get_sales_stats(&self, nft_contract_id: Option<AccountId>) -> StructSalesStats {
+		let key = nft_contract_id.map(|nft_contract_id| nft_contract_id.to_string()).unwrap_or_default();
+
+		self.sales_stats.get(&key).unwrap_or_default()
+	}
+}
+
+impl Contract {
+	/*
+		add a completed sale to the bounded histories and update the counters of the collection and the market. The
+		market pays for this storage, so the number of collections and tokens it is kept for is bounded too.
+	*/
+	pub(crate) fn internal_record_sale(&mut self, record: StructSaleRecord) {
+		if !self.sales_history_by_nft_contract_id.contains_key(&record.nft_contract_id)
+			&& self.verified_collections.get(&record.nft_contract_id).is_none()
+		{
+			self.internal_track_collection(&record.nft_contract_id);
+		}
+
+		let mut collection_history = self
+			.sales_history_by_nft_contract_id
+			.get(&record.nft_contract_id)
+			.unwrap_or_default();
+		if collection_history.len() >= MAX_COLLECTION_SALES_HISTORY_LEN {
+			collection_history.remove(0);
+		}
+		collection_history.push(record.clone());
+		self.sales_history_by_nft_contract_id
+			.insert(&record.nft_contract_id, &collection_history);
+
+		let sale_key = StructSaleKey::new(&record.nft_contract_id, &record.token_id);
+		if !self.sales_history_by_token.contains_key(&sale_key) {
+			self.internal_track_token(&sale_key);
+		}
+		let mut token_history = self.sales_history_by_token.get(&sale_key).unwrap_or_default();
+		if token_history.len() >= MAX_TOKEN_SALES_HISTORY_LEN {
+			token_history.remove(0);
+		}
+		token_history.push(record.clone());
+		self.sales_history_by_token.insert(&sale_key, &token_history);
+
+		// the market wide counters are stored under the empty key
+		for key in [record.nft_contract_id.to_string(), String::new()] {
+			let mut stats = self.sales_stats.get(&key).unwrap_or_default();
+			stats.sale_count += 1;
+			if let Some(ft_token_id) = record.ft_token_id.as_ref() {
+				let volume = stats.ft_volumes.entry(ft_token_id.clone()).or_insert(U128(0));
+				volume.0 += record.price.0;
+				// only the fungible tokens the market still accepts are counted, the owner controls how many there are
+				stats.ft_volumes.retain(|ft_token_id, _| self.ft_token_ids.contains(ft_token_id));
+			} else {
+				stats.volume.0 += record.price.0;
+			}
+			stats.last_sale = Some(record.clone());
+			self.sales_stats.insert(&key, &stats);
+		}
+	}
+
+	// give an unverified collection a slot in tracked_collections, dropping the history and stats of the one it replaces
+	pub(crate) fn internal_track_collection(&mut self, nft_contract_id: &AccountId) {
+		let slot = self.next_tracked_collection_slot % MAX_TRACKED_COLLECTIONS_LEN;
+		self.next_tracked_collection_slot += 1;
+
+		if slot >= self.tracked_collections.len() {
+			self.tracked_collections.push(nft_contract_id);
+			return;
+		}
+
+		let replaced_id = self.tracked_collections.replace(slot, nft_contract_id);
+		// a collection that was verified in the meantime keeps its history
+		if self.verified_collections.get(&replaced_id).is_none() {
+			self.sales_history_by_nft_contract_id.remove(&replaced_id);
+			self.sales_stats.remove(&replaced_id.to_string());
+		}
+	}
+
+	// give a token a slot in tracked_tokens, dropping the history of the one it replaces
+	pub(crate) fn internal_track_token(&mut self, sale_key: &StructSaleKey) {
+		let slot = self.next_tracked_token_slot % MAX_TRACKED_TOKENS_LEN;
+		self.next_tracked_token_slot += 1;
+
+		if slot >= self.tracked_tokens.len() {
+			self.tracked_tokens.push(sale_key);
+			return;
+		}
+
+		let replaced_key = self.tracked_tokens.replace(slot, sale_key);
+		self.sales_history_by_token.remove(&replaced_key);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::accounts;
+
+	fn record(nft_contract_id: &str, token_id: &str, price: u128, ft_token_id: Option<&str>) -> StructSaleRecord {
+		StructSaleRecord {
+			buyer_id: accounts(2),
+			seller_id: accounts(1),
+			nft_contract_id: nft_contract_id.parse().unwrap(),
+			token_id: token_id.to_string(),
+			price: U128(price),
+			ft_token_id: ft_token_id.map(|ft_token_id| ft_token_id.parse().unwrap()),
+			sold_at: U64(0),
+		}
+	}
+
+	fn token_prices(contract: &Contract, token_id: &str) -> Vec<u128> {
+		contract
+			.get_sales_history_by_token(nft_id(), token_id.to_string(), None, None)
+			.into_iter()
+			.map(|record| record.price.0)
+			.collect()
+	}
+
+	#[test]
+	fn a_purchase_is_recorded_in_the_history_and_stats() {
+		let mut contract = setup();
+
+		set_callback_context(vec![payout_result(&[(accounts(1), 100)])]);
+		contract.resolve_purchase(purchase(accounts(2), accounts(1), "1", 100), U128(0));
+
+		let history = contract.get_sales_history_by_nft_contract_id(nft_id(), None, None);
+		assert_eq!(history.len(), 1);
+		assert_eq!((history[0].buyer_id.clone(), history[0].price), (accounts(2), U128(100)));
+		assert_eq!(token_prices(&contract, "1"), vec![100]);
+		assert_eq!(contract.get_sales_stats(Some(nft_id())).sale_count, 1);
+	}
+
+	#[test]
+	fn a_refunded_purchase_is_not_recorded() {
+		let mut contract = setup();
+
+		set_callback_context(vec![PromiseResult::Failed]);
+		contract.resolve_purchase(purchase(accounts(2), accounts(1), "1", 100), U128(0));
+
+		assert!(contract.get_sales_history_by_nft_contract_id(nft_id(), None, None).is_empty());
+		assert_eq!(contract.get_sales_stats(None).sale_count, 0);
+	}
+
+	#[test]
+	fn the_history_of_a_token_keeps_its_latest_sales() {
+		let mut contract = setup();
+
+		for price in 1..=(MAX_TOKEN_SALES_HISTORY_LEN as u128 + 2) {
+			contract.internal_record_sale(record("nft", "1", price, None));
+		}
+
+		let expected: Vec<u128> = (3..=(MAX_TOKEN_SALES_HISTORY_LEN as u128 + 2)).collect();
+		assert_eq!(token_prices(&contract, "1"), expected);
+		let page = contract.get_sales_history_by_token(nft_id(), "1".to_string(), Some(U128(1)), Some(2));
+		assert_eq!(page.iter().map(|record| record.price.0).collect::<Vec<_>>(), vec![4, 5]);
+	}
+
+	#[test]
+	fn volumes_are_counted_per_currency_for_the_collection_and_the_market() {
+		let mut contract = setup();
+		set_context(owner_id(), 0);
+		contract.add_ft_token_ids(vec!["usdc".parse().unwrap()]);
+
+		contract.internal_record_sale(record("nft", "1", 100, None));
+		contract.internal_record_sale(record("nft", "2", 30, Some("usdc")));
+		contract.internal_record_sale(record("other", "1", 50, None));
+
+		let stats = contract.get_sales_stats(Some(nft_id()));
+		assert_eq!((stats.sale_count, stats.volume), (2, U128(100)));
+		assert_eq!(stats.ft_volumes.get(&"usdc".parse().unwrap()), Some(&U128(30)));
+		assert_eq!(stats.last_sale.unwrap().token_id, "2");
+
+		let stats = contract.get_sales_stats(None);
+		assert_eq!((stats.sale_count, stats.volume), (3, U128(150)));
+	}
+
+	#[test]
+	fn the_oldest_unverified_collection_loses_its_history_to_a_new_one() {
+		let mut contract = setup();
+		contract.internal_record_sale(record("nft", "1", 100, None));
+		for index in 1..MAX_TRACKED_COLLECTIONS_LEN {
+			contract.internal_track_collection(&format!("collection{}", index).parse().unwrap());
+		}
+		assert_eq!(contract.get_sales_stats(Some(nft_id())).sale_count, 1);
+
+		contract.internal_record_sale(record("other", "1", 100, None));
+
+		assert!(contract.get_sales_history_by_nft_contract_id(nft_id(), None, None).is_empty());
+		assert_eq!(contract.get_sales_stats(Some(nft_id())).sale_count, 0);
+		// the market wide counters keep every sale
+		assert_eq!(contract.get_sales_stats(None).sale_count, 2);
+	}
+
+	#[test]
+	fn a_verified_collection_keeps_its_history() {
+		let mut contract = setup();
+		set_context(owner_id(), 0);
+		contract.verify_collection(
+			nft_id(),
+			StructCollectionPolicy {
+				near_allowed: true,
+				ft_token_ids: vec![],
+				min_price: U128(0),
+				listing_fee: U128(0),
+			},
+		);
+		contract.internal_record_sale(record("nft", "1", 100, None));
+		for index in 0..MAX_TRACKED_COLLECTIONS_LEN {
+			contract.internal_track_collection(&format!("collection{}", index).parse().unwrap());
+		}
+
+		assert_eq!(contract.get_sales_history_by_nft_contract_id(nft_id(), None, None).len(), 1);
+	}
+}
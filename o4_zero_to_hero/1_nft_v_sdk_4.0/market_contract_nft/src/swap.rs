@@ -0,0 +1,552 @@
+use crate::*;
+
+// a token asked for in a swap
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructSwapToken {
+	pub nft_contract_id: AccountId,
+	pub token_id: TypeTokenId,
+}
+
+// a token given in a swap, together with the approval its owner gave the market for it
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructSwapItem {
+	pub nft_contract_id: AccountId,
+	pub token_id: TypeTokenId,
+	pub approval_id: u64,
+}
+
+/*
+	A proposal to trade the `offered` tokens of the proposer, plus `amount` in NEAR, for the `requested` tokens of
+	the counterparty. The amount is escrowed on the market together with CONST_STORAGE_PER_SWAP to cover the storage,
+	both are returned to the proposer if the swap doesn't go through.
+*/
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructSwap {
+	pub swap_id: U64,
+	pub proposer_id: AccountId,
+	pub counterparty_id: AccountId,
+	pub offered: Vec<StructSwapItem>,
+	pub requested: Vec<StructSwapToken>,
+	pub amount: U128,
+	// when the swap stops being valid, in nanoseconds. None means it never expires
+	pub expires_at: Option<U64>,
+}
+
+impl StructSwap {
+	pub fn is_expired(&self) -> bool {
+		self.expires_at
+			.map(|expires_at| env::block_timestamp() >= expires_at.0)
+			.unwrap_or(false)
+	}
+}
+
+/*
+	The GAS every step of accepting a swap of `legs` tokens needs: 10 TGas for its own work, plus the GAS it attaches
+	to every call it makes, CONST_GAS_FOR_RECEIPT for sending each of them and CONST_GAS_FOR_DATA_RECEIPT for every
+	result its callback waits on. MAX_SWAP_LEN is what fits in 300 TGas.
+*/
+pub(crate) const fn resolve_swap_gas(legs: usize) -> Gas {
+	// handing a token to its new owner, or back to its old one
+	let per_token = CONST_GAS_FOR_NFT_TRANSFER.0
+		+ CONST_GAS_FOR_RESOLVE_RETURN_TOKEN.0
+		+ 2 * CONST_GAS_FOR_RECEIPT.0
+		+ CONST_GAS_FOR_DATA_RECEIPT.0;
+	Gas(10_000_000_000_000 + per_token * legs as u64)
+}
+
+pub(crate) const fn accept_swap_gas(legs: usize) -> Gas {
+	let per_token = CONST_GAS_FOR_NFT_TRANSFER.0 + CONST_GAS_FOR_RECEIPT.0 + CONST_GAS_FOR_DATA_RECEIPT.0;
+	Gas(10_000_000_000_000 + CONST_GAS_FOR_RECEIPT.0 + resolve_swap_gas(legs).0 + per_token * legs as u64)
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		Propose to trade some of your tokens, plus an optional amount in NEAR, for tokens of the counterparty. Both
+		sides need at least one token and the swap can have up to MAX_SWAP_LEN tokens in total. Approve the market for the offered tokens first. The attached deposit must cover
+		the amount plus the storage for the swap. Returns the swap ID the counterparty accepts.
+	*/
+	#[payable]
+	pub fn propose_swap(
+		&mut self,
+		counterparty_id: AccountId,
+		offered: Vec<StructSwapItem>,
+		requested: Vec<StructSwapToken>,
+		amount: Option<U128>,
+		expires_at: Option<U64>,
+	) -> U64 {
+		let deposit = env::attached_deposit();
+		let amount = amount.unwrap_or(U128(0));
+		let proposer_id = env::predecessor_account_id();
+
+		assert_ne!(proposer_id, counterparty_id, "Can not swap with yourself");
+		assert!(
+			!offered.is_empty() && !requested.is_empty(),
+			"Must offer and request at least 1 token"
+		);
+		assert!(
+			offered.len() + requested.len() <= MAX_SWAP_LEN,
+			"A swap can have at most {} tokens",
+			MAX_SWAP_LEN
+		);
+		assert!(
+			deposit >= amount.0 + CONST_STORAGE_PER_SWAP,
+			"Attached deposit must cover the amount plus {} for storage",
+			CONST_STORAGE_PER_SWAP
+		);
+		if let Some(expires_at) = expires_at {
+			assert!(
+				expires_at.0 > env::block_timestamp(),
+				"Swap must expire in the future"
+			);
+		}
+
+		// refund anything attached on top of the amount and storage
+		let refund = deposit - amount.0 - CONST_STORAGE_PER_SWAP;
+		if refund > 0 {
+			Promise::new(proposer_id.clone()).transfer(refund);
+		}
+
+		let swap_id = self.next_swap_id;
+		self.next_swap_id += 1;
+
+		self.swaps.insert(
+			&swap_id,
+			&StructSwap {
+				swap_id: U64(swap_id),
+				proposer_id: proposer_id.clone(),
+				counterparty_id: counterparty_id.clone(),
+				offered,
+				requested,
+				amount,
+				expires_at,
+			},
+		);
+
+		// the swap shows up for both sides
+		for account_id in [&proposer_id, &counterparty_id] {
+			let mut by_account_id = self.swaps_by_account_id.get(account_id).unwrap_or_else(|| {
+				UnorderedSet::new(
+					EnumStorageKey::SwapsByAccountIdInner {
+						account_id_hash: hash_account_id(account_id),
+					}
+					.try_to_vec()
+					.unwrap(),
+				)
+			});
+			by_account_id.insert(&swap_id);
+			self.swaps_by_account_id.insert(account_id, &by_account_id);
+		}
+
+		let swap_proposed_log: EventLog = EventLog {
+			standard: CONST_MARKET_STANDARD_NAME.to_string(),
+			version: CONST_MARKET_EVENT_VERSION.to_string(),
+			event: EventLogVariant::SwapProposed(vec![SwapProposedLog {
+				swap_id: U64(swap_id),
+				proposer_id: proposer_id.to_string(),
+				counterparty_id: counterparty_id.to_string(),
+			}]),
+		};
+
+		env::log_str(&swap_proposed_log.to_string());
+
+		U64(swap_id)
+	}
+
+	/*
+		Cancel a swap and give the proposer the escrow back. Either side can cancel at any time, anyone else can only
+		clean up swaps that have expired.
+	*/
+	#[payable]
+	pub fn cancel_swap(&mut self, swap_id: U64) {
+		assert_one_yocto();
+
+		let swap = self.internal_remove_swap(swap_id.0);
+		let caller_id = env::predecessor_account_id();
+
+		assert!(
+			caller_id == swap.proposer_id || caller_id == swap.counterparty_id || swap.is_expired(),
+			"Only the proposer or the counterparty can cancel a swap that hasn't expired"
+		);
+
+		Promise::new(swap.proposer_id).transfer(swap.amount.0 + CONST_STORAGE_PER_SWAP);
+	}
+
+	/*
+		Accept a swap as the counterparty. Approve the market for the requested tokens first and pass the approval IDs
+		in the order of `requested`. The market moves every token to itself with the approval it was given, which fails
+		for a token its side doesn't own anymore, and only hands them to their new owners once all of them arrived.
+		If any transfer fails, the tokens go back to where they came from. The call needs accept_swap_gas for the tokens
+		of the swap.
+	*/
+	#[payable]
+	pub fn accept_swap(&mut self, swap_id: U64, approval_ids: Vec<u64>) {
+		assert_one_yocto();
+
+		// the tokens and the escrow are only safe if every callback gets the GAS it needs
+		let swap = self.swaps.get(&swap_id.0).expect("No swap");
+		let required_gas = accept_swap_gas(swap.offered.len() + swap.requested.len());
+		assert!(
+			env::prepaid_gas() >= required_gas,
+			"Accepting this swap needs at least {} TGas",
+			required_gas.0 / 1_000_000_000_000
+		);
+
+		let swap = self.internal_remove_swap(swap_id.0);
+
+		assert_eq!(
+			env::predecessor_account_id(),
+			swap.counterparty_id,
+			"Only the counterparty can accept a swap"
+		);
+		assert!(!swap.is_expired(), "Swap has expired");
+		assert_eq!(
+			approval_ids.len(),
+			swap.requested.len(),
+			"Must pass an approval ID for every requested token"
+		);
+
+		let requested: Vec<StructSwapItem> = swap
+			.requested
+			.iter()
+			.zip(approval_ids)
+			.map(|(token, approval_id)| StructSwapItem {
+				nft_contract_id: token.nft_contract_id.clone(),
+				token_id: token.token_id.clone(),
+				approval_id,
+			})
+			.collect();
+
+		// bids are escrowed on the market, so a token can't be swapped away while its auction has bids
+		for (index, item) in swap.offered.iter().chain(requested.iter()).enumerate() {
+			let from_id = if index < swap.offered.len() {
+				&swap.proposer_id
+			} else {
+				&swap.counterparty_id
+			};
+			if let Some(sale) = self.sales.get(&StructSaleKey::new(&item.nft_contract_id, &item.token_id)) {
+				if let Some(Auction::English(auction)) = sale.auction.as_ref() {
+					assert!(
+						&sale.owner_id != from_id || auction.highest_bid().is_none(),
+						"Token is in an auction with bids"
+					);
+				}
+			}
+		}
+
+		// the NFT contract only lets the market move a token with the approval its owner gave
+		let market_id = env::current_account_id();
+		let mut transfers: Option<Promise> = None;
+		for item in swap.offered.iter().chain(requested.iter()) {
+			let transfer = ext_contract::nft_transfer(
+				market_id.clone(),
+				item.token_id.clone(),
+				Some(item.approval_id),
+				Some("swap on market".to_string()),
+				item.nft_contract_id.clone(),
+				1,
+				CONST_GAS_FOR_NFT_TRANSFER,
+			);
+			transfers = Some(match transfers {
+				Some(transfers) => transfers.and(transfer),
+				None => transfer,
+			});
+		}
+
+		let resolve_gas = resolve_swap_gas(swap.offered.len() + requested.len());
+		transfers.unwrap().then(ext_self::resolve_swap(
+			swap,
+			requested,
+			market_id,
+			CONST_NO_DEPOSIT,
+			resolve_gas,
+		));
+	}
+
+	/*
+		private callback for the transfers of accept_swap. If every token made it to the market, the offered tokens go
+		to the counterparty together with the amount, and the requested tokens go to the proposer. Otherwise the tokens
+		that did arrive go back to their owners and the proposer gets the escrow back. Returns whether the swap went
+		through.
+	*/
+	#[private]
+	pub fn resolve_swap(&mut self, swap: StructSwap, requested: Vec<StructSwapItem>) -> bool {
+		let offered_len = swap.offered.len();
+		let arrived: Vec<bool> = (0..offered_len + requested.len())
+			.map(|index| matches!(env::promise_result(index as u64), PromiseResult::Successful(_)))
+			.collect();
+		let is_success = arrived.iter().all(|arrived| *arrived);
+
+		for (index, item) in swap.offered.iter().chain(requested.iter()).enumerate() {
+			let (from_id, to_id) = if index < offered_len {
+				(&swap.proposer_id, &swap.counterparty_id)
+			} else {
+				(&swap.counterparty_id, &swap.proposer_id)
+			};
+
+			if is_success {
+				// the token changed hands, so a listing the giving side left behind can't be bought anymore
+				self.internal_remove_stale_sale(&item.nft_contract_id, &item.token_id, from_id);
+				self.internal_return_escrowed_token(item.nft_contract_id.clone(), item.token_id.clone(), to_id.clone());
+			} else if arrived[index] {
+				self.internal_return_escrowed_token(item.nft_contract_id.clone(), item.token_id.clone(), from_id.clone());
+			}
+		}
+
+		if !is_success {
+			env::log_str(&format!("Swap {} failed, the tokens are returned", swap.swap_id.0));
+			Promise::new(swap.proposer_id).transfer(swap.amount.0 + CONST_STORAGE_PER_SWAP);
+			return false;
+		}
+
+		if swap.amount.0 > 0 {
+			Promise::new(swap.counterparty_id.clone()).transfer(swap.amount.0);
+		}
+		Promise::new(swap.proposer_id.clone()).transfer(CONST_STORAGE_PER_SWAP);
+
+		let swap_accepted_log: EventLog = EventLog {
+			standard: CONST_MARKET_STANDARD_NAME.to_string(),
+			version: CONST_MARKET_EVENT_VERSION.to_string(),
+			event: EventLogVariant::SwapAccepted(vec![SwapAcceptedLog {
+				swap_id: swap.swap_id,
+				proposer_id: swap.proposer_id.to_string(),
+				counterparty_id: swap.counterparty_id.to_string(),
+			}]),
+		};
+
+		env::log_str(&swap_accepted_log.to_string());
+
+		true
+	}
+
+	pub fn get_swap(&self, swap_id: U64) -> Option<StructSwap> {
+		self.swaps.get(&swap_id.0)
+	}
+
+	// Returns paginated swaps an account proposed or was asked to accept
+	pub fn get_swaps_by_account_id(
+		&self,
+		account_id: AccountId,
+		from_index: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<StructSwap> {
+		let swap_ids = if let Some(swap_ids) = self.swaps_by_account_id.get(&account_id) {
+			swap_ids
+		} else {
+			return vec![];
+		};
+
+		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+		let start = u128::from(from_index.unwrap_or(U128(0)));
+
+		swap_ids
+			.as_vector()
+			.iter()
+			.skip(start as usize)
+			.take(limit.unwrap_or(CONST_DEFAULT_PAGE_LIMIT) as usize)
+			.map(|swap_id| self.swaps.get(&swap_id).unwrap())
+			.collect()
+	}
+}
+
+impl Contract {
+	// internal method for removing a swap from the market. This returns the removed swap
+	pub(crate) fn internal_remove_swap(&mut self, swap_id: u64) -> StructSwap {
+		let swap = self.swaps.remove(&swap_id).expect("No swap");
+
+		for account_id in [&swap.proposer_id, &swap.counterparty_id] {
+			let mut by_account_id = self
+				.swaps_by_account_id
+				.get(account_id)
+				.expect("No swap by_account_id");
+			by_account_id.remove(&swap_id);
+
+			if by_account_id.is_empty() {
+				self.swaps_by_account_id.remove(account_id);
+			} else {
+				self.swaps_by_account_id.insert(account_id, &by_account_id);
+			}
+		}
+
+		swap
+	}
+
+	/*
+		remove the sale of a token its owner doesn't have anymore. Only a sale of `owner_id` is removed, and an escrowed
+		bid on it goes back to the bidder. This is called from callbacks, so it doesn't panic.
+	*/
+	pub(crate) fn internal_remove_stale_sale(
+		&mut self,
+		nft_contract_id: &AccountId,
+		token_id: &TypeTokenId,
+		owner_id: &AccountId,
+	) {
+		let is_stale = self
+			.sales
+			.get(&StructSaleKey::new(nft_contract_id, token_id))
+			.map(|sale| &sale.owner_id == owner_id && !sale.escrowed)
+			.unwrap_or(false);
+		if !is_stale {
+			return;
+		}
+
+		let sale = self.internal_remove_sale(nft_contract_id.clone(), token_id.clone());
+		if let Some(Auction::English(auction)) = sale.auction.as_ref() {
+			if let Some(highest_bid) = auction.highest_bid() {
+				Promise::new(highest_bid.bidder_id.clone()).transfer(highest_bid.amount.0);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::{accounts, VMContextBuilder};
+	use near_sdk::testing_env;
+
+	fn swap_item(token_id: &str) -> StructSwapItem {
+		StructSwapItem {
+			nft_contract_id: nft_id(),
+			token_id: token_id.to_string(),
+			approval_id: 0,
+		}
+	}
+
+	fn swap_token(token_id: &str) -> StructSwapToken {
+		StructSwapToken {
+			nft_contract_id: nft_id(),
+			token_id: token_id.to_string(),
+		}
+	}
+
+	// bob offers token "a" plus 100 yoctoNEAR for the `requested` tokens of charlie
+	fn propose(contract: &mut Contract, requested: &[&str], expires_at: Option<U64>) -> U64 {
+		set_context(accounts(1), 100 + CONST_STORAGE_PER_SWAP);
+		contract.propose_swap(
+			accounts(2),
+			vec![swap_item("a")],
+			requested.iter().map(|token_id| swap_token(token_id)).collect(),
+			Some(U128(100)),
+			expires_at,
+		)
+	}
+
+	fn accept(contract: &mut Contract, swap_id: U64, approval_ids: Vec<u64>, prepaid_gas: Gas) {
+		testing_env!(VMContextBuilder::new()
+			.current_account_id(market_id())
+			.signer_account_id(accounts(2))
+			.predecessor_account_id(accounts(2))
+			.attached_deposit(1)
+			.prepaid_gas(prepaid_gas)
+			.build());
+		contract.accept_swap(swap_id, approval_ids);
+	}
+
+	// the number of nft_transfer calls the last call made
+	fn nft_transfer_count() -> usize {
+		function_calls()
+			.into_iter()
+			.filter(|(_, function_name)| function_name == "nft_transfer")
+			.count()
+	}
+
+	#[test]
+	fn a_proposed_swap_shows_up_for_both_sides() {
+		let mut contract = setup();
+
+		set_context(accounts(1), 150 + CONST_STORAGE_PER_SWAP);
+		let swap_id = contract.propose_swap(accounts(2), vec![swap_item("a")], vec![swap_token("b")], Some(U128(100)), None);
+
+		// anything on top of the amount and storage is refunded
+		assert_eq!(transfers(), vec![(accounts(1), 50)]);
+		assert_eq!(contract.get_swaps_by_account_id(accounts(1), None, None)[0].swap_id, swap_id);
+		assert_eq!(contract.get_swaps_by_account_id(accounts(2), None, None)[0].swap_id, swap_id);
+	}
+
+	#[test]
+	#[should_panic(expected = "A swap can have at most 3 tokens")]
+	fn a_swap_can_not_have_more_than_max_swap_len_tokens() {
+		let mut contract = setup();
+
+		propose(&mut contract, &["b", "c", "d"], None);
+	}
+
+	#[test]
+	#[should_panic(expected = "Only the proposer or the counterparty can cancel a swap that hasn't expired")]
+	fn only_the_two_sides_can_cancel_a_swap_that_has_not_expired() {
+		let mut contract = setup();
+		let swap_id = propose(&mut contract, &["b"], None);
+
+		set_context(accounts(3), 1);
+		contract.cancel_swap(swap_id);
+	}
+
+	#[test]
+	fn anyone_can_clean_up_an_expired_swap() {
+		let mut contract = setup();
+		let swap_id = propose(&mut contract, &["b"], Some(U64(1_000)));
+
+		set_context_at(accounts(3), 1, 1_000);
+		contract.cancel_swap(swap_id);
+
+		assert_eq!(transfers(), vec![(accounts(1), 100 + CONST_STORAGE_PER_SWAP)]);
+		assert!(contract.get_swaps_by_account_id(accounts(1), None, None).is_empty());
+	}
+
+	#[test]
+	fn the_largest_swap_can_be_accepted_with_300_tgas() {
+		let mut contract = setup();
+		let swap_id = propose(&mut contract, &["b", "c"], None);
+		assert!(accept_swap_gas(MAX_SWAP_LEN).0 <= 300_000_000_000_000);
+
+		// creating the calls panics if they don't fit
+		accept(&mut contract, swap_id, vec![0, 0], Gas(300_000_000_000_000));
+
+		assert_eq!(nft_transfer_count(), MAX_SWAP_LEN);
+		assert!(contract.get_swap(swap_id).is_none());
+	}
+
+	#[test]
+	#[should_panic(expected = "Accepting this swap needs at least")]
+	fn accepting_a_swap_without_enough_gas_is_rejected() {
+		let mut contract = setup();
+		let swap_id = propose(&mut contract, &["b"], None);
+
+		accept(&mut contract, swap_id, vec![0], Gas(accept_swap_gas(2).0 - 1));
+	}
+
+	#[test]
+	fn once_every_token_arrived_they_change_hands_with_the_amount() {
+		let mut contract = setup();
+		let swap_id = propose(&mut contract, &["b", "c"], None);
+		let swap = contract.get_swap(swap_id).unwrap();
+
+		set_callback_context_with_gas(
+			resolve_swap_gas(MAX_SWAP_LEN),
+			(0..MAX_SWAP_LEN).map(|_| PromiseResult::Successful(vec![])).collect(),
+		);
+		assert!(contract.resolve_swap(swap, vec![swap_item("b"), swap_item("c")]));
+
+		assert_eq!(nft_transfer_count(), MAX_SWAP_LEN);
+		assert_eq!(transfers(), vec![(accounts(2), 100), (accounts(1), CONST_STORAGE_PER_SWAP)]);
+	}
+
+	#[test]
+	fn a_token_that_did_not_arrive_rolls_the_swap_back() {
+		let mut contract = setup();
+		let swap_id = propose(&mut contract, &["b"], None);
+		let swap = contract.get_swap(swap_id).unwrap();
+
+		set_callback_context(vec![PromiseResult::Successful(vec![]), PromiseResult::Failed]);
+		assert!(!contract.resolve_swap(swap, vec![swap_item("b")]));
+
+		// token "a" goes back to bob, who gets the escrow back too
+		assert_eq!(nft_transfer_count(), 1);
+		assert_eq!(transfers(), vec![(accounts(1), 100 + CONST_STORAGE_PER_SWAP)]);
+	}
+}
@@ -0,0 +1,136 @@
+// helpers shared by the unit tests of the market
+use crate::*;
+use near_sdk::mock::VmAction;
+use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+pub(crate) fn market_id() -> AccountId {
+	"market".parse().unwrap()
+}
+
+pub(crate) fn nft_id() -> AccountId {
+	"nft".parse().unwrap()
+}
+
+// the owner of the market
+pub(crate) fn owner_id() -> AccountId {
+	accounts(0)
+}
+
+// a call signed by `predecessor_id` at the given block timestamp
+pub(crate) fn set_context_at(predecessor_id: AccountId, attached_deposit: Balance, block_timestamp: u64) {
+	testing_env!(VMContextBuilder::new()
+		.current_account_id(market_id())
+		.signer_account_id(predecessor_id.clone())
+		.predecessor_account_id(predecessor_id)
+		.attached_deposit(attached_deposit)
+		.block_timestamp(block_timestamp)
+		.build());
+}
+
+pub(crate) fn set_context(predecessor_id: AccountId, attached_deposit: Balance) {
+	set_context_at(predecessor_id, attached_deposit, 0);
+}
+
+// a private callback of the market with `prepaid_gas`, and the results of the promises it waited for
+pub(crate) fn set_callback_context_with_gas(prepaid_gas: Gas, promise_results: Vec<PromiseResult>) {
+	testing_env!(
+		VMContextBuilder::new()
+			.current_account_id(market_id())
+			.predecessor_account_id(market_id())
+			.prepaid_gas(prepaid_gas)
+			.build(),
+		VMConfig::test(),
+		RuntimeFeesConfig::test(),
+		Default::default(),
+		promise_results,
+	);
+}
+
+pub(crate) fn set_callback_context(promise_results: Vec<PromiseResult>) {
+	set_callback_context_with_gas(Gas(300_000_000_000_000), promise_results);
+}
+
+// the result of a promise that returned `value` as JSON
+pub(crate) fn json_result<T: Serialize>(value: &T) -> PromiseResult {
+	PromiseResult::Successful(near_sdk::serde_json::to_vec(value).unwrap())
+}
+
+// the result of nft_transfer_payout or nft_payout
+pub(crate) fn payout_result(payout: &[(AccountId, u128)]) -> PromiseResult {
+	json_result(&Payout {
+		payout: payout
+			.iter()
+			.map(|(account_id, amount)| (account_id.clone(), U128(*amount)))
+			.collect(),
+	})
+}
+
+// a purchase of a token of the NFT contract in NEAR, without a referrer
+pub(crate) fn purchase(buyer_id: AccountId, seller_id: AccountId, token_id: &str, price: u128) -> StructPurchase {
+	StructPurchase {
+		buyer_id,
+		seller_id,
+		nft_contract_id: nft_id(),
+		token_id: token_id.to_string(),
+		price: U128(price),
+		ft_token_id: None,
+		referrer_id: None,
+		referral_bounty: U128(0),
+	}
+}
+
+
+pub(crate) fn setup() -> Contract {
+	set_context(owner_id(), 0);
+	Contract::new(owner_id())
+}
+
+// the NFT contract calls nft_on_approve for a token of `owner_id` with approval ID 0
+pub(crate) fn approve(contract: &mut Contract, owner_id: AccountId, token_id: &str, msg: &str) {
+	testing_env!(VMContextBuilder::new()
+		.current_account_id(market_id())
+		.signer_account_id(owner_id.clone())
+		.predecessor_account_id(nft_id())
+		.build());
+	contract.nft_on_approve(token_id.to_string(), owner_id, 0, msg.to_string());
+}
+
+// pay for the storage of one more sale and list the token of `owner_id` with the given SaleArgs
+pub(crate) fn list(contract: &mut Contract, owner_id: AccountId, token_id: &str, msg: &str) {
+	set_context(owner_id.clone(), CONST_STORAGE_PER_SALE);
+	contract.storage_deposit(None, None);
+	approve(contract, owner_id, token_id, msg);
+}
+
+pub(crate) fn get_sale(contract: &Contract, token_id: &str) -> Option<StructSale> {
+	contract.sales.get(&StructSaleKey::new(&nft_id(), &token_id.to_string()))
+}
+
+// the NEAR transfers the last call made, by receiver
+pub(crate) fn transfers() -> Vec<(AccountId, Balance)> {
+	get_created_receipts()
+		.into_iter()
+		.flat_map(|receipt| {
+			let receiver_id = receipt.receiver_id;
+			receipt.actions.into_iter().filter_map(move |action| match action {
+				VmAction::Transfer { deposit } => Some((receiver_id.clone(), deposit)),
+				_ => None,
+			})
+		})
+		.collect()
+}
+
+// the names of the methods the last call called, by receiver
+pub(crate) fn function_calls() -> Vec<(AccountId, String)> {
+	get_created_receipts()
+		.into_iter()
+		.flat_map(|receipt| {
+			let receiver_id = receipt.receiver_id;
+			receipt.actions.into_iter().filter_map(move |action| match action {
+				VmAction::FunctionCall { function_name, .. } => Some((receiver_id.clone(), function_name)),
+				_ => None,
+			})
+		})
+		.collect()
+}
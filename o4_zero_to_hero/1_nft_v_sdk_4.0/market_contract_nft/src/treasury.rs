@@ -0,0 +1,212 @@
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+	// Set the cut the market takes from every sale, in basis points. Only the owner of the market can call this.
+	pub fn set_protocol_fee(&mut self, fee_basis_points: u16) {
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the owner can set the protocol fee"
+		);
+		assert!(
+			fee_basis_points <= MAX_PROTOCOL_FEE_BASIS_POINTS,
+			"Protocol fee can't be more than {} basis points",
+			MAX_PROTOCOL_FEE_BASIS_POINTS
+		);
+
+		self.protocol_fee_basis_points = fee_basis_points;
+	}
+
+	pub fn get_protocol_fee(&self) -> u16 {
+		self.protocol_fee_basis_points
+	}
+
+	// Returns the protocol fees collected in NEAR, or in the given fungible token, that haven't been withdrawn yet
+	pub fn get_treasury_balance(&self, ft_token_id: Option<AccountId>) -> U128 {
+		if let Some(ft_token_id) = ft_token_id {
+			U128(self.ft_treasury_balances.get(&ft_token_id).unwrap_or(0))
+		} else {
+			U128(self.treasury_balance)
+		}
+	}
+
+	/*
+		Withdraw collected protocol fees to the owner, in NEAR or in the given fungible token. The owner needs to be
+		registered with the FT contract, if the ft_transfer fails the amount goes back into the treasury.
+	*/
+	#[payable]
+	pub fn withdraw_treasury(&mut self, amount: U128, ft_token_id: Option<AccountId>) {
+		assert_one_yocto();
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the owner can withdraw from the treasury"
+		);
+
+		let balance = self.get_treasury_balance(ft_token_id.clone()).0;
+		assert!(
+			amount.0 <= balance,
+			"Amount exceeds the treasury balance of {}",
+			balance
+		);
+
+		if let Some(ft_token_id) = ft_token_id {
+			self.ft_treasury_balances
+				.insert(&ft_token_id, &(balance - amount.0));
+
+			ext_ft::ft_transfer(
+				self.owner_id.clone(),
+				amount,
+				Some("treasury withdrawal from market".to_string()),
+				ft_token_id.clone(),
+				1,
+				CONST_GAS_FOR_FT_TRANSFER,
+			)
+			.then(ext_self::resolve_ft_treasury_withdrawal(
+				ft_token_id,
+				amount,
+				env::current_account_id(),
+				CONST_NO_DEPOSIT,
+				CONST_GAS_FOR_FT_TRANSFER,
+			));
+		} else {
+			self.treasury_balance -= amount.0;
+			Promise::new(self.owner_id.clone()).transfer(amount.0);
+		}
+	}
+
+	// private callback putting the amount back into the treasury if the ft_transfer to the owner failed
+	#[private]
+	pub fn resolve_ft_treasury_withdrawal(&mut self, ft_token_id: AccountId, amount: U128) {
+		if !is_promise_success() {
+			self.internal_collect_fee(Some(&ft_token_id), amount.0);
+		}
+	}
+}
+
+impl Contract {
+	// add a protocol fee to the treasury balance of the currency it was paid in
+	pub(crate) fn internal_collect_fee(&mut self, ft_token_id: Option<&AccountId>, fee: Balance) {
+		if fee == 0 {
+			return;
+		}
+
+		if let Some(ft_token_id) = ft_token_id {
+			let balance = self.ft_treasury_balances.get(ft_token_id).unwrap_or(0);
+			self.ft_treasury_balances.insert(ft_token_id, &(balance + fee));
+		} else {
+			self.treasury_balance += fee;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::accounts;
+
+	fn usdc_id() -> AccountId {
+		"usdc".parse().unwrap()
+	}
+
+	// a market taking 5% of every sale
+	fn setup_with_fee() -> Contract {
+		let mut contract = setup();
+		set_context(owner_id(), 0);
+		contract.set_protocol_fee(500);
+
+		contract
+	}
+
+	#[test]
+	#[should_panic(expected = "Only the owner can set the protocol fee")]
+	fn only_the_owner_can_set_the_protocol_fee() {
+		let mut contract = setup();
+
+		set_context(accounts(1), 0);
+		contract.set_protocol_fee(500);
+	}
+
+	#[test]
+	#[should_panic(expected = "Protocol fee can't be more than 1000 basis points")]
+	fn the_protocol_fee_is_capped() {
+		let mut contract = setup();
+
+		set_context(owner_id(), 0);
+		contract.set_protocol_fee(MAX_PROTOCOL_FEE_BASIS_POINTS + 1);
+	}
+
+	#[test]
+	fn the_fee_is_collected_once_the_payout_went_through() {
+		let mut contract = setup_with_fee();
+		let fee = contract.internal_protocol_fee(U128(100));
+		assert_eq!(fee, U128(5));
+
+		// the NFT contract splits the price minus the fee
+		set_callback_context(vec![payout_result(&[(accounts(1), 95)])]);
+		contract.resolve_purchase(purchase(accounts(2), accounts(1), "1", 100), fee);
+
+		assert_eq!(contract.get_treasury_balance(None), U128(5));
+		assert_eq!(transfers(), vec![(accounts(1), 95)]);
+	}
+
+	#[test]
+	fn a_refunded_purchase_leaves_no_fee() {
+		let mut contract = setup_with_fee();
+
+		set_callback_context(vec![PromiseResult::Failed]);
+		contract.resolve_purchase(purchase(accounts(2), accounts(1), "1", 100), U128(5));
+
+		assert_eq!(contract.get_treasury_balance(None), U128(0));
+		assert_eq!(transfers(), vec![(accounts(2), 100)]);
+	}
+
+	#[test]
+	fn the_owner_withdraws_near_from_the_treasury() {
+		let mut contract = setup_with_fee();
+		contract.internal_collect_fee(None, 5);
+
+		set_context(owner_id(), 1);
+		contract.withdraw_treasury(U128(3), None);
+
+		assert_eq!(transfers(), vec![(owner_id(), 3)]);
+		assert_eq!(contract.get_treasury_balance(None), U128(2));
+	}
+
+	#[test]
+	#[should_panic(expected = "Amount exceeds the treasury balance of 5")]
+	fn the_owner_can_not_withdraw_more_than_the_treasury_holds() {
+		let mut contract = setup_with_fee();
+		contract.internal_collect_fee(None, 5);
+
+		set_context(owner_id(), 1);
+		contract.withdraw_treasury(U128(6), None);
+	}
+
+	#[test]
+	#[should_panic(expected = "Only the owner can withdraw from the treasury")]
+	fn only_the_owner_can_withdraw_from_the_treasury() {
+		let mut contract = setup_with_fee();
+		contract.internal_collect_fee(None, 5);
+
+		set_context(accounts(1), 1);
+		contract.withdraw_treasury(U128(5), None);
+	}
+
+	#[test]
+	fn a_failed_ft_withdrawal_goes_back_into_the_treasury() {
+		let mut contract = setup_with_fee();
+		contract.internal_collect_fee(Some(&usdc_id()), 10);
+
+		set_context(owner_id(), 1);
+		contract.withdraw_treasury(U128(10), Some(usdc_id()));
+		assert_eq!(contract.get_treasury_balance(Some(usdc_id())), U128(0));
+		assert!(function_calls().contains(&(usdc_id(), "ft_transfer".to_string())));
+
+		set_callback_context(vec![PromiseResult::Failed]);
+		contract.resolve_ft_treasury_withdrawal(usdc_id(), U128(10));
+		assert_eq!(contract.get_treasury_balance(Some(usdc_id())), U128(10));
+	}
+}
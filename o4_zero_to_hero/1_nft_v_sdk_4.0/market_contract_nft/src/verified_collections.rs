@@ -0,0 +1,246 @@
+use crate::*;
+
+// how a verified NFT contract can be listed on the market
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StructCollectionPolicy {
+	// whether the collection can be listed in NEAR
+	pub near_allowed: bool,
+	// the accepted fungible tokens the collection can be listed in
+	pub ft_token_ids: Vec<AccountId>,
+	// the lowest price (or reserve price) a sale in NEAR can be listed at
+	pub min_price: U128,
+	// taken out of the seller's storage balance into the treasury for every listing
+	pub listing_fee: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+	/*
+		Add an NFT contract to the registry of verified collections, or replace its policy. Only the owner of the market
+		can call this.
+	*/
+	pub fn verify_collection(&mut self, nft_contract_id: AccountId, policy: StructCollectionPolicy) {
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the owner can verify collections"
+		);
+
+		self.verified_collections.insert(&nft_contract_id, &policy);
+	}
+
+	// Take an NFT contract out of the registry. Its open sales stay listed. Only the owner of the market can call this.
+	pub fn unverify_collection(&mut self, nft_contract_id: AccountId) {
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the owner can unverify collections"
+		);
+
+		self.verified_collections.remove(&nft_contract_id);
+	}
+
+	// Set whether NFT contracts that aren't in the registry can list. Only the owner of the market can call this.
+	pub fn set_allow_unverified_listings(&mut self, allow_unverified_listings: bool) {
+		assert_eq!(
+			env::predecessor_account_id(),
+			self.owner_id,
+			"Only the owner can set whether unverified collections can list"
+		);
+
+		self.allow_unverified_listings = allow_unverified_listings;
+	}
+
+	pub fn get_allow_unverified_listings(&self) -> bool {
+		self.allow_unverified_listings
+	}
+
+	pub fn is_verified_collection(&self, nft_contract_id: AccountId) -> bool {
+		self.verified_collections.get(&nft_contract_id).is_some()
+	}
+
+	pub fn get_collection_policy(&self, nft_contract_id: AccountId) -> Option<StructCollectionPolicy> {
+		self.verified_collections.get(&nft_contract_id)
+	}
+
+	// Returns the paginated verified collections with their policies
+	pub fn get_verified_collections(
+		&self,
+		from_index: Option<U128>,
+		limit: Option<u64>,
+	) -> Vec<(AccountId, StructCollectionPolicy)> {
+		// Where to start pagination - if we have a from_index, we'll use that - otherwise start from 0 index
+		let start = u128::from(from_index.unwrap_or(U128(0)));
+
+		self.verified_collections
+			.iter()
+			.skip(start as usize)
+			.take(limit.unwrap_or(CONST_DEFAULT_PAGE_LIMIT) as usize)
+			.collect()
+	}
+}
+
+impl Contract {
+	// panics if the NFT contract isn't verified and unverified contracts can't list. Returns its policy if it is verified
+	pub(crate) fn internal_assert_collection_can_list(
+		&self,
+		nft_contract_id: &AccountId,
+	) -> Option<StructCollectionPolicy> {
+		let policy = self.verified_collections.get(nft_contract_id);
+
+		assert!(
+			policy.is_some() || self.allow_unverified_listings,
+			"Collection {} is not verified",
+			nft_contract_id
+		);
+
+		policy
+	}
+
+	/*
+		check a new listing against the policy of its collection. Returns the listing fee the seller owes, which is 0
+		for unverified collections.
+	*/
+	pub(crate) fn internal_assert_listing_policy(
+		&self,
+		nft_contract_id: &AccountId,
+		sale_conditions: TypeSalePriceInYoctoNear,
+		ft_token_id: Option<&AccountId>,
+	) -> Balance {
+		let policy = if let Some(policy) = self.internal_assert_collection_can_list(nft_contract_id) {
+			policy
+		} else {
+			return 0;
+		};
+
+		if let Some(ft_token_id) = ft_token_id {
+			assert!(
+				policy.ft_token_ids.contains(ft_token_id),
+				"Collection {} can't be listed in {}",
+				nft_contract_id,
+				ft_token_id
+			);
+		} else {
+			assert!(
+				policy.near_allowed,
+				"Collection {} can't be listed in NEAR",
+				nft_contract_id
+			);
+			assert!(
+				sale_conditions.0 >= policy.min_price.0,
+				"Collection {} can't be listed below {}",
+				nft_contract_id,
+				policy.min_price.0
+			);
+		}
+
+		policy.listing_fee.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::*;
+	use near_sdk::test_utils::accounts;
+
+	const LISTING_FEE: Balance = 50;
+
+	// nft is verified, can only be listed in NEAR from 100 yoctoNEAR and charges a listing fee
+	fn setup_verified() -> Contract {
+		let mut contract = setup();
+		set_context(owner_id(), 0);
+		contract.verify_collection(
+			nft_id(),
+			StructCollectionPolicy {
+				near_allowed: true,
+				ft_token_ids: vec![],
+				min_price: U128(100),
+				listing_fee: U128(LISTING_FEE),
+			},
+		);
+
+		contract
+	}
+
+	// bob pays for the storage of the sale and the listing fee, then lists token "1"
+	fn list_with_fee(contract: &mut Contract, msg: &str) {
+		set_context(accounts(1), CONST_STORAGE_PER_SALE + LISTING_FEE);
+		contract.storage_deposit(None, None);
+		approve(contract, accounts(1), "1", msg);
+	}
+
+	#[test]
+	#[should_panic(expected = "Only the owner can verify collections")]
+	fn only_the_owner_can_verify_a_collection() {
+		let mut contract = setup();
+
+		set_context(accounts(1), 0);
+		contract.verify_collection(
+			nft_id(),
+			StructCollectionPolicy {
+				near_allowed: true,
+				ft_token_ids: vec![],
+				min_price: U128(0),
+				listing_fee: U128(0),
+			},
+		);
+	}
+
+	#[test]
+	fn the_listing_fee_is_taken_from_the_storage_balance_into_the_treasury() {
+		let mut contract = setup_verified();
+
+		list_with_fee(&mut contract, r#"{"sale_conditions":"100"}"#);
+
+		assert_eq!(contract.get_treasury_balance(None), U128(LISTING_FEE));
+		assert_eq!(contract.storage_balance_of(accounts(1)).unwrap().total, U128(CONST_STORAGE_PER_SALE));
+		assert!(contract.get_sale(nft_id(), "1".to_string()).unwrap().verified);
+	}
+
+	#[test]
+	#[should_panic(expected = "for the storage of the sale plus a listing fee of 50")]
+	fn the_listing_fee_must_be_covered_by_the_storage_balance() {
+		let mut contract = setup_verified();
+
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+	}
+
+	#[test]
+	#[should_panic(expected = "Collection nft can't be listed below 100")]
+	fn a_verified_collection_can_not_be_listed_below_its_minimum_price() {
+		let mut contract = setup_verified();
+
+		list_with_fee(&mut contract, r#"{"sale_conditions":"99"}"#);
+	}
+
+	#[test]
+	#[should_panic(expected = "Collection nft can't be listed in usdc")]
+	fn a_verified_collection_can_only_be_listed_in_its_currencies() {
+		let mut contract = setup_verified();
+		set_context(owner_id(), 0);
+		contract.add_ft_token_ids(vec!["usdc".parse().unwrap()]);
+
+		list_with_fee(&mut contract, r#"{"sale_conditions":"100","ft_token_id":"usdc"}"#);
+	}
+
+	#[test]
+	fn unverified_collections_list_without_a_policy() {
+		let mut contract = setup();
+
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"1"}"#);
+
+		assert!(!contract.get_sale(nft_id(), "1".to_string()).unwrap().verified);
+	}
+
+	#[test]
+	#[should_panic(expected = "Collection nft is not verified")]
+	fn unverified_collections_can_not_list_once_that_is_turned_off() {
+		let mut contract = setup();
+		set_context(owner_id(), 0);
+		contract.set_allow_unverified_listings(false);
+
+		list(&mut contract, accounts(1), "1", r#"{"sale_conditions":"100"}"#);
+	}
+}